@@ -0,0 +1,56 @@
+//! Compares assembling a file with many includes from segments, as the loader
+//! does, with splicing every include into the growing text, last one first, as it
+//! did before. The time per include should stay about the same as the number of
+//! includes grows.
+//!
+//! Run with `cargo bench --bench assembly`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use recursive_file_loader::Loader;
+use temp_dir::TempDir;
+
+const DIRECTIVE: &str = "${include_indent(\"part.txt\")}";
+const LINE: &str = "some text before ${include_indent(\"part.txt\")} and after\n";
+
+fn assembly(c: &mut Criterion) {
+    let dir = TempDir::new().unwrap();
+    let part = "a line of included text\n".repeat(10);
+    std::fs::write(dir.child("part.txt"), &part).unwrap();
+
+    let mut group = c.benchmark_group("assembly");
+    group.sample_size(10);
+
+    for includes in [100, 1_000, 10_000] {
+        let text = LINE.repeat(includes);
+        std::fs::write(dir.child("start.txt"), &text).unwrap();
+        let loader = Loader::new();
+        assert_eq!(splice(&text, &part), loader.load_file_recursively(dir.child("start.txt")).unwrap());
+
+        group.throughput(Throughput::Elements(includes as u64));
+        group.bench_with_input(BenchmarkId::new("segments", includes), &includes, |b, _| {
+            b.iter(|| loader.load_file_recursively(dir.child("start.txt")).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("splicing", includes), &text, |b, text| {
+            b.iter(|| splice(text, &part))
+        });
+    }
+
+    group.finish();
+}
+
+/// Replace every include in `text` with the indented `part` in place, without
+/// reading any files.
+fn splice(text: &str, part: &str) -> String {
+    let indentation = LINE.find(DIRECTIVE).unwrap();
+    let indented = part.trim_end_matches('\n').replace('\n', &format!("\n{}", " ".repeat(indentation)));
+    let ranges: Vec<_> = text.match_indices(DIRECTIVE).map(|(start, it)| start..start + it.len()).collect();
+
+    let mut text = text.to_owned();
+    for range in ranges.into_iter().rev() {
+        text.replace_range(range, &indented);
+    }
+    text
+}
+
+criterion_group!(benches, assembly);
+criterion_main!(benches);
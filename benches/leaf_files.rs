@@ -0,0 +1,40 @@
+//! Compares loading a tree dominated by leaf files without directives, which skip
+//! the directive regex, with the same tree whose leaf files contain `${` and still
+//! have to be scanned like every file was before.
+//!
+//! Run with `cargo bench --bench leaf_files`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use recursive_file_loader::Loader;
+use temp_dir::TempDir;
+
+const LEAVES: usize = 1_000;
+
+fn leaf_files(c: &mut Criterion) {
+    let mut group = c.benchmark_group("leaf_files");
+    group.sample_size(20);
+    group.throughput(Throughput::Elements(LEAVES as u64));
+
+    for (name, line) in [
+        ("skipped", "a line of static text without any directives, $5 or {braces}\n"),
+        ("scanned", "a line of static text without any directives, ${5} or {braces}\n"),
+    ] {
+        let dir = TempDir::new().unwrap();
+        let mut start = String::new();
+        for index in 0..LEAVES {
+            std::fs::write(dir.child(format!("leaf{}.txt", index)), line.repeat(200)).unwrap();
+            start.push_str(&format!("${{include(\"leaf{}.txt\")}}\n", index));
+        }
+        std::fs::write(dir.child("start.txt"), start).unwrap();
+
+        let loader = Loader::new();
+        group.bench_function(BenchmarkId::from_parameter(name), |b| {
+            b.iter(|| loader.load_file_recursively(dir.child("start.txt")).unwrap())
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, leaf_files);
+criterion_main!(benches);
@@ -0,0 +1,45 @@
+//! Compares loading a tree that references a few files many times, where resolving
+//! the path of every include dominates, with and without reusing the canonical
+//! paths resolved earlier in the load.
+//!
+//! Run with `cargo bench --bench repeated_includes`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use recursive_file_loader::{Loader, LoaderBuilder};
+use temp_dir::TempDir;
+
+const FILES: usize = 5;
+const REFERENCES: usize = 2_000;
+
+fn repeated_includes(c: &mut Criterion) {
+    let dir = TempDir::new().unwrap();
+    std::fs::create_dir_all(dir.child("nested/parts")).unwrap();
+    for index in 0..FILES {
+        std::fs::write(dir.child(format!("nested/parts/part{}.txt", index)), format!("part {}\n", index)).unwrap();
+    }
+    let mut start = String::new();
+    for index in 0..REFERENCES {
+        start.push_str(&format!("${{include(\"nested/parts/part{}.txt\")}}\n", index % FILES));
+    }
+    std::fs::write(dir.child("start.txt"), start).unwrap();
+
+    let mut group = c.benchmark_group("repeated_includes");
+    group.sample_size(20);
+    group.throughput(Throughput::Elements(REFERENCES as u64));
+
+    // A custom canonicalizer is called for every include, like the file system was
+    // before resolved paths were reused.
+    for (name, loader) in [
+        ("reused", Loader::new()),
+        ("resolved_every_time", LoaderBuilder::new().canonicalize(|path| std::fs::canonicalize(path)).build()),
+    ] {
+        group.bench_function(BenchmarkId::from_parameter(name), |b| {
+            b.iter(|| loader.load_file_recursively(dir.child("start.txt")).unwrap())
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, repeated_includes);
+criterion_main!(benches);
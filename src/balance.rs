@@ -0,0 +1,123 @@
+/// The delimiters checked by [crate::LoaderBuilder::validate_balanced].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delimiters {
+    /// Every `{`, `[` and `(` is closed by the matching `}`, `]` or `)`, in order.
+    Brackets,
+
+    /// Like [Delimiters::Brackets], ignoring brackets inside double-quoted strings
+    /// with backslash escapes, as in JSON.
+    Json,
+
+    /// Every start tag like `<a href="x">` is closed by the matching end tag `</a>`,
+    /// in order, as in XML. Self-closing tags like `<br/>`, comments, processing
+    /// instructions, CDATA sections and declarations like `<!DOCTYPE html>` need no
+    /// end tag. HTML elements without end tags, like `<br>`, must be self-closing.
+    Tags,
+}
+
+/// Check that the delimiters in `text` are balanced. Fails with the byte offset
+/// of the first delimiter that isn't and a description of the problem.
+pub(crate) fn check(text: &str, delimiters: Delimiters) -> Result<(), (usize, String)> {
+    match delimiters {
+        Delimiters::Brackets => check_brackets(text, false),
+        Delimiters::Json => check_brackets(text, true),
+        Delimiters::Tags => check_tags(text),
+    }
+}
+
+fn check_brackets(text: &str, skip_strings: bool) -> Result<(), (usize, String)> {
+    let mut open: Vec<(usize, char)> = vec![];
+    let mut chars = text.char_indices();
+    while let Some((offset, char)) = chars.next() {
+        match char {
+            '"' if skip_strings => loop {
+                match chars.next() {
+                    Some((_, '\\')) => {
+                        chars.next();
+                    },
+                    Some((_, '"')) => break,
+                    Some(_) => {},
+                    None => return Err((offset, "unterminated string".to_owned())),
+                }
+            },
+            '{' | '[' | '(' => open.push((offset, char)),
+            '}' | ']' | ')' => match open.pop() {
+                Some((_, opening)) if closing(opening) == char => {},
+                Some((_, opening)) => {
+                    return Err((offset, format!("expected `{}` but found `{}`", closing(opening), char)));
+                },
+                None => return Err((offset, format!("unexpected `{}`", char))),
+            },
+            _ => {},
+        }
+    }
+
+    match open.pop() {
+        Some((offset, opening)) => Err((offset, format!("unclosed `{}`", opening))),
+        None => Ok(()),
+    }
+}
+
+fn closing(opening: char) -> char {
+    match opening {
+        '{' => '}',
+        '[' => ']',
+        _ => ')',
+    }
+}
+
+fn check_tags(text: &str) -> Result<(), (usize, String)> {
+    let tag_regex = lazy_regex::regex!(r#"<(?:!--.*?-->|!\[CDATA\[.*?\]\]>|[!?][^>]*>|(?P<end>/)?(?P<name>[A-Za-z_][\w:.-]*)(?:"[^"]*"|'[^']*'|[^'">])*?(?P<empty>/)?>)"#s);
+
+    let mut open: Vec<(usize, &str)> = vec![];
+    for capture in tag_regex.captures_iter(text) {
+        let Some(name) = capture.name("name") else {
+            continue;
+        };
+        let offset = capture.get(0).unwrap().start();
+        match (capture.name("end"), capture.name("empty")) {
+            (None, None) => open.push((offset, name.as_str())),
+            (None, Some(_)) => {},
+            (Some(_), _) => match open.pop() {
+                Some((_, opening)) if opening == name.as_str() => {},
+                Some((_, opening)) => {
+                    return Err((offset, format!("expected `</{}>` but found `</{}>`", opening, name.as_str())));
+                },
+                None => return Err((offset, format!("unexpected `</{}>`", name.as_str()))),
+            },
+        }
+    }
+
+    match open.pop() {
+        Some((offset, name)) => Err((offset, format!("unclosed `<{}>`", name))),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test_check {
+    use super::{check, Delimiters};
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(Delimiters::Brackets, "fn f(a: [u8; 2]) { g() }", Ok(()))]
+    #[case(Delimiters::Brackets, "{ (] }", Err((3, "expected `)` but found `]`")))]
+    #[case(Delimiters::Brackets, "a) (", Err((1, "unexpected `)`")))]
+    #[case(Delimiters::Brackets, "{ [ ] ( ", Err((6, "unclosed `(`")))]
+    #[case(Delimiters::Brackets, r#"{"a": "}"}"#, Err((9, "unexpected `}`")))]
+    #[case(Delimiters::Json, r#"{"a": "}\"{", "b": [1, 2]}"#, Ok(()))]
+    #[case(Delimiters::Json, r#"{"a": ["b"}"#, Err((10, "expected `]` but found `}`")))]
+    #[case(Delimiters::Json, r#"{"a": "b}"#, Err((6, "unterminated string")))]
+    #[case(Delimiters::Tags, "<?xml version=\"1.0\"?><!DOCTYPE a><a x=\"</b>\"><!-- <c> --><b/><![CDATA[<d>]]></a>", Ok(()))]
+    #[case(Delimiters::Tags, "<a>\n<b></a>", Err((7, "expected `</b>` but found `</a>`")))]
+    #[case(Delimiters::Tags, "<a></a></b>", Err((7, "unexpected `</b>`")))]
+    #[case(Delimiters::Tags, "<a><br>x</a", Err((3, "unclosed `<br>`")))]
+    #[case(Delimiters::Tags, "1 < 2 && 3 > 2", Ok(()))]
+    fn should_check_balanced_delimiters(
+        #[case] delimiters: Delimiters,
+        #[case] text: &str,
+        #[case] expectation: Result<(), (usize, &str)>,
+    ) {
+        assert_eq!(check(text, delimiters), expectation.map_err(|(offset, message)| (offset, message.to_owned())));
+    }
+}
@@ -0,0 +1,696 @@
+use std::{collections::HashMap, io, path::{Path, PathBuf}, sync::Arc, time::Duration};
+
+use crate::{
+    canonical_path::clean,
+    directives,
+    loader::Loader,
+    options::{Callback, CommentStyle, CycleKey, EscapedOutput, IndentMode, LoadOptions, Options, RelativeBase},
+    Delimiters, Error, FileSource,
+};
+#[cfg(feature = "cache")]
+use crate::DiskCache;
+
+/// Builder for a [Loader] with non-default settings.
+///
+/// ```
+/// use recursive_file_loader::{EscapedOutput, LoaderBuilder};
+///
+/// let loader = LoaderBuilder::new()
+///     .escaped_output(EscapedOutput::Literal)
+///     .build();
+/// ```
+#[derive(Debug, Default)]
+pub struct LoaderBuilder {
+    options: Options,
+}
+
+impl LoaderBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set how backslashes in front of directives are written to the output.
+    /// Defaults to [EscapedOutput::Collapsed].
+    pub fn escaped_output(mut self, escaped_output: EscapedOutput) -> Self {
+        self.options.escaped_output = escaped_output;
+        self
+    }
+
+    /// Refuse to read files larger than the given number of bytes, returning an
+    /// [crate::Error::FileTooLarge] instead. Files whose size isn't known up front,
+    /// like those of a [crate::FileSource] or fetched over HTTP, are read up to a byte
+    /// past the limit at most. Unlimited by default.
+    pub fn max_file_bytes(mut self, max_file_bytes: u64) -> Self {
+        self.options.max_file_bytes = Some(max_file_bytes);
+        self
+    }
+
+    /// Refuse to load files containing more than the given number of directives,
+    /// not counting escaped ones, returning an [crate::Error::TooManyIncludes]
+    /// instead. Unlimited by default.
+    pub fn max_includes_per_file(mut self, max_includes: usize) -> Self {
+        self.options.max_includes_per_file = Some(max_includes);
+        self
+    }
+
+    /// Refuse to replace more than the given number of directives in a single load,
+    /// returning an [crate::Error::ExpansionBudgetExceeded] instead. Every replaced
+    /// directive counts, including repeated includes of the same file and every
+    /// repetition of an `include_repeat`. Escaped directives and includes producing
+    /// nothing, like an `include_if` whose variable isn't set, don't. Bounds the work
+    /// done for untrusted files regardless of how their includes are nested.
+    /// Unlimited by default.
+    pub fn max_expansions(mut self, max_expansions: usize) -> Self {
+        self.options.max_expansions = Some(max_expansions);
+        self
+    }
+
+    /// Refuse to follow includes nested deeper than the given number of levels,
+    /// returning an [crate::Error::MaxDepthExceeded] instead. The loaded file is at
+    /// depth 0, the files it includes at depth 1 and so on. Unlimited by default,
+    /// except for [crate::LITERAL_MAX_DEPTH] with [CycleKey::Literal].
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.options.max_depth = Some(max_depth);
+        self
+    }
+
+    /// Abort a load taking longer than the given duration, returning an
+    /// [crate::Error::Timeout] instead. The elapsed time is checked whenever a file is
+    /// about to be loaded, so a single slow read or command isn't interrupted.
+    /// Unlimited by default.
+    pub fn max_duration(mut self, max_duration: Duration) -> Self {
+        self.options.max_duration = Some(max_duration);
+        self
+    }
+
+    /// Refuse to load files outside of the given directory, returning an
+    /// [crate::Error::OutsideRoot] instead. Files are compared by their
+    /// symlink-resolved path regardless of [LoaderBuilder::cycle_detection], so a
+    /// symlink in the directory pointing outside of it is refused as well.
+    ///
+    /// Overridden files and files read from a [LoaderBuilder::file_source] are
+    /// compared by the path they're referenced by instead, with `.` and `..`
+    /// resolved lexically. Remote files, see `allow_http`, aren't restricted.
+    /// Unrestricted by default.
+    pub fn restrict_to<P: AsRef<Path>>(mut self, root: P) -> Self {
+        self.options.restrict_to = Some(root.as_ref().to_owned());
+        self
+    }
+
+    /// Set the base directory relative include paths are resolved against.
+    /// Defaults to [RelativeBase::SourceFile].
+    pub fn relative_to(mut self, relative_to: RelativeBase) -> Self {
+        self.options.relative_to = relative_to;
+        self
+    }
+
+    /// Resolve the relative includes of the loaded file against the given directory
+    /// instead of the file's own, as if the file lived there, e.g. for a generated
+    /// file in a temporary directory that refers to a source tree. Files included
+    /// by it still resolve against their own directories, and paths starting with
+    /// `^/` against the loaded file's actual directory.
+    pub fn base_dir_override<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.options.base_dir_override = Some(dir.as_ref().to_owned());
+        self
+    }
+
+    /// Set the key identifying files for cycle detection. Defaults to
+    /// [CycleKey::Canonical].
+    pub fn cycle_detection(mut self, cycle_detection: CycleKey) -> Self {
+        self.options.cycle_detection = cycle_detection;
+        self
+    }
+
+    /// Use a custom function in place of [std::fs::canonicalize] to identify files,
+    /// e.g. a cheaper lexical normalization on slow network filesystems. The result
+    /// is used for cycle detection and to read the file, and an error of kind
+    /// [io::ErrorKind::NotFound] is reported as [Error::FileNotFound]. Only applies
+    /// to [CycleKey::Canonical].
+    ///
+    /// ```
+    /// use recursive_file_loader::LoaderBuilder;
+    ///
+    /// let loader = LoaderBuilder::new()
+    ///     .canonicalize(|path| std::path::absolute(path))
+    ///     .build();
+    /// ```
+    pub fn canonicalize<F>(mut self, canonicalize: F) -> Self
+    where
+        F: Fn(&Path) -> io::Result<PathBuf> + Send + Sync + 'static,
+    {
+        self.options.canonicalizer = Some(Callback(Arc::new(canonicalize)));
+        self
+    }
+
+    /// Rewrite the paths of directives before resolving them. The callback receives
+    /// the path as written in the directive; returning `Some` replaces it and `None`
+    /// keeps it. The resulting path is resolved like any other, so relative paths
+    /// are still relative to the including file. Fallback paths are rewritten too.
+    ///
+    /// ```
+    /// use recursive_file_loader::LoaderBuilder;
+    ///
+    /// let loader = LoaderBuilder::new()
+    ///     .rewrite_path(|path| path.strip_prefix("old/").map(|rest| format!("new/{}", rest)))
+    ///     .build();
+    /// ```
+    pub fn rewrite_path<F>(mut self, rewrite_path: F) -> Self
+    where
+        F: Fn(&str) -> Option<String> + Send + Sync + 'static,
+    {
+        self.options.rewrite_path = Some(Callback(Arc::new(rewrite_path)));
+        self
+    }
+
+    /// Treat paths differing only in case as the same file for cycle detection,
+    /// and fail with an [Error::CaseMismatch] when an include's spelling doesn't
+    /// match the file on disk exactly. This helps catch templates that only work on
+    /// case-insensitive filesystems. Disabled by default.
+    pub fn case_insensitive(mut self, case_insensitive: bool) -> Self {
+        self.options.case_insensitive = case_insensitive;
+        self
+    }
+
+    /// When a referenced path doesn't exist, look for a file whose path only differs
+    /// in case and load that instead, e.g. for templates written on case-insensitive
+    /// filesystems. Fails with an [Error::AmbiguousCase] if several files match.
+    /// Only applies to files on the local filesystem. Disabled by default.
+    pub fn resolve_case_insensitive(mut self, resolve_case_insensitive: bool) -> Self {
+        self.options.resolve_case_insensitive = resolve_case_insensitive;
+        self
+    }
+
+    /// When a referenced path doesn't exist, try appending each of the given
+    /// extensions in order and use the first path that exists. The path as
+    /// written is always tried first. If nothing is found, an
+    /// [Error::FilesNotFound] lists all attempted paths.
+    ///
+    /// ```
+    /// use recursive_file_loader::LoaderBuilder;
+    ///
+    /// // `${include("header")}` will load `header.txt` or `header.md`
+    /// let loader = LoaderBuilder::new()
+    ///     .try_extensions(["txt", "md"])
+    ///     .build();
+    /// ```
+    pub fn try_extensions<I, S>(mut self, extensions: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.options.try_extensions = extensions
+            .into_iter()
+            .map(|it| it.as_ref().trim_start_matches('.').to_owned())
+            .collect();
+        self
+    }
+
+    /// Resolve relative includes against a stack of directories, e.g. a theme
+    /// overlaying a base template directory. An include inside one of the roots
+    /// loads the file at the same location relative to the first root that contains
+    /// it, so files in earlier roots shadow those in later ones. Includes outside of
+    /// the roots are resolved as usual. Cycles are still detected on the files
+    /// actually loaded.
+    ///
+    /// ```
+    /// use recursive_file_loader::LoaderBuilder;
+    ///
+    /// // `${include("header.txt")}` in `base/page.txt` loads `theme/header.txt`,
+    /// // if it exists
+    /// let loader = LoaderBuilder::new()
+    ///     .layered_roots(["theme", "base"])
+    ///     .build();
+    /// ```
+    pub fn layered_roots<I, P>(mut self, roots: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        self.options.layered_roots = roots.into_iter().map(|it| it.as_ref().to_owned()).collect();
+        self
+    }
+
+    /// Resolve the includes of each file on the rayon thread pool. The output,
+    /// including which error is reported, is the same as for sequential loading,
+    /// only the order in which files are read differs. Only affects
+    /// [Loader::load_file_recursively]. Disabled by default.
+    #[cfg(feature = "parallel")]
+    pub fn parallel(mut self, parallel: bool) -> Self {
+        self.options.parallel = parallel;
+        self
+    }
+
+    /// Use the given content instead of reading the file for each of the paths,
+    /// whether the file exists or not. Paths are matched after resolving them
+    /// against the current directory at the time this is called, and against the
+    /// canonical path of existing files. Relative paths of the loaded files are
+    /// resolved against the same directory when matching them, so changing the
+    /// current directory later doesn't change which files are overridden. Overridden
+    /// content is scanned for includes like any other.
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    /// use std::path::PathBuf;
+    /// use recursive_file_loader::LoaderBuilder;
+    ///
+    /// let loader = LoaderBuilder::new()
+    ///     .overrides(HashMap::from([(PathBuf::from("header.txt"), "preview".to_owned())]))
+    ///     .build();
+    /// ```
+    pub fn overrides(mut self, overrides: HashMap<PathBuf, String>) -> Self {
+        let current_dir = std::env::current_dir().unwrap_or_default();
+        for (path, content) in overrides {
+            if let Ok(canonical) = std::fs::canonicalize(&path) {
+                self.options.overrides.insert(canonical, content.clone());
+            }
+            self.options.overrides.insert(clean(&current_dir.join(path)), content);
+        }
+        self.options.override_dir = current_dir;
+        self
+    }
+
+    /// Substitute a placeholder for includes whose file doesn't exist instead of
+    /// failing, see [LoaderBuilder::failure_placeholder]. Other errors, and a
+    /// missing file passed to the loader itself, still fail. When building include
+    /// trees, files closing a cycle are marked instead of failing, see
+    /// [crate::IncludeTree::closes_cycle]. Disabled by default.
+    pub fn lenient(mut self, lenient: bool) -> Self {
+        self.options.lenient = lenient;
+        self
+    }
+
+    /// Set the text substituted for includes of missing files in lenient mode,
+    /// with `{path}` replaced by the path of the missing file. It's indented like
+    /// the content of an included file. Defaults to the text of the directive.
+    ///
+    /// ```
+    /// use recursive_file_loader::LoaderBuilder;
+    ///
+    /// let loader = LoaderBuilder::new()
+    ///     .lenient(true)
+    ///     .failure_placeholder("<!-- missing: {path} -->")
+    ///     .build();
+    /// ```
+    pub fn failure_placeholder<S: AsRef<str>>(mut self, placeholder: S) -> Self {
+        self.options.failure_placeholder = Some(placeholder.as_ref().to_owned());
+        self
+    }
+
+    /// Strip the leading whitespace common to all lines of an included file before
+    /// indenting it, so snippets that are indented themselves aren't indented twice.
+    /// Disabled by default.
+    pub fn dedent_included(mut self, dedent_included: bool) -> Self {
+        self.options.dedent_included = dedent_included;
+        self
+    }
+
+    /// Decode every file with the encoding of the given label, like `windows-1252`
+    /// or `shift_jis`, instead of reading it as UTF-8 or UTF-16 depending on its
+    /// byte order mark. Labels are looked up as in the [WHATWG Encoding Standard],
+    /// so e.g. `latin1` selects windows-1252. A byte order mark of the encoding is
+    /// removed, others are kept as part of the text. Loads fail with an
+    /// [Error::UnknownEncoding] for labels that don't name an encoding. UTF-8 is used
+    /// by default.
+    ///
+    /// Invalid sequences fail the load unless [LoaderBuilder::lossy_decoding] is
+    /// enabled.
+    ///
+    /// ```
+    /// use recursive_file_loader::LoaderBuilder;
+    ///
+    /// let loader = LoaderBuilder::new()
+    ///     .encoding("windows-1252")
+    ///     .build();
+    /// ```
+    ///
+    /// [WHATWG Encoding Standard]: https://encoding.spec.whatwg.org/#names-and-labels
+    #[cfg(feature = "encoding")]
+    pub fn encoding(mut self, label: &str) -> Self {
+        self.options.encoding = Some(label.to_owned());
+        self
+    }
+
+    /// Replace invalid sequences in files with U+FFFD instead of failing with an
+    /// [Error::InvalidUtf8] or an invalid data error. Applies to the detected
+    /// encoding as well as one set with `LoaderBuilder::encoding`. Disabled by
+    /// default.
+    pub fn lossy_decoding(mut self, lossy_decoding: bool) -> Self {
+        self.options.lossy_decoding = lossy_decoding;
+        self
+    }
+
+    /// Remove spaces and tabs at the end of every line of each file as it's read,
+    /// before looking for directives in it. Line breaks are kept. Disabled by default.
+    pub fn trim_trailing_whitespace(mut self, trim_trailing_whitespace: bool) -> Self {
+        self.options.trim_trailing_whitespace = trim_trailing_whitespace;
+        self
+    }
+
+    /// Control whether the loaded text ends with a line break. `Some(true)` ends it
+    /// with exactly one, `Some(false)` removes all trailing line breaks, and `None`
+    /// keeps the text as loaded, which is the default. Included files are unaffected.
+    pub fn ensure_final_newline(mut self, ensure_final_newline: Option<bool>) -> Self {
+        self.options.ensure_final_newline = ensure_final_newline;
+        self
+    }
+
+    /// Fail with an [crate::Error::LineTooLong] if any line of the loaded text is
+    /// longer than the given number of characters. The text is checked as a whole
+    /// once assembled, so a line spliced together from several files is measured in
+    /// full. Unlimited by default.
+    pub fn max_line_length(mut self, max_line_length: usize) -> Self {
+        self.options.max_line_length = Some(max_line_length);
+        self
+    }
+
+    /// Fail with an [crate::Error::Unbalanced] if the delimiters of the loaded text
+    /// aren't balanced, e.g. for a JSON or XML document assembled from several
+    /// files. Like [LoaderBuilder::max_line_length], the text is checked as a whole
+    /// once assembled. Not checked by default.
+    ///
+    /// ```
+    /// use recursive_file_loader::{Delimiters, Error, LoaderBuilder};
+    /// # let dir = temp_dir::TempDir::new().unwrap();
+    /// # let path = dir.child("doc.json");
+    /// # std::fs::write(&path, "{\"items\": [1, 2}").unwrap();
+    ///
+    /// let loader = LoaderBuilder::new().validate_balanced(Delimiters::Json).build();
+    ///
+    /// assert!(matches!(loader.load_file_recursively(&path), Err(Error::Unbalanced(_, 15, _))));
+    /// ```
+    pub fn validate_balanced(mut self, delimiters: Delimiters) -> Self {
+        self.options.validate_balanced = Some(delimiters);
+        self
+    }
+
+    /// Replace `${var:NAME}` placeholders in the content of every loaded file with
+    /// the value of the variable, or fail with an [crate::Error::UndefinedVariable]
+    /// if it isn't defined. `${var:NAME:default}` uses the default instead, and a
+    /// backslash before a placeholder keeps it as is.
+    ///
+    /// Placeholders are replaced right after reading a file, before looking for
+    /// directives in it, so variables can be used in include paths and values may
+    /// contain directives. Content included by `include_escaped` is left as is.
+    /// Placeholders aren't replaced by default.
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    /// use recursive_file_loader::LoaderBuilder;
+    /// # let dir = temp_dir::TempDir::new().unwrap();
+    /// # let path = dir.child("greeting.txt");
+    /// # std::fs::write(&path, "Hello ${var:name}${var:punctuation:!}").unwrap();
+    ///
+    /// let loader = LoaderBuilder::new()
+    ///     .variables(HashMap::from([("name".to_owned(), "World".to_owned())]))
+    ///     .build();
+    ///
+    /// assert_eq!(loader.load_file_recursively(&path).unwrap(), "Hello World!");
+    /// ```
+    pub fn variables(mut self, variables: HashMap<String, String>) -> Self {
+        self.options.variables = Some(variables);
+        self
+    }
+
+    /// Surround the content of every included file with comments of the given style
+    /// marking where it begins and ends, like `// >>> begin included/path.txt` and
+    /// `// <<< end included/path.txt`. The path is relative to the including file's
+    /// directory. The markers are put on lines of their own and indented along with
+    /// the content. Disabled by default.
+    pub fn annotate_boundaries(mut self, style: CommentStyle) -> Self {
+        self.options.annotate_boundaries = Some(style);
+        self
+    }
+
+    /// Set how the indentation in front of directives like `include_indent` is
+    /// reproduced in the included content. Defaults to [IndentMode::Literal].
+    pub fn indent_mode(mut self, indent_mode: IndentMode) -> Self {
+        self.options.indent_mode = indent_mode;
+        self
+    }
+
+    /// Set the number of the first line of files included with `include_numbered`.
+    /// Defaults to 1.
+    pub fn line_number_start(mut self, start: usize) -> Self {
+        self.options.line_number_start = Some(start);
+        self
+    }
+
+    /// Set the minimum width line numbers of `include_numbered` are right-aligned
+    /// to. By default they're aligned to the width of the largest number.
+    pub fn line_number_width(mut self, width: usize) -> Self {
+        self.options.line_number_width = width;
+        self
+    }
+
+    /// Set the markers delimiting the sections selected by `include_section`. A
+    /// section starts with a line consisting of the start marker and the section's
+    /// name and ends with a line starting with the end marker. Defaults to
+    /// `# region <name>` and `# endregion`.
+    ///
+    /// ```
+    /// use recursive_file_loader::LoaderBuilder;
+    ///
+    /// let loader = LoaderBuilder::new()
+    ///     .region_markers("//#region", "//#endregion")
+    ///     .build();
+    /// ```
+    pub fn region_markers<S: AsRef<str>, T: AsRef<str>>(mut self, start: S, end: T) -> Self {
+        self.options.region_markers = Some((start.as_ref().to_owned(), end.as_ref().to_owned()));
+        self
+    }
+
+    /// Resolve paths starting with `@/` against the closest directory containing a
+    /// file with the given name, searching upwards from the including file. Without
+    /// a marker such paths are relative paths like any other.
+    ///
+    /// ```
+    /// use recursive_file_loader::LoaderBuilder;
+    ///
+    /// // `${include("@/shared/header.txt")}` will load `shared/header.txt` next to `.loaderroot`
+    /// let loader = LoaderBuilder::new()
+    ///     .root_marker(".loaderroot")
+    ///     .build();
+    /// ```
+    pub fn root_marker<S: AsRef<str>>(mut self, marker: S) -> Self {
+        self.options.root_marker = Some(marker.as_ref().to_owned());
+        self
+    }
+
+    /// Expand a leading `~` or `~/` in include paths to the home directory of the
+    /// current user, taken from `HOME`, or `USERPROFILE` on Windows. Paths like
+    /// `~user/x.txt` fail with an [Error::TildeNotExpanded]. Disabled by default, so
+    /// that file names starting with `~` work as usual.
+    pub fn expand_tilde(mut self, expand_tilde: bool) -> Self {
+        self.options.expand_tilde = expand_tilde;
+        self
+    }
+
+    /// Let `include_where` directives include files without frontmatter, whose
+    /// conditions can't be evaluated. They're left out by default.
+    #[cfg(feature = "glob")]
+    pub fn include_without_frontmatter(mut self, include_without_frontmatter: bool) -> Self {
+        self.options.include_without_frontmatter = include_without_frontmatter;
+        self
+    }
+
+    /// Only recognize the directives with the given names, e.g. `["include",
+    /// "include_with"]` to disable everything else. Disabled directives are kept
+    /// as literal text, see [LoaderBuilder::reject_disabled_directives]. Only
+    /// `include` and `include_indent` are enabled by default, see
+    /// [LoaderBuilder::allow_all_directives].
+    ///
+    /// ```
+    /// use recursive_file_loader::LoaderBuilder;
+    ///
+    /// let loader = LoaderBuilder::new()
+    ///     .allowed_directives(&["include", "include_indent", "include_with"])
+    ///     .build();
+    /// ```
+    pub fn allowed_directives<I, S>(mut self, directives: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.options.allowed_directives = Some(directives.into_iter().map(|it| it.as_ref().to_owned()).collect());
+        self
+    }
+
+    /// Recognize every directive, instead of only `include` and `include_indent`,
+    /// see [LoaderBuilder::allowed_directives].
+    pub fn allow_all_directives(mut self) -> Self {
+        self.options.allowed_directives = Some(directives::directive_names());
+        self
+    }
+
+    /// Fail with an [Error::DirectiveNotAllowed] on directives disabled by
+    /// [LoaderBuilder::allowed_directives], instead of keeping them as literal text.
+    /// Disabled by default.
+    pub fn reject_disabled_directives(mut self, reject_disabled_directives: bool) -> Self {
+        self.options.reject_disabled_directives = reject_disabled_directives;
+        self
+    }
+
+    /// Fail with an [Error::UnknownDirective] on text shaped like a directive, e.g.
+    /// `${inclde("x")}` or `${name}`, that isn't a recognized one, instead of
+    /// keeping it as is. Such text has to be escaped with a backslash to appear in
+    /// the output. Disabled by default.
+    pub fn strict_directives(mut self, strict_directives: bool) -> Self {
+        self.options.strict_directives = strict_directives;
+        self
+    }
+
+    /// Accept a single path without quotes, e.g. `${include(header.txt)}`, which ends
+    /// at the closing parenthesis and can't contain whitespace, quotes, parentheses,
+    /// commas or `#`. Quoted paths keep working alongside. Disabled by default.
+    pub fn allow_unquoted_paths(mut self, allow_unquoted_paths: bool) -> Self {
+        self.options.allow_unquoted_paths = allow_unquoted_paths;
+        self
+    }
+
+    /// Fail with an [Error::UnclosedDirective] on an opening like `${include(` that
+    /// isn't closed on the same line, e.g. because an editor wrapped the line, instead
+    /// of keeping it as is. Escaped openings are ignored. Disabled by default.
+    pub fn warn_unclosed_directives(mut self, warn_unclosed_directives: bool) -> Self {
+        self.options.warn_unclosed_directives = warn_unclosed_directives;
+        self
+    }
+
+    /// Fail with an [Error::DuplicateInclude] when a file is included a second
+    /// time anywhere in the same load, not just within its own include chain.
+    /// Files are loaded in document order, so the first include of a file is the
+    /// one that comes first in the output. Disabled by default.
+    pub fn forbid_duplicate_includes(mut self, forbid_duplicate_includes: bool) -> Self {
+        self.options.forbid_duplicate_includes = forbid_duplicate_includes;
+        self
+    }
+
+    /// Collect the files referenced through differently spelled paths, e.g.
+    /// `./a.txt` and `../dir/a.txt` from the same directory, which is often
+    /// unintentional duplication. They're returned by [Loader::load_with_warnings]
+    /// without failing the load. Disabled by default.
+    pub fn warn_redundant_paths(mut self, warn_redundant_paths: bool) -> Self {
+        self.options.warn_redundant_paths = warn_redundant_paths;
+        self
+    }
+
+    /// Transform the content of every file after reading it and before looking
+    /// for directives in it. The callback receives the path of the file as it was
+    /// referenced. Cycle detection happens before the transform and isn't affected
+    /// by it.
+    ///
+    /// ```
+    /// use recursive_file_loader::LoaderBuilder;
+    ///
+    /// let loader = LoaderBuilder::new()
+    ///     .transform(|_path, content| Ok(content.replace("{{year}}", "2024")))
+    ///     .build();
+    /// ```
+    pub fn transform<F>(mut self, transform: F) -> Self
+    where
+        F: Fn(&Path, String) -> Result<String, Error> + Send + Sync + 'static,
+    {
+        self.options.transform = Some(Callback(Arc::new(transform)));
+        self
+    }
+
+    /// Run the shell commands of `include_cmd` directives and insert their output.
+    /// Disabled by default, as it allows any loaded file to run arbitrary commands
+    /// with the permissions of the process. Only enable it for trusted files.
+    ///
+    /// Commands run with `sh -c`, or `cmd /C` on Windows, in the directory of the
+    /// file containing the directive. Their output is decoded as UTF-8, replacing
+    /// invalid sequences.
+    pub fn allow_commands(mut self, allow_commands: bool) -> Self {
+        self.options.allow_commands = allow_commands;
+        self
+    }
+
+    /// Read files from the given source instead of the file system.
+    pub fn file_source<S: FileSource + 'static>(mut self, source: S) -> Self {
+        self.options.file_source = Some(Callback(Arc::new(source)));
+        self
+    }
+
+    /// Fetch includes referring to `http://` or `https://` URLs from the network.
+    /// Relative includes in remote files are resolved against their URL. Disabled
+    /// by default, as it allows included files to make requests to any host.
+    ///
+    /// ```
+    /// use recursive_file_loader::LoaderBuilder;
+    ///
+    /// // `${include("https://intranet/base.txt")}` will fetch the file
+    /// let loader = LoaderBuilder::new()
+    ///     .allow_http(true)
+    ///     .build();
+    /// ```
+    #[cfg(feature = "http")]
+    pub fn allow_http(mut self, allow_http: bool) -> Self {
+        self.options.allow_http = allow_http;
+        self
+    }
+
+    /// Fail with an [Error::HttpTimeout] if a remote file takes longer than the
+    /// given duration to fetch. Defaults to 30 seconds.
+    #[cfg(feature = "http")]
+    pub fn http_timeout(mut self, timeout: Duration) -> Self {
+        self.options.http_timeout = Some(timeout);
+        self
+    }
+
+    /// Take the resolved content of files from the given cache where their
+    /// content and that of everything they include is unchanged, and store it
+    /// there otherwise. All files are still read to compute the cache keys.
+    ///
+    /// ```
+    /// use recursive_file_loader::{DirectoryCache, LoaderBuilder};
+    ///
+    /// let loader = LoaderBuilder::new()
+    ///     .disk_cache(DirectoryCache::new("target/include-cache"))
+    ///     .build();
+    /// ```
+    #[cfg(feature = "cache")]
+    pub fn disk_cache<C: DiskCache + 'static>(mut self, cache: C) -> Self {
+        self.options.disk_cache = Some(Callback(Arc::new(cache)));
+        self
+    }
+
+    /// The settings of this builder that can be expressed as [LoadOptions].
+    pub fn load_options(&self) -> LoadOptions {
+        LoadOptions {
+            max_depth: self.options.max_depth,
+            restrict_to: self.options.restrict_to.clone(),
+            max_file_bytes: self.options.max_file_bytes,
+            max_includes_per_file: self.options.max_includes_per_file,
+            max_expansions: self.options.max_expansions,
+            relative_to: self.options.relative_to,
+            base_dir_override: self.options.base_dir_override.clone(),
+            escaped_output: self.options.escaped_output,
+            lenient: self.options.lenient,
+            strict_directives: self.options.strict_directives,
+        }
+    }
+
+    pub fn build(self) -> Loader {
+        Loader::with_options(self.options)
+    }
+}
+
+impl From<LoadOptions> for LoaderBuilder {
+    fn from(options: LoadOptions) -> Self {
+        Self {
+            options: Options {
+                max_depth: options.max_depth,
+                restrict_to: options.restrict_to,
+                max_file_bytes: options.max_file_bytes,
+                max_includes_per_file: options.max_includes_per_file,
+                max_expansions: options.max_expansions,
+                relative_to: options.relative_to,
+                base_dir_override: options.base_dir_override,
+                escaped_output: options.escaped_output,
+                lenient: options.lenient,
+                strict_directives: options.strict_directives,
+                ..Default::default()
+            },
+        }
+    }
+}
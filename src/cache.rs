@@ -0,0 +1,149 @@
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+use crate::includes::Include;
+
+/// A persistent store for resolved file contents, see [crate::LoaderBuilder::disk_cache].
+///
+/// Entries are keyed by the hex encoded SHA-256 hash of a file's path and content
+/// together with the hashes of everything it includes, so a changed file
+/// invalidates all files including it. The values of `include_env`, `include_cmd`
+/// and the other directives not including a file are part of the hash as well. The stored text doesn't depend on other
+/// loader settings, so a cache should only be shared between loaders configured
+/// the same way.
+pub trait DiskCache: Send + Sync {
+    /// Look up the resolved content stored for the given hash.
+    fn get(&self, hash: &str) -> Option<String>;
+
+    /// Store the resolved content for the given hash.
+    fn put(&self, hash: &str, text: String);
+
+    /// Remove all entries, see [crate::Loader::clear_cache]. Does nothing by default.
+    fn clear(&self) {}
+}
+
+/// Disk cache usage of a loader since it was created or last cleared, see
+/// [crate::Loader::cache_stats].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    hits: usize,
+    misses: usize,
+    entries: usize,
+}
+
+impl CacheStats {
+    pub(crate) fn record_lookup(&mut self, hit: bool) {
+        match hit {
+            true => self.hits += 1,
+            false => self.misses += 1,
+        }
+    }
+
+    pub(crate) fn record_entry(&mut self) {
+        self.entries += 1;
+    }
+
+    /// The number of files whose resolved content was taken from the cache.
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+
+    /// The number of files whose content had to be assembled.
+    pub fn misses(&self) -> usize {
+        self.misses
+    }
+
+    /// The number of entries the loader stored in the cache.
+    pub fn entries(&self) -> usize {
+        self.entries
+    }
+}
+
+/// A [DiskCache] storing each entry as a file named after its hash in a directory.
+/// The directory should be used for nothing else, as clearing the cache removes it.
+///
+/// Failures to read or write entries are ignored, they only cause cache misses.
+#[derive(Debug, Clone)]
+pub struct DirectoryCache {
+    dir: PathBuf,
+}
+
+impl DirectoryCache {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        Self { dir: dir.as_ref().to_owned() }
+    }
+}
+
+impl DiskCache for DirectoryCache {
+    fn get(&self, hash: &str) -> Option<String> {
+        std::fs::read_to_string(self.dir.join(hash)).ok()
+    }
+
+    fn put(&self, hash: &str, text: String) {
+        let _ = std::fs::create_dir_all(&self.dir)
+            .and_then(|_| std::fs::write(self.dir.join(hash), text));
+    }
+
+    fn clear(&self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// A file that was read along with everything it includes, not yet assembled.
+pub(crate) struct HashedFile {
+    pub(crate) path: PathBuf,
+    pub(crate) content: String,
+    pub(crate) includes: Vec<Include>,
+    /// The loaded file for each of `includes`, if it refers to one.
+    pub(crate) children: Vec<Option<HashedFile>>,
+    pub(crate) hash: String,
+}
+
+impl HashedFile {
+    pub(crate) fn new(
+        path: &Path,
+        content: String,
+        includes: Vec<Include>,
+        children: Vec<Option<HashedFile>>,
+    ) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(path.to_string_lossy().as_bytes());
+        hasher.update([0]);
+        hasher.update(content.as_bytes());
+        for child in &children {
+            hasher.update([0]);
+            if let Some(child) = child {
+                hasher.update(child.hash.as_bytes());
+            }
+        }
+        let hash = hasher
+            .finalize()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+
+        Self { path: path.to_owned(), content, includes, children, hash }
+    }
+}
+
+#[cfg(test)]
+mod test_directory_cache {
+    use super::{DirectoryCache, DiskCache};
+    use rstest::rstest;
+    use temp_dir::TempDir;
+
+    #[rstest]
+    fn should_store_entries_in_directory() -> Result<(), std::io::Error> {
+        let dir = TempDir::new()?;
+        let cache = DirectoryCache::new(dir.child("cache"));
+
+        assert_eq!(cache.get("abc"), None);
+        cache.put("abc", "text".to_owned());
+        assert_eq!(cache.get("abc").as_deref(), Some("text"));
+        cache.clear();
+        assert_eq!(cache.get("abc"), None);
+
+        Ok(())
+    }
+}
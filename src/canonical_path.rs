@@ -1,24 +1,48 @@
-use std::{path::{Path, PathBuf}, hash::Hash, fmt::Display, io::ErrorKind};
+use std::{path::{Component, Path, PathBuf}, hash::Hash, fmt::Display, io::{self, ErrorKind}};
 
 use crate::Error;
 
+/// A path along with the key identifying the file it points to.
+///
+/// The canonical path is either the symlink-resolved path or, for
+/// [CanonicalPath::literal], the lexically cleaned absolute path. It's used to
+/// access the file and, unless altered e.g. by [CanonicalPath::into_lowercase],
+/// as the key for equality.
 #[derive(Debug, Clone)]
 pub struct CanonicalPath {
     source: PathBuf,
     canonical: PathBuf,
+    key: PathBuf,
 }
 
 impl CanonicalPath {
-    pub fn new<P: AsRef<Path>>(source: P) -> Result<Self, Error> {
+    /// Create a path using a custom function in place of [std::fs::canonicalize].
+    pub fn with_canonicalizer<P, F>(source: P, canonicalize: F) -> Result<Self, Error>
+    where
+        P: AsRef<Path>,
+        F: FnOnce(&Path) -> io::Result<PathBuf>,
+    {
         let source = source.as_ref().to_owned();
-        let canonical = std::fs::canonicalize(&source).map_err(|e| {
-            match e.kind() {
-                ErrorKind::NotFound => Error::FileNotFound(source.clone()),
-                _ => Error::IOError(e),
-            }
-        })?;
+        let canonical = canonicalize(&source).map_err(|e| to_error(&source, e))?;
 
-        Ok(CanonicalPath { source, canonical })
+        Ok(CanonicalPath { source, key: canonical.clone(), canonical })
+    }
+
+    /// Create a path keyed on its lexically cleaned absolute form, without
+    /// resolving symlinks.
+    pub fn literal<P: AsRef<Path>>(source: P) -> Result<Self, Error> {
+        let source = source.as_ref().to_owned();
+        std::fs::metadata(&source).map_err(|e| to_error(&source, e))?;
+        let canonical = clean(&std::env::current_dir()?.join(&source));
+
+        Ok(CanonicalPath { source, key: canonical.clone(), canonical })
+    }
+
+    /// Lowercase the key, so paths differing only in case are considered equal.
+    pub fn into_lowercase(self) -> Self {
+        let key = PathBuf::from(self.key.to_string_lossy().to_lowercase());
+
+        Self { key, ..self }
     }
 
     pub fn source(&self) -> &Path {
@@ -32,13 +56,37 @@ impl CanonicalPath {
         let source = PathBuf::from_str(source).unwrap();
         let canonical = PathBuf::from_str(canonical).unwrap();
 
-        Self { source, canonical }
+        Self { source, key: canonical.clone(), canonical }
+    }
+}
+
+/// Map a failure to access `source` to an [Error].
+pub(crate) fn to_error(source: &Path, e: io::Error) -> Error {
+    match e.kind() {
+        ErrorKind::NotFound => Error::FileNotFound(source.to_owned()),
+        _ => Error::ReadFailed(source.to_owned(), e),
+    }
+}
+
+/// Lexically remove `.` and `..` components from a path.
+pub(crate) fn clean(path: &Path) -> PathBuf {
+    let mut cleaned = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {},
+            Component::ParentDir => {
+                cleaned.pop();
+            },
+            other => cleaned.push(other),
+        }
     }
+
+    cleaned
 }
 
 impl PartialEq for CanonicalPath {
     fn eq(&self, other: &Self) -> bool {
-        self.canonical == other.canonical
+        self.key == other.key
     }
 }
 
@@ -46,7 +94,21 @@ impl Eq for CanonicalPath {}
 
 impl Hash for CanonicalPath {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        self.canonical.hash(state);
+        self.key.hash(state);
+    }
+}
+
+/// Orders by the key, like equality, so equal paths with different sources
+/// compare as equal.
+impl Ord for CanonicalPath {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.key.cmp(&other.key)
+    }
+}
+
+impl PartialOrd for CanonicalPath {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
     }
 }
 
@@ -61,3 +123,56 @@ impl Display for CanonicalPath {
         write!(f, "{}", self.source.to_string_lossy())
     }
 }
+
+#[cfg(test)]
+mod test_clean {
+    use super::clean;
+    use rstest::rstest;
+    use std::path::Path;
+
+    #[rstest]
+    #[case("/a/b/c.txt", "/a/b/c.txt")]
+    #[case("/a/./b/c.txt", "/a/b/c.txt")]
+    #[case("/a/b/../c.txt", "/a/c.txt")]
+    #[case("/a/../../c.txt", "/c.txt")]
+    fn should_clean_paths(
+        #[case] input: &str,
+        #[case] expectation: &str,
+    ) {
+        assert_eq!(clean(Path::new(input)), Path::new(expectation));
+    }
+}
+
+#[cfg(test)]
+mod test_ordering {
+    use super::CanonicalPath;
+    use rstest::rstest;
+    use std::cmp::Ordering;
+
+    #[rstest]
+    #[case(("b", "/a"), ("a", "/b"), Ordering::Less)]
+    #[case(("a", "/b"), ("b", "/a"), Ordering::Greater)]
+    #[case(("a", "/dir/x"), ("../dir/x", "/dir/x"), Ordering::Equal)]
+    fn should_order_by_canonical_path(
+        #[case] left: (&str, &str),
+        #[case] right: (&str, &str),
+        #[case] expectation: Ordering,
+    ) {
+        let left = CanonicalPath::_new(left.0, left.1);
+        let right = CanonicalPath::_new(right.0, right.1);
+
+        assert_eq!(left.cmp(&right), expectation);
+        assert_eq!(left == right, expectation == Ordering::Equal);
+    }
+
+    #[rstest]
+    fn should_sort_case_insensitive_paths_by_key() {
+        let mut paths = [
+            CanonicalPath::_new("b", "/B").into_lowercase(),
+            CanonicalPath::_new("a", "/a").into_lowercase(),
+        ];
+        paths.sort();
+
+        assert_eq!(paths.iter().map(|it| it.source().to_str().unwrap()).collect::<Vec<_>>(), vec!["a", "b"]);
+    }
+}
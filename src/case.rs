@@ -0,0 +1,141 @@
+use std::{
+    ffi::{OsStr, OsString},
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::Error;
+
+/// Compare every component of `path` against the listing of its parent directory.
+///
+/// Returns the path as it's spelled on disk if some component only matches an
+/// entry of its directory when ignoring case, `None` if all components match
+/// exactly or some component doesn't exist at all.
+pub(crate) fn find_case_mismatch(path: &Path) -> Result<Option<PathBuf>, Error> {
+    let mut actual = PathBuf::new();
+    let mut mismatch = false;
+
+    for component in path.components() {
+        let Component::Normal(name) = component else {
+            actual.push(component);
+            continue;
+        };
+
+        let directory = match actual.as_os_str().is_empty() {
+            true => Path::new("."),
+            false => actual.as_path(),
+        };
+        match matching_entry(directory, name)? {
+            Some(entry) => {
+                mismatch |= entry != name;
+                actual.push(entry);
+            },
+            None => return Ok(None),
+        }
+    }
+
+    Ok(mismatch.then_some(actual))
+}
+
+/// Find the entry of `directory` matching `name`, preferring an exact match over
+/// one that only matches when ignoring case.
+fn matching_entry(directory: &Path, name: &OsStr) -> Result<Option<OsString>, Error> {
+    Ok(matching_entries(directory, name)?.pop())
+}
+
+/// Find the entries of `directory` matching `name`: the exact match if there is
+/// one, otherwise all entries that match when ignoring case.
+fn matching_entries(directory: &Path, name: &OsStr) -> Result<Vec<OsString>, Error> {
+    let Ok(entries) = fs::read_dir(directory) else {
+        return Ok(vec![]);
+    };
+
+    let lowercase_name = name.to_string_lossy().to_lowercase();
+    let mut candidates = vec![];
+    for entry in entries {
+        let entry_name = entry?.file_name();
+        if entry_name == name {
+            return Ok(vec![entry_name]);
+        }
+        if entry_name.to_string_lossy().to_lowercase() == lowercase_name {
+            candidates.push(entry_name);
+        }
+    }
+    candidates.sort();
+
+    Ok(candidates)
+}
+
+/// Find the file `path` refers to when ignoring the case of its components that
+/// don't exist as spelled, see [crate::LoaderBuilder::resolve_case_insensitive].
+///
+/// Returns `None` if some component has no match at all, and fails with an
+/// [Error::AmbiguousCase] if some component matches several entries.
+pub(crate) fn resolve_case_insensitive(path: &Path) -> Result<Option<PathBuf>, Error> {
+    let mut actual = PathBuf::new();
+
+    for component in path.components() {
+        let Component::Normal(name) = component else {
+            actual.push(component);
+            continue;
+        };
+
+        let directory = match actual.as_os_str().is_empty() {
+            true => Path::new("."),
+            false => actual.as_path(),
+        };
+        match matching_entries(directory, name)?.as_slice() {
+            [] => return Ok(None),
+            [entry] => actual.push(entry),
+            entries => {
+                let matches = entries.iter().map(|it| actual.join(it)).collect();
+                return Err(Error::AmbiguousCase(path.to_owned(), matches));
+            },
+        }
+    }
+
+    Ok(Some(actual))
+}
+
+#[cfg(test)]
+mod test_find_case_mismatch {
+    use super::{find_case_mismatch, resolve_case_insensitive};
+    use crate::Error;
+    use rstest::rstest;
+    use temp_dir::TempDir;
+
+    #[rstest]
+    fn should_find_case_mismatches() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::create_dir(dir.child("Sub"))?;
+        std::fs::write(dir.child("Sub/file.txt"), "".as_bytes())?;
+
+        assert_eq!(find_case_mismatch(&dir.child("Sub/file.txt"))?, None);
+        assert_eq!(find_case_mismatch(&dir.child("sub/FILE.txt"))?, Some(dir.child("Sub/file.txt")));
+        assert_eq!(find_case_mismatch(&dir.child("sub/other.txt"))?, None);
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_resolve_paths_ignoring_case() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::create_dir(dir.child("Sub"))?;
+        std::fs::write(dir.child("Sub/File.txt"), "".as_bytes())?;
+        std::fs::write(dir.child("Sub/twin.txt"), "".as_bytes())?;
+        std::fs::write(dir.child("Sub/TWIN.txt"), "".as_bytes())?;
+
+        assert_eq!(resolve_case_insensitive(&dir.child("sub/file.TXT"))?, Some(dir.child("Sub/File.txt")));
+        assert_eq!(resolve_case_insensitive(&dir.child("Sub/twin.txt"))?, Some(dir.child("Sub/twin.txt")));
+        assert_eq!(resolve_case_insensitive(&dir.child("sub/other.txt"))?, None);
+        match resolve_case_insensitive(&dir.child("sub/Twin.txt")) {
+            Err(Error::AmbiguousCase(path, matches)) => {
+                assert_eq!(path, dir.child("sub/Twin.txt"));
+                assert_eq!(matches, vec![dir.child("Sub/TWIN.txt"), dir.child("Sub/twin.txt")]);
+            },
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        Ok(())
+    }
+}
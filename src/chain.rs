@@ -0,0 +1,103 @@
+use std::{fmt::Display, path::{Path, PathBuf}};
+
+use crate::Error;
+
+/// An [Error] along with the chain of files that led to it, see
+/// [crate::Loader::load_with_chain].
+///
+/// `Display` shows the error alone, [ChainedError::chain_display] adds the chain.
+#[derive(Debug)]
+pub struct ChainedError {
+    error: Error,
+    chain: Vec<PathBuf>,
+}
+
+impl ChainedError {
+    pub(crate) fn new(error: Error, chain: Vec<PathBuf>) -> Self {
+        Self { error, chain }
+    }
+
+    pub fn error(&self) -> &Error {
+        &self.error
+    }
+
+    pub fn into_error(self) -> Error {
+        self.error
+    }
+
+    /// The files from the loaded file down to the one that failed to load or
+    /// contains the failing directive.
+    pub fn chain(&self) -> &[PathBuf] {
+        &self.chain
+    }
+
+    /// Render the error followed by one line per file of the chain, innermost first.
+    ///
+    /// ```text
+    /// file not found: 'missing.txt'
+    ///   while loading 'missing.txt'
+    ///   included from 'nested.txt'
+    ///   included from 'start.txt'
+    /// ```
+    pub fn chain_display(&self) -> String {
+        let mut lines = vec![self.error.to_string()];
+        for (index, path) in self.chain.iter().rev().enumerate() {
+            let context = match index {
+                0 => "while loading",
+                _ => "included from",
+            };
+            lines.push(format!("  {} '{}'", context, path.to_string_lossy()));
+        }
+
+        lines.join("\n")
+    }
+}
+
+impl Display for ChainedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+impl std::error::Error for ChainedError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl From<ChainedError> for Error {
+    fn from(value: ChainedError) -> Self {
+        value.error
+    }
+}
+
+/// Build a chain from the paths of a frame, innermost first, and an optional
+/// path entered from it.
+pub(crate) fn collect<'a, I: Iterator<Item = &'a Path>>(paths: I, entered: Option<&Path>) -> Vec<PathBuf> {
+    let mut chain: Vec<PathBuf> = paths.map(Path::to_owned).collect();
+    chain.reverse();
+    chain.extend(entered.map(Path::to_owned));
+    chain
+}
+
+#[cfg(test)]
+mod test_chained_error {
+    use super::ChainedError;
+    use crate::Error;
+    use rstest::rstest;
+    use std::path::PathBuf;
+
+    #[rstest]
+    fn should_display_chain_innermost_first() {
+        let error = ChainedError::new(
+            Error::FileNotFound(PathBuf::from("c.txt")),
+            vec![PathBuf::from("a.txt"), PathBuf::from("b.txt"), PathBuf::from("c.txt")],
+        );
+
+        assert_eq!(error.to_string(), "file not found: 'c.txt'");
+        assert_eq!(
+            error.chain_display(),
+            "file not found: 'c.txt'\n  while loading 'c.txt'\n  included from 'b.txt'\n  included from 'a.txt'",
+        );
+    }
+}
@@ -0,0 +1,99 @@
+use std::path::Path;
+
+use crate::{encoding, Error};
+
+/// Whether an include path is an inline `data:` URI rather than a path.
+pub(crate) fn is_data_uri(path: &str) -> bool {
+    path.starts_with("data:")
+}
+
+/// Decode the content of a `data:[<media type>][;base64],<data>` URI. The data is
+/// percent-decoded, or base64 decoded if marked as such, and then read like a file.
+pub(crate) fn decode(uri: &str) -> Result<String, Error> {
+    encoding::decode(Path::new(uri), decode_bytes(uri)?)
+}
+
+/// Like [decode], but returns the decoded bytes as they are.
+pub(crate) fn decode_bytes(uri: &str) -> Result<Vec<u8>, Error> {
+    let invalid = |reason: &str| Error::InvalidDataUri(uri.to_owned(), reason.to_owned());
+
+    let (header, data) = uri
+        .strip_prefix("data:")
+        .and_then(|it| it.split_once(','))
+        .ok_or_else(|| invalid("missing ','"))?;
+    let bytes = match header.strip_suffix(";base64") {
+        Some(_) => decode_base64(data).map_err(|reason| invalid(&reason))?,
+        None => decode_percent(data).ok_or_else(|| invalid("malformed percent-encoding"))?,
+    };
+
+    Ok(bytes)
+}
+
+#[cfg(feature = "base64")]
+fn decode_base64(data: &str) -> Result<Vec<u8>, String> {
+    use base64::Engine;
+
+    base64::engine::general_purpose::STANDARD
+        .decode(data)
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(not(feature = "base64"))]
+fn decode_base64(_data: &str) -> Result<Vec<u8>, String> {
+    Err("base64 data requires the `base64` feature".to_owned())
+}
+
+/// Replace `%` followed by two hex digits with the byte they encode.
+fn decode_percent(data: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(data.len());
+    let mut rest = data.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        rest = match byte {
+            b'%' => {
+                let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+                bytes.push(u8::from_str_radix(hex, 16).ok()?);
+                &tail[2..]
+            },
+            _ => {
+                bytes.push(byte);
+                tail
+            },
+        };
+    }
+
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod test_decode {
+    use super::decode;
+    use crate::Error;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("data:,Hello", "Hello")]
+    #[case("data:text/plain,a%20b%0Ac", "a b\nc")]
+    #[case("data:text/plain;charset=utf-8,%C3%A4", "ä")]
+    #[cfg_attr(feature = "base64", case("data:text/plain;base64,SGVsbG8=", "Hello"))]
+    fn should_decode_data_uris(
+        #[case] uri: &str,
+        #[case] expectation: &str,
+    ) -> Result<(), Error> {
+        assert_eq!(decode(uri)?, expectation);
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[case("data:text/plain")]
+    #[case("data:,%4")]
+    #[case("data:,%zz")]
+    #[case("data:;base64,SGVsbG8")]
+    #[case("data:;base64,****")]
+    fn should_reject_malformed_data_uris(#[case] uri: &str) {
+        match decode(uri) {
+            Err(Error::InvalidDataUri(actual, _)) => assert_eq!(actual, uri),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
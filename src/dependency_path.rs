@@ -1,17 +1,134 @@
-use std::path::{PathBuf, Path};
+use std::{collections::HashMap, path::{PathBuf, Path}};
+
+use crate::{canonical_path::clean, Error, RelativeBase};
+#[cfg(feature = "http")]
+use crate::http;
+
+/// The prefix of paths resolved against the directory containing the root marker.
+const MARKED_ROOT_PREFIX: &str = "@/";
+/// The prefix of paths resolved against the directory of the loaded root file.
+const DOCUMENT_ROOT_PREFIX: &str = "^/";
 
 pub trait DependencyPath {
-    fn get_dependency_path(&self, path: &str) -> PathBuf;
+    /// Resolve `path` as referenced from this file. Paths starting with `@/` are
+    /// resolved against `marked_root` instead, if there is one, and paths starting
+    /// with `^/` against `document_root`, the directory of the loaded root file.
+    fn get_dependency_path(
+        &self,
+        path: &str,
+        relative_to: RelativeBase,
+        marked_root: Option<&Path>,
+        document_root: Option<&Path>,
+    ) -> PathBuf;
+}
+
+/// Whether the path is meant to be resolved against the marked root directory.
+pub(crate) fn is_marked(path: &str) -> bool {
+    path.starts_with(MARKED_ROOT_PREFIX)
+}
+
+/// Whether the path is meant to be resolved against the directory of the loaded
+/// root file.
+pub(crate) fn is_document_relative(path: &str) -> bool {
+    path.starts_with(DOCUMENT_ROOT_PREFIX)
+}
+
+/// Expand a leading `~` or `~/` of the path to the home directory. Returns `None`
+/// for paths not starting with `~`, and fails for the unsupported `~user` form or
+/// if there is no home directory.
+pub(crate) fn expand_tilde(path: &str, home: Option<PathBuf>) -> Result<Option<PathBuf>, Error> {
+    let Some(rest) = path.strip_prefix('~') else {
+        return Ok(None);
+    };
+    let rest = match rest.strip_prefix(['/', std::path::MAIN_SEPARATOR]) {
+        Some(rest) => rest,
+        None if rest.is_empty() => rest,
+        None => return Err(Error::TildeNotExpanded(path.to_owned())),
+    };
+
+    home.map(|home| Some(home.join(rest)))
+        .ok_or_else(|| Error::TildeNotExpanded(path.to_owned()))
+}
+
+/// The home directory of the current user, according to the environment.
+pub(crate) fn home_dir() -> Option<PathBuf> {
+    let name = match cfg!(windows) {
+        true => "USERPROFILE",
+        false => "HOME",
+    };
+
+    std::env::var_os(name).filter(|it| !it.is_empty()).map(PathBuf::from)
+}
+
+/// Find the closest directory containing a file named `marker`, starting at `dir`
+/// and walking up. Results are remembered in `cache` for every directory visited.
+pub(crate) fn find_marked_root(
+    dir: &Path,
+    marker: &str,
+    cache: &mut HashMap<PathBuf, Option<PathBuf>>,
+) -> Option<PathBuf> {
+    let mut visited = vec![];
+    let mut root = None;
+    for ancestor in dir.ancestors() {
+        if let Some(cached) = cache.get(ancestor) {
+            root = cached.clone();
+            break;
+        }
+        visited.push(ancestor.to_owned());
+        if ancestor.join(marker).exists() {
+            root = Some(ancestor.to_owned());
+            break;
+        }
+    }
+
+    for dir in visited {
+        cache.insert(dir, root.clone());
+    }
+
+    root
+}
+
+/// Find the file at `path` in the first of the layered `roots` containing it, where
+/// the location of `path` relative to whichever root it lies in is looked up in all
+/// of them, see [crate::LoaderBuilder::layered_roots]. Returns `None` if `path`
+/// isn't inside any of the roots or none of them contains the file.
+pub(crate) fn find_in_layers<F>(path: &Path, roots: &[PathBuf], exists: F) -> Option<PathBuf>
+where
+    F: Fn(&Path) -> bool,
+{
+    let path = clean(path);
+    let relative = roots.iter().find_map(|root| path.strip_prefix(root).ok())?;
+
+    roots.iter().map(|root| root.join(relative)).find(|it| exists(it))
 }
 
 impl<T: AsRef<Path>> DependencyPath for T {
     fn get_dependency_path(
         &self,
-        path: &str
+        path: &str,
+        relative_to: RelativeBase,
+        marked_root: Option<&Path>,
+        document_root: Option<&Path>,
     ) -> PathBuf {
+        if let Some(root) = marked_root.filter(|_| is_marked(path)) {
+            return root.join(&path[MARKED_ROOT_PREFIX.len()..]);
+        }
+        if let Some(root) = document_root.filter(|_| is_document_relative(path)) {
+            return root.join(&path[DOCUMENT_ROOT_PREFIX.len()..]);
+        }
+
         let origin_path = self.as_ref();
+        #[cfg(feature = "http")]
+        if http::is_url(Path::new(path)) {
+            return PathBuf::from(path);
+        }
+        #[cfg(feature = "http")]
+        if http::is_url(origin_path) {
+            return http::join(origin_path, path);
+        }
+
         let path = Path::new(path);
-        let ret = if path.is_absolute() {
+        let ret = if path.is_absolute() || relative_to == RelativeBase::Cwd {
             path.to_path_buf()
         } else if origin_path.is_dir() {
             origin_path.join(path)
@@ -22,3 +139,145 @@ impl<T: AsRef<Path>> DependencyPath for T {
         ret
     }
 }
+
+#[cfg(test)]
+mod test_dependency_path {
+    use super::DependencyPath;
+    use crate::RelativeBase;
+    use rstest::rstest;
+    use std::path::Path;
+
+    #[rstest]
+    #[case("/dir/start.txt", "x.txt", RelativeBase::SourceFile, "/dir/x.txt")]
+    #[case("/dir/start.txt", "../x.txt", RelativeBase::SourceFile, "/dir/../x.txt")]
+    #[case("/dir/start.txt", "/other/x.txt", RelativeBase::SourceFile, "/other/x.txt")]
+    #[case("/dir/start.txt", "x.txt", RelativeBase::Cwd, "x.txt")]
+    #[case("/dir/start.txt", "/other/x.txt", RelativeBase::Cwd, "/other/x.txt")]
+    fn should_resolve_dependency_paths(
+        #[case] origin: &str,
+        #[case] path: &str,
+        #[case] relative_to: RelativeBase,
+        #[case] expectation: &str,
+    ) {
+        assert_eq!(origin.get_dependency_path(path, relative_to, None, None), Path::new(expectation));
+    }
+
+    #[rstest]
+    #[case("@/x.txt", Some("/root"), "/root/x.txt")]
+    #[case("@/sub/x.txt", Some("/root"), "/root/sub/x.txt")]
+    #[case("@/x.txt", None, "/dir/@/x.txt")]
+    #[case("x.txt", Some("/root"), "/dir/x.txt")]
+    fn should_resolve_marked_paths(
+        #[case] path: &str,
+        #[case] marked_root: Option<&str>,
+        #[case] expectation: &str,
+    ) {
+        let marked_root = marked_root.map(Path::new);
+        assert_eq!(
+            "/dir/start.txt".get_dependency_path(path, RelativeBase::SourceFile, marked_root, None),
+            Path::new(expectation),
+        );
+    }
+
+    #[rstest]
+    #[case("^/x.txt", Some("/top"), "/top/x.txt")]
+    #[case("^/shared/x.txt", Some("top"), "top/shared/x.txt")]
+    #[case("^/x.txt", None, "/dir/^/x.txt")]
+    #[case("x.txt", Some("/top"), "/dir/x.txt")]
+    fn should_resolve_paths_relative_to_document_root(
+        #[case] path: &str,
+        #[case] document_root: Option<&str>,
+        #[case] expectation: &str,
+    ) {
+        let document_root = document_root.map(Path::new);
+        assert_eq!(
+            "/dir/start.txt".get_dependency_path(path, RelativeBase::SourceFile, None, document_root),
+            Path::new(expectation),
+        );
+    }
+}
+
+#[cfg(test)]
+mod test_expand_tilde {
+    use super::expand_tilde;
+    use crate::Error;
+    use rstest::rstest;
+    use std::path::PathBuf;
+
+    #[rstest]
+    #[case("~", Some("/home/me"))]
+    #[case("~/sub/x.txt", Some("/home/me/sub/x.txt"))]
+    #[case("dir/a~b.txt", None)]
+    #[case("x~", None)]
+    fn should_expand_leading_tilde(
+        #[case] path: &str,
+        #[case] expectation: Option<&str>,
+    ) -> Result<(), Error> {
+        assert_eq!(expand_tilde(path, Some("/home/me".into()))?, expectation.map(PathBuf::from));
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[case("~user/x.txt", Some("/home/me"))]
+    #[case("~/x.txt", None)]
+    fn should_fail_to_expand_tilde(
+        #[case] path: &str,
+        #[case] home: Option<&str>,
+    ) {
+        match expand_tilde(path, home.map(PathBuf::from)) {
+            Err(Error::TildeNotExpanded(it)) => assert_eq!(it, path),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_find_in_layers {
+    use super::find_in_layers;
+    use rstest::rstest;
+    use std::path::{Path, PathBuf};
+
+    #[rstest]
+    #[case("/overlay/a.txt", Some("/overlay/a.txt"))]
+    #[case("/base/sub/../a.txt", Some("/overlay/a.txt"))]
+    #[case("/base/b.txt", Some("/base/b.txt"))]
+    #[case("/overlay/b.txt", Some("/base/b.txt"))]
+    #[case("/base/c.txt", None)]
+    #[case("/elsewhere/a.txt", None)]
+    fn should_find_files_in_the_first_layer_containing_them(
+        #[case] path: &str,
+        #[case] expectation: Option<&str>,
+    ) {
+        let roots = [PathBuf::from("/overlay"), PathBuf::from("/base")];
+        let files = [Path::new("/overlay/a.txt"), Path::new("/base/a.txt"), Path::new("/base/b.txt")];
+
+        let found = find_in_layers(Path::new(path), &roots, |it| files.contains(&it));
+        assert_eq!(found, expectation.map(PathBuf::from));
+    }
+}
+
+#[cfg(test)]
+mod test_find_marked_root {
+    use super::find_marked_root;
+    use rstest::rstest;
+    use std::collections::HashMap;
+    use temp_dir::TempDir;
+
+    #[rstest]
+    fn should_find_closest_marker() -> Result<(), std::io::Error> {
+        let dir = TempDir::new()?;
+        std::fs::create_dir_all(dir.child("a/b/c"))?;
+        std::fs::write(dir.child("a/.root"), [])?;
+
+        let mut cache = HashMap::new();
+        assert_eq!(find_marked_root(&dir.child("a/b/c"), ".root", &mut cache), Some(dir.child("a")));
+        assert_eq!(cache.get(&dir.child("a/b")), Some(&Some(dir.child("a"))));
+
+        std::fs::remove_file(dir.child("a/.root"))?;
+        assert_eq!(find_marked_root(&dir.child("a/b"), ".root", &mut cache), Some(dir.child("a")));
+        assert_eq!(find_marked_root(&dir.child("a/b"), ".root", &mut HashMap::new()), None);
+
+        Ok(())
+    }
+}
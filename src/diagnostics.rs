@@ -0,0 +1,168 @@
+use std::path::{Path, PathBuf};
+
+use crate::{Error, SyntaxIssue};
+
+/// The issues found in a tree of files by [crate::Loader::diagnose], ordered by
+/// file, then by position.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Diagnostics {
+    entries: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    pub(crate) fn new(mut entries: Vec<Diagnostic>) -> Self {
+        entries.sort();
+        entries.dedup();
+
+        Self { entries }
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Diagnostic> {
+        self.entries.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl IntoIterator for Diagnostics {
+    type Item = Diagnostic;
+    type IntoIter = std::vec::IntoIter<Diagnostic>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Diagnostics {
+    type Item = &'a Diagnostic;
+    type IntoIter = std::slice::Iter<'a, Diagnostic>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.iter()
+    }
+}
+
+/// The kind of a [Diagnostic].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DiagnosticKind {
+    /// A referenced file, or all of its fallbacks, doesn't exist.
+    MissingFile,
+
+    /// A file includes itself, directly or through other files.
+    Cycle,
+
+    /// A directive with an empty path or variable name.
+    EmptyPath,
+
+    /// A directive-like expression with a name that isn't a known directive.
+    UnknownDirective,
+
+    /// A known directive with arguments it doesn't accept.
+    InvalidArguments,
+
+    /// A directive that isn't closed on its line.
+    Unclosed,
+
+    /// Any other error, e.g. a file that can't be read or decoded.
+    Other,
+}
+
+/// An issue found in a file, see [Diagnostics].
+///
+/// Diagnostics are ordered by the path of the file, then by their position.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Diagnostic {
+    path: PathBuf,
+    position: Option<(usize, usize)>,
+    kind: DiagnosticKind,
+    message: String,
+}
+
+impl Diagnostic {
+    /// A diagnostic for a malformed directive in `text`, the content of the file at `path`.
+    pub(crate) fn from_syntax(path: &Path, text: &str, issue: &SyntaxIssue) -> Self {
+        let kind = match issue {
+            SyntaxIssue::EmptyPath(_) => DiagnosticKind::EmptyPath,
+            SyntaxIssue::UnknownDirective(_) => DiagnosticKind::UnknownDirective,
+            SyntaxIssue::InvalidArguments(_) => DiagnosticKind::InvalidArguments,
+            SyntaxIssue::Unclosed(_) => DiagnosticKind::Unclosed,
+        };
+
+        Self {
+            path: path.to_owned(),
+            position: Some(position(text, issue.range().start)),
+            kind,
+            message: format!("{} `{}`", issue.description(), &text[issue.range().clone()]),
+        }
+    }
+
+    /// A diagnostic for an error loading the file at `path`, or one it includes at
+    /// the given position. Errors loading an entry of a list file are reported at
+    /// the entry's line in the list file.
+    pub(crate) fn from_error(path: &Path, position: Option<(usize, usize)>, error: &Error) -> Self {
+        let kind = match error {
+            Error::ListEntry(list, line, error) => {
+                return Self { path: list.to_owned(), position: Some((*line, 1)), ..Self::from_error(path, position, error) };
+            },
+            Error::FileNotFound(_) | Error::FilesNotFound(_) => DiagnosticKind::MissingFile,
+            Error::CyclicDependency { .. } => DiagnosticKind::Cycle,
+            Error::InvalidArgument(..) => DiagnosticKind::InvalidArguments,
+            _ => DiagnosticKind::Other,
+        };
+
+        Self { path: path.to_owned(), position, kind, message: error.to_string() }
+    }
+
+    /// The file the issue was found in, as referenced by the file including it.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The line and column of the issue in its file, both starting at 1, with
+    /// columns counted in characters. `None` for issues concerning the file as a
+    /// whole, like a root file that doesn't exist.
+    pub fn position(&self) -> Option<(usize, usize)> {
+        self.position
+    }
+
+    pub fn kind(&self) -> DiagnosticKind {
+        self.kind
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+/// The line and column of the byte `offset` in `text`, both starting at 1.
+pub(crate) fn position(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset];
+    let line_start = before.rfind('\n').map_or(0, |it| it + 1);
+
+    (before.matches('\n').count() + 1, before[line_start..].chars().count() + 1)
+}
+
+#[cfg(test)]
+mod test_position {
+    use super::position;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("abc", 0, (1, 1))]
+    #[case("abc", 2, (1, 3))]
+    #[case("a\nbc", 2, (2, 1))]
+    #[case("a\r\näb\nc", 5, (2, 2))]
+    fn should_find_line_and_column(
+        #[case] text: &str,
+        #[case] offset: usize,
+        #[case] expectation: (usize, usize),
+    ) {
+        assert_eq!(position(text, offset), expectation);
+    }
+}
@@ -0,0 +1,696 @@
+use std::{borrow::Cow, path::{Path, PathBuf}};
+
+use crate::{
+    data,
+    escape::EscapeFormat,
+    includes::{render_texts, Include, IncludeKind, Segments},
+    list,
+    options::{CommentStyle, Options},
+    section,
+    tree::relative_path,
+    EscapedOutput, Error,
+};
+
+/// The names of all directives, for telling malformed directives from unknown ones.
+const DIRECTIVE_NAMES: [&str; 18] = [
+    "include", "include_indent", "include_with", "include_wrap", "include_fenced", "include_numbered",
+    "include_repeat", "include_escaped", "include_raw_bytes", "include_list", "include_if", "include_if_newer", "include_section", "include_name", "include_env", "include_env_indent", "include_cmd",
+    "self_path",
+];
+
+/// The names of the directives only available with the `glob` feature.
+const GLOB_DIRECTIVE_NAMES: [&str; 2] = ["include_glob", "include_where"];
+
+/// The names of the directives enabled unless configured otherwise, see
+/// [crate::LoaderBuilder::allowed_directives].
+pub(crate) const DEFAULT_DIRECTIVE_NAMES: [&str; 2] = ["include", "include_indent"];
+
+/// Whether the name is that of a directive available with the enabled features.
+pub(crate) fn is_directive_name(name: &str) -> bool {
+    DIRECTIVE_NAMES.contains(&name) || (cfg!(feature = "glob") && GLOB_DIRECTIVE_NAMES.contains(&name))
+}
+
+/// The names of all directives available with the enabled features.
+pub(crate) fn directive_names() -> Vec<String> {
+    DIRECTIVE_NAMES
+        .iter()
+        .chain(GLOB_DIRECTIVE_NAMES.iter().filter(|_| cfg!(feature = "glob")))
+        .map(|it| it.to_string())
+        .collect()
+}
+
+/// Find the directives in `text` and turn them into includes, last one first.
+///
+/// `source` is the path of the file containing the text, which `${self_path}`
+/// expands to; without it `${self_path}` is left as is. `resolve_path` maps the
+/// path arguments of directives to the paths of the included files. Failures to
+/// resolve the path of an escaped directive are ignored.
+///
+/// Directives with invalid arguments fail the search, unless `invalid` is given,
+/// which collects their errors in the order they appear in instead, each with the
+/// offset of the directive, leaving the directives out.
+///
+/// This only works on the text and doesn't access the file system itself.
+pub(crate) fn find_includes<F>(
+    text: &str,
+    source: Option<&Path>,
+    options: &Options,
+    mut invalid: Option<&mut Vec<(usize, Error)>>,
+    resolve_path: F,
+) -> Result<Vec<Include>, Error>
+where
+    F: Fn(&str) -> Result<PathBuf, Error>,
+{
+    use lazy_regex::{regex::Match, Captures};
+
+    // Every directive starts with `${`, so files without it can skip the regex.
+    if !text.contains("${") {
+        return Ok(vec![]);
+    }
+
+    let env_regex = lazy_regex::regex!(
+        r##"(?P<backslashes>\\*)(?P<expr>\$\{(?:(?P<name>include\w*)[ \t]*\([ \t]*(?:(?P<unquoted>[^\s"(),\#]+)|(?P<args>(?:"(?:[^"\\]|\\.)*"|[^"\n(){}])*?))(?:[ \t]+else[ \t]+"(?P<fallback>(?:[^"\\]|\\.)*)")?[ \t]*(?:\#[^\n]*?)?\)|(?P<variable>self_path))})"##
+    );
+
+    let line_breaks: Vec<usize> = text.match_indices('\n').map(|(index, _)| index).collect();
+
+    let parse = |capture: Captures| {
+        let backslashes = capture.name("backslashes").unwrap().range();
+        let expression: Match = capture.name("expr").unwrap();
+        let name = capture.name("name").or(capture.name("variable")).unwrap().as_str();
+        let line = line_breaks.partition_point(|it| *it < expression.start()) + 1;
+        let is_escaped = backslashes.len() % 2 == 1;
+        let is_allowed = match &options.allowed_directives {
+            Some(allowed) => allowed.iter().any(|it| it == name),
+            None => DEFAULT_DIRECTIVE_NAMES.contains(&name),
+        };
+        if !is_allowed {
+            return (options.reject_disabled_directives && !is_escaped).then(|| Err(Error::DirectiveNotAllowed(
+                source.unwrap_or(Path::new("")).to_owned(),
+                line,
+                expression.as_str().to_owned(),
+            )));
+        }
+        let arguments = match (capture.name("args"), capture.name("unquoted")) {
+            (Some(args), _) => match parse_arguments(args.as_str()) {
+                Ok(arguments) => arguments,
+                Err(message) if options.strict_directives && !is_escaped => return Some(Err(Error::InvalidArgument(
+                    source.unwrap_or(Path::new("")).to_owned(),
+                    line,
+                    format!("{} in {}", message, expression.as_str()),
+                ))),
+                Err(_) => return None,
+            },
+            (None, Some(path)) if options.allow_unquoted_paths => Arguments(vec![(None, Cow::Borrowed(path.as_str()))]),
+            (None, Some(_)) => return None,
+            (None, None) => Arguments::default(),
+        };
+        let args = arguments.positional();
+
+        let new_include_for = |path: PathBuf, aligned: bool| {
+            let include = Include::new(
+                expression.range(),
+                path,
+                backslashes.clone(),
+                None,
+            )
+            .with_expression(expression.as_str(), line)
+            .with_dedent(options.dedent_included);
+            match aligned {
+                true => include.with_alignment(options.indent_mode),
+                false => include,
+            }
+        };
+        let new_path_include = |path: &str, aligned: bool, data_uris: bool| {
+            let rewritten = options.rewrite_path.as_ref().and_then(|rewrite| (rewrite.0)(path));
+            let path = rewritten.as_deref().unwrap_or(path);
+            if data_uris && data::is_data_uri(path) {
+                return Ok(new_include_for(PathBuf::from(path), aligned).with_kind(IncludeKind::Data));
+            }
+            let resolved = match resolve_path(path) {
+                Err(_) if is_escaped => PathBuf::from(path),
+                resolved => resolved?,
+            };
+            Ok(new_include_for(resolved, aligned))
+        };
+        // Only directives including the content at the path accept a data URI instead.
+        let new_include = |path: &str, aligned: bool| new_path_include(path, aligned, true);
+
+        let include = match (name, args.as_slice()) {
+            ("include", [path]) => new_include(path, false),
+            ("include_indent", [path]) => new_include(path, true),
+            ("include_with", [indentation, path]) => new_include(path, false).map(|it| it.with_indentation(*indentation)),
+            ("include_wrap", [prefix, suffix, path]) => new_include(path, true)
+                .map(|it| it.with_wrapping(unescape(prefix), unescape(suffix))),
+            ("include_fenced", [language, path]) => new_include(path, true)
+                .map(|it| it.with_wrapping(format!("```{}\n", language), "\n```")),
+            ("include_numbered", [path]) => new_include(path, true)
+                .map(|it| it.with_numbering(
+                    options.line_number_start.unwrap_or(1),
+                    options.line_number_width,
+                )),
+            ("include_repeat", [path, count]) => match count.trim().parse() {
+                Ok(count) => new_include(path, true).map(|it| it.with_repeat(count)),
+                Err(_) if is_escaped => new_include(path, false),
+                Err(_) => Err(Error::InvalidArgument(
+                    source.unwrap_or(Path::new("")).to_owned(),
+                    line,
+                    format!("invalid count \"{}\" in {}", count, expression.as_str()),
+                )),
+            },
+            ("include_escaped", [format, path]) => match format.parse::<EscapeFormat>() {
+                Ok(format) => new_include(path, false).map(|it| it.with_escape(format)),
+                Err(_) if is_escaped => new_include(path, false),
+                Err(message) => Err(Error::InvalidArgument(
+                    source.unwrap_or(Path::new("")).to_owned(),
+                    line,
+                    format!("{} in {}", message, expression.as_str()),
+                )),
+            },
+            ("include_if", [condition, path]) => new_include(path, false)
+                .map(|it| it.with_condition(*condition)),
+            ("include_if_newer", [path]) => new_include(path, false)
+                .map(|it| it.with_if_newer(true)),
+            ("include_section", [path, name]) => new_path_include(path, false, false)
+                .map(|it| it.with_section(*name)),
+            ("include_raw_bytes", [path]) => new_include(path, false)
+                .map(|it| it.with_kind(IncludeKind::RawBytes)),
+            ("include_list", [path]) => new_path_include(path, false, false)
+                .map(|it| it.with_kind(IncludeKind::List)),
+            ("include_name", [path]) => new_path_include(path, false, false)
+                .map(|it| it.with_kind(IncludeKind::Name)),
+            ("include_env", [name]) => Ok(new_include_for(PathBuf::from(name), false)
+                .with_kind(IncludeKind::Env)),
+            ("include_env", [name, default]) => Ok(new_include_for(PathBuf::from(name), false)
+                .with_kind(IncludeKind::Env)
+                .with_default(*default)),
+            ("include_env_indent", [name]) => Ok(new_include_for(PathBuf::from(name), true)
+                .with_kind(IncludeKind::Env)),
+            ("include_env_indent", [name, default]) => Ok(new_include_for(PathBuf::from(name), true)
+                .with_kind(IncludeKind::Env)
+                .with_default(*default)),
+            ("include_cmd", [command]) => Ok(new_include_for(PathBuf::from(command), true)
+                .with_kind(IncludeKind::Command)),
+            #[cfg(feature = "glob")]
+            ("include_glob", [pattern]) => new_path_include(pattern, false, false)
+                .map(|it| it.with_kind(IncludeKind::Glob)),
+            #[cfg(feature = "glob")]
+            ("include_where", [pattern, condition]) => new_path_include(pattern, false, false)
+                .map(|it| it.with_kind(IncludeKind::Glob).with_filter(*condition)),
+            ("self_path", []) => Ok(new_include_for(source?.to_owned(), false)
+                .with_kind(IncludeKind::Name)),
+            _ => return None,
+        };
+
+        let include = match (include, capture.name("fallback")) {
+            (Ok(include), Some(fallback)) if include.kind() == IncludeKind::File => {
+                new_include(&unescape_quotes(fallback.as_str()), false).map(|it| include.with_fallback(it.path()))
+            },
+            (Ok(_), Some(_)) => return None,
+            (include, _) => include,
+        };
+        let include = match include {
+            Ok(include) if !is_escaped => arguments
+                .apply_named(include)
+                .map_err(|message| Error::InvalidArgument(
+                    source.unwrap_or(Path::new("")).to_owned(),
+                    line,
+                    format!("{} in {}", message, expression.as_str()),
+                )),
+            include => include,
+        };
+        let include = match (include, &options.annotate_boundaries) {
+            (Ok(include), Some(style)) if include.kind() == IncludeKind::File => Ok(annotate(include, source, style)),
+            (include, _) => include,
+        };
+        Some(include)
+    };
+    let captures: Vec<Result<Include, Error>> = env_regex
+        .captures_iter(text)
+        .map(|capture| (capture.name("expr").unwrap().start(), capture))
+        .filter_map(|(start, capture)| match (parse(capture)?, invalid.as_deref_mut()) {
+            (Err(error @ Error::InvalidArgument(..)), Some(invalid)) => {
+                invalid.push((start, error));
+                None
+            },
+            (include, _) => Some(include),
+        })
+        .collect();
+
+    let source = source.unwrap_or(Path::new(""));
+    let includes = captures.into_iter().rev().collect::<Result<Vec<_>, _>>()?;
+    if options.strict_directives {
+        check_unknown_directives(source, text, &includes)?;
+    }
+    if options.warn_unclosed_directives {
+        check_unclosed_directives(source, text, &includes)?;
+    }
+    if let Some(max_includes) = options.max_includes_per_file {
+        let count = includes.iter().filter(|it| !it.is_escaped()).count();
+        if count > max_includes {
+            return Err(Error::TooManyIncludes(source.to_owned(), count));
+        }
+    }
+
+    Ok(includes)
+}
+
+/// Mark the beginning and end of the included file with comments, see
+/// [crate::LoaderBuilder::annotate_boundaries].
+pub(crate) fn annotate(include: Include, source: Option<&Path>, style: &CommentStyle) -> Include {
+    let path = match source.and_then(Path::parent) {
+        Some(dir) => relative_path(include.path(), dir),
+        None => include.path().to_owned(),
+    };
+    let begin = style.comment(&format!(">>> begin {}", path.display()));
+    let end = style.comment(&format!("<<< end {}", path.display()));
+
+    include.with_annotation(begin, end)
+}
+
+/// Replace the directives in `content` with the content returned by `resolver`
+/// for their path, see [crate::resolve_includes].
+pub(crate) fn resolve_includes<F>(content: &str, mut resolver: F) -> Result<String, Error>
+where
+    F: FnMut(&Path) -> Result<String, Error>,
+{
+    let includes = find_includes(content, None, &Options::with_all_directives(), None, |path| Ok(PathBuf::from(path)))?;
+    let mut produce = |include: &Include| match include.kind() {
+        IncludeKind::File if is_emitted(include) => {
+            let text = load_with_fallback(include, &mut resolver)?;
+            match include.section() {
+                Some(name) => {
+                    let (start, end) = section::DEFAULT_MARKERS;
+                    section::extract(&text, name, start, end)
+                        .ok_or_else(|| Error::SectionNotFound(include.path().to_owned(), name.to_owned()))
+                },
+                None => Ok(text),
+            }
+        },
+        IncludeKind::RawBytes if is_emitted(include) => match data::is_data_uri(&include.path().to_string_lossy()) {
+            true => data::decode(&include.path().to_string_lossy()),
+            false => resolver(include.path()),
+        },
+        IncludeKind::List if is_emitted(include) => {
+            let list = include.path();
+            let text = resolver(list)?;
+            let texts = list::entries(&text)
+                .map(|(line, entry)| {
+                    let path = list.parent().unwrap_or(Path::new("")).join(entry);
+                    let text = resolver(&path).map_err(|error| Error::ListEntry(list.to_owned(), line, Box::new(error)))?;
+                    Ok(text.strip_suffix('\n').map(str::to_owned).unwrap_or(text))
+                })
+                .collect::<Result<Vec<_>, Error>>()?;
+            Ok(texts.join(include.separator()))
+        },
+        _ => produce_value(include),
+    };
+    // The texts are produced in the order the includes appear in.
+    let texts = includes
+        .iter()
+        .rev()
+        .map(|include| (!include.is_escaped()).then(|| produce(include)).transpose())
+        .collect::<Result<Vec<_>, Error>>()?;
+    let texts = render_texts(content, &includes, texts, EscapedOutput::Collapsed)?;
+
+    let mut content = Segments::new(content.to_owned());
+    for (include, text) in includes.iter().zip(texts.into_iter().rev()) {
+        include.replace(&mut content, EscapedOutput::Collapsed, text);
+    }
+
+    Ok(content.into_string())
+}
+
+/// Load the file an include refers to, or its fallback if it doesn't exist.
+pub(crate) fn load_with_fallback<T, F>(include: &Include, mut load: F) -> Result<T, Error>
+where
+    F: FnMut(&Path) -> Result<T, Error>,
+{
+    let Some(fallback) = include.fallback() else {
+        return load(include.path());
+    };
+
+    let mut tried = match load(include.path()) {
+        Err(error) => not_found(&error, include.path()).ok_or(error)?,
+        result => return result,
+    };
+    match load(fallback) {
+        Err(error) => {
+            tried.extend(not_found(&error, fallback).ok_or(error)?);
+            Err(Error::FilesNotFound(tried))
+        },
+        result => result,
+    }
+}
+
+/// The paths tried if the error means that the given path wasn't found.
+pub(crate) fn not_found(error: &Error, path: &Path) -> Option<Vec<PathBuf>> {
+    match error {
+        Error::FileNotFound(it) if it == path => Some(vec![it.to_owned()]),
+        Error::ListEntry(_, _, error) => not_found(error, path),
+        Error::FilesNotFound(it) if it.first().is_some_and(|first| first == path) => Some(it.to_owned()),
+        _ => None,
+    }
+}
+
+/// Whether an include produces anything, i.e. whether the environment variable
+/// of an `include_if` is set to a non-empty value and an `include_repeat` has a
+/// count other than zero. Files of includes that aren't emitted must not be read.
+pub(crate) fn is_emitted(include: &Include) -> bool {
+    include.repeat() != Some(0)
+        && include
+            .condition()
+            .map_or(true, |name| std::env::var_os(name).is_some_and(|value| !value.is_empty()))
+}
+
+/// Produce the text of an include that doesn't refer to a file or isn't emitted.
+/// Commands and glob patterns are refused, they're up to the loader.
+pub(crate) fn produce_value(include: &Include) -> Result<String, Error> {
+    match include.kind() {
+        _ if !is_emitted(include) => Ok(String::new()),
+        IncludeKind::Data => data::decode(&include.path().to_string_lossy()),
+        IncludeKind::Command => Err(Error::CommandNotAllowed(include.path().to_string_lossy().into_owned())),
+        #[cfg(feature = "glob")]
+        IncludeKind::Glob => Err(Error::InvalidGlob(
+            include.path().to_string_lossy().into_owned(),
+            "glob patterns can only be expanded when loading files".to_owned(),
+        )),
+        IncludeKind::Env => std::env::var(include.path())
+            .ok()
+            .or_else(|| include.default().map(str::to_owned))
+            .ok_or_else(|| Error::UndefinedEnvVar(include.path().to_string_lossy().into_owned())),
+        _ => Ok(include.path().to_string_lossy().into_owned()),
+    }
+}
+
+/// Fail on the first unescaped directive-like expression in the text that isn't
+/// one of the recognized includes.
+fn check_unknown_directives(source: &Path, text: &str, includes: &[Include]) -> Result<(), Error> {
+    match unknown_directives(text, includes).next() {
+        Some(expr) => Err(Error::UnknownDirective(expr.as_str().to_owned(), source.to_owned())),
+        None => Ok(()),
+    }
+}
+
+/// The unescaped directive-like expressions in the text that aren't one of the
+/// recognized includes.
+pub(crate) fn unknown_directives<'t>(
+    text: &'t str,
+    includes: &[Include],
+) -> impl Iterator<Item = lazy_regex::regex::Match<'t>> {
+    let directive_regex = lazy_regex::regex!(
+        r"(?P<backslashes>\\*)(?P<expr>\$\{[ \t]*[A-Za-z_]\w*[ \t]*(?:\([^\n]*?\))?[ \t]*})"
+    );
+    let ranges: Vec<_> = includes.iter().map(|it| it.range().clone()).collect();
+
+    directive_regex
+        .captures_iter(text)
+        .filter(|it| it["backslashes"].len() % 2 == 0)
+        .map(|it| it.name("expr").unwrap())
+        .filter(move |expr| !ranges.iter().any(|range| range.contains(&expr.start())))
+}
+
+/// Fail on the first unescaped opening of an include directive that isn't one of
+/// the recognized includes and isn't closed on its line.
+fn check_unclosed_directives(source: &Path, text: &str, includes: &[Include]) -> Result<(), Error> {
+    match unclosed_directives(text, includes).next() {
+        Some(opening) => Err(Error::UnclosedDirective(source.to_owned(), text[..opening.start()].matches('\n').count() + 1)),
+        None => Ok(()),
+    }
+}
+
+/// The unescaped openings of include directives that aren't one of the recognized
+/// includes and aren't closed on their line.
+pub(crate) fn unclosed_directives<'t>(
+    text: &'t str,
+    includes: &[Include],
+) -> impl Iterator<Item = lazy_regex::regex::Match<'t>> {
+    let opening_regex = lazy_regex::regex!(r"(?P<backslashes>\\*)(?P<opening>\$\{[ \t]*include\w*[ \t]*\()");
+    let is_closed = |end: usize| {
+        let line = text[end..].split('\n').next().unwrap_or_default();
+        line.split("${").next().unwrap_or_default().contains('}')
+    };
+    let ranges: Vec<_> = includes.iter().map(|it| it.range().clone()).collect();
+
+    opening_regex
+        .captures_iter(text)
+        .filter(|it| it["backslashes"].len() % 2 == 0)
+        .map(|it| it.name("opening").unwrap())
+        .filter(move |opening| !is_closed(opening.end()))
+        .filter(move |opening| !ranges.iter().any(|range| range.contains(&opening.start())))
+}
+
+/// The arguments of a directive in order, with the names of named arguments like
+/// `indent="  "`.
+#[derive(Debug, Default)]
+struct Arguments<'a>(Vec<(Option<&'a str>, Cow<'a, str>)>);
+
+impl Arguments<'_> {
+    fn positional(&self) -> Vec<&str> {
+        self.0
+            .iter()
+            .filter(|(name, _)| name.is_none())
+            .map(|(_, value)| value.as_ref())
+            .collect()
+    }
+
+    /// Apply the named arguments to the include, failing if they don't all follow
+    /// the positional ones or one of them is unknown or given more than once.
+    fn apply_named(&self, mut include: Include) -> Result<Include, String> {
+        let mut seen = vec![];
+        for (name, value) in &self.0 {
+            let Some(name) = name else {
+                match seen.is_empty() {
+                    true => continue,
+                    false => return Err(format!("positional argument \"{}\" follows named arguments", value)),
+                }
+            };
+            if seen.contains(name) {
+                return Err(format!("argument '{}' is given more than once", name));
+            }
+            seen.push(name);
+
+            include = match *name {
+                "indent" => include.with_indentation(value.as_ref()),
+                #[cfg(feature = "glob")]
+                "sort" if include.kind() == IncludeKind::Glob => include.with_glob_order(value.parse()?),
+                "sep" if include.kind().is_multi_file() => include.with_separator(unescape(value)),
+                _ => return Err(format!("unknown argument '{}'", name)),
+            };
+        }
+
+        Ok(include)
+    }
+}
+
+/// Parse a directive's argument list: quoted strings, with escaped quotes unescaped,
+/// and unquoted numbers, each optionally named like `indent="  "` and separated by
+/// commas. Spaces and tabs around arguments and a single trailing comma are allowed.
+/// Fails with a description of the first problem found.
+fn parse_arguments(args: &str) -> Result<Arguments<'_>, String> {
+    let argument_regex = lazy_regex::regex!(r#"^(?:(?P<name>\w+)[ \t]*=[ \t]*)?(?:"(?P<value>(?:[^"\\]|\\.)*)"|(?P<number>\d+)\b)"#);
+
+    let mut arguments = vec![];
+    let mut rest = args.trim_start_matches([' ', '\t']);
+    while !rest.is_empty() {
+        let Some(capture) = argument_regex.captures(rest) else {
+            return Err(describe_malformed_argument(rest));
+        };
+        arguments.push((
+            capture.name("name").map(|it| it.as_str()),
+            match capture.name("value") {
+                Some(value) => unescape_quotes(value.as_str()),
+                None => Cow::Borrowed(capture.name("number").unwrap().as_str()),
+            },
+        ));
+
+        rest = rest[capture.get(0).unwrap().end()..].trim_start_matches([' ', '\t']);
+        match rest.strip_prefix(',') {
+            Some(tail) => rest = tail.trim_start_matches([' ', '\t']),
+            None if rest.is_empty() => {},
+            None => return Err(format!("missing comma before `{}`", rest)),
+        }
+    }
+
+    Ok(Arguments(arguments))
+}
+
+/// Describe why the argument at the start of `rest` can't be parsed.
+fn describe_malformed_argument(rest: &str) -> String {
+    let value = match lazy_regex::regex_captures!(r"^(\w+)[ \t]*=[ \t]*", rest) {
+        Some((whole, name)) => match &rest[whole.len()..] {
+            value if value.is_empty() || value.starts_with(',') => {
+                return format!("missing value for argument '{}'", name);
+            },
+            value => value,
+        },
+        None => rest,
+    };
+
+    match value.chars().next() {
+        Some('"') => format!("unbalanced quote in `{}`", value),
+        Some(',') => "empty argument".to_owned(),
+        _ => format!("expected a quoted string or a number at `{}`", value),
+    }
+}
+
+/// Replace `\"` with `"`, keeping all other backslashes. A pair of backslashes is
+/// kept as is and doesn't escape a following quote.
+fn unescape_quotes(argument: &str) -> Cow<'_, str> {
+    if !argument.contains("\\\"") {
+        return Cow::Borrowed(argument);
+    }
+
+    let mut unescaped = String::with_capacity(argument.len());
+    let mut chars = argument.chars();
+    while let Some(char) = chars.next() {
+        match (char, chars.clone().next()) {
+            ('\\', Some('"')) => unescaped.push('"'),
+            ('\\', Some('\\')) => unescaped.push_str("\\\\"),
+            (char, _) => {
+                unescaped.push(char);
+                continue;
+            },
+        }
+        chars.next();
+    }
+
+    Cow::Owned(unescaped)
+}
+
+/// Replace the escape sequences `\n`, `\t` and `\\` of an argument whose escaped
+/// quotes were already unescaped. Other backslashes are kept.
+fn unescape(argument: &str) -> String {
+    let mut unescaped = String::with_capacity(argument.len());
+    let mut chars = argument.chars();
+    while let Some(char) = chars.next() {
+        let escaped = match (char, chars.clone().next()) {
+            ('\\', Some('n')) => '\n',
+            ('\\', Some('t')) => '\t',
+            ('\\', Some('\\')) => '\\',
+            (char, _) => {
+                unescaped.push(char);
+                continue;
+            },
+        };
+        unescaped.push(escaped);
+        chars.next();
+    }
+
+    unescaped
+}
+
+#[cfg(test)]
+mod test_resolve_includes {
+    use super::resolve_includes;
+    use crate::Error;
+    use rstest::rstest;
+    use std::path::Path;
+
+    fn resolver(path: &Path) -> Result<String, Error> {
+        match path.to_str().unwrap() {
+            "a.txt" => Ok("A\nB\n".to_owned()),
+            "doc.txt" => Ok("# region x\nX\n# endregion\n".to_owned()),
+            "list.txt" => Ok("# files\na.txt\n\n  doc.txt\n".to_owned()),
+            _ => Err(Error::FileNotFound(path.to_owned())),
+        }
+    }
+
+    #[rstest]
+    #[case(r#"${include("a.txt")}"#, "A\nB")]
+    #[case("  ${include_indent(\"a.txt\")}", "  A\n  B")]
+    #[case(r#"\${include("a.txt")}"#, r#"${include("a.txt")}"#)]
+    #[case(r#"${include("missing.txt" else "a.txt")}"#, "A\nB")]
+    #[case(r#"${include_section("doc.txt", "x")}"#, "X")]
+    #[case(r#"${include_name("../b.txt")} ${self_path}"#, "../b.txt ${self_path}")]
+    #[case(r#"${include_wrap("<\"x\">\n", "\n</\\t>", "a.txt")}"#, "<\"x\">\nA\nB\n</\\t>")]
+    #[case("  ${include_wrap(\"[\", \"]\", \"a.txt\")}", "  [A\n  B]")]
+    #[case(r#"${include_name("C:\dir\\")}"#, r#"C:\dir\\"#)]
+    #[case(r#"${include_name("say \"hi\"")}"#, r#"say "hi""#)]
+    #[case(r#"${include_if("RFL_TEST_UNSET_CONDITION", "missing.txt")}"#, "")]
+    #[case(r#"  ${include("a.txt", indent="> ")}"#, "  A\n> B")]
+    #[case("  ${include_indent(\"a.txt\" , indent = \"\")}", "  A\nB")]
+    #[case(r#"\${include("a.txt", size="2")}"#, r#"${include("a.txt", size="2")}"#)]
+    #[case(r#"- ${include_repeat("a.txt", 2)}"#, "- A\n  B\n  A\n  B")]
+    #[case(r#"${include_repeat("a.txt","3")}"#, "A\nB\nA\nB\nA\nB")]
+    #[case(r#"[${include_repeat("missing.txt", 0)}]"#, "[]")]
+    #[case(r#"${include_list("list.txt")}|"#, "A\nB\n# region x\nX\n# endregion|")]
+    #[case(r#"${include_raw_bytes("a.txt")}|${include_raw_bytes("data:,a%20b")}"#, "A\nB\n|a b")]
+    #[case(r#"${include_list("list.txt", sep="\n--\t\\n\x")}|"#, "A\nB\n--\t\\n\\x# region x\nX\n# endregion|")]
+    #[case(r#"{"text": "${include_escaped("json", "a.txt")}"}"#, r#"{"text": "A\nB"}"#)]
+    #[case(r#"${include("a.txt",)} ${include_section( "doc.txt" ,"x" , )}"#, "A\nB X")]
+    #[case(r#"${include("a.txt" "b.txt")}"#, r#"${include("a.txt" "b.txt")}"#)]
+    fn should_resolve_includes_in_strings(
+        #[case] input: &str,
+        #[case] expectation: &str,
+    ) -> Result<(), Error> {
+        assert_eq!(resolve_includes(input, resolver)?, expectation);
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[case(r#"${include("a.txt", size="2")}"#, "unknown argument 'size'")]
+    #[case(r#"${include("a.txt", indent=" ", indent="")}"#, "argument 'indent' is given more than once")]
+    #[case("\n${include(indent=\" \", \"a.txt\")}", "positional argument \"a.txt\" follows named arguments")]
+    #[case(r#"${include_repeat("a.txt", "-1")}"#, "invalid count \"-1\"")]
+    #[case(r#"${include_escaped("xml", "a.txt")}"#, "unknown escape format \"xml\", expected html, json or shell")]
+    #[case(r#"${include("a.txt", sort="natural")}"#, "unknown argument 'sort'")]
+    #[case(r#"${include("a.txt", sep=",")}"#, "unknown argument 'sep'")]
+    fn should_reject_invalid_arguments(
+        #[case] input: &str,
+        #[case] expectation: &str,
+    ) {
+        match resolve_includes(input, resolver) {
+            Err(Error::InvalidArgument(_, line, message)) => {
+                assert_eq!(line, input.matches('\n').count() + 1);
+                assert_eq!(message, format!("{} in {}", expectation, input.trim_start()));
+            },
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[rstest]
+    fn should_pass_resolver_errors() {
+        match resolve_includes(r#"${include("missing.txt")}"#, resolver) {
+            Err(Error::FileNotFound(path)) => assert_eq!(path, Path::new("missing.txt")),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_parse_arguments {
+    use super::parse_arguments;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(r#""#, vec![])]
+    #[case(r#""a.txt""#, vec![(None, "a.txt")])]
+    #[case("\t\"a.txt\" ,\t\"b\\\"c\"  ", vec![(None, "a.txt"), (None, "b\"c")])]
+    #[case(r#""row.txt", 5,"#, vec![(None, "row.txt"), (None, "5")])]
+    #[case(r#""a.txt" , indent = "  " , "#, vec![(None, "a.txt"), (Some("indent"), "  ")])]
+    #[case(r#""a, b", "(x)""#, vec![(None, "a, b"), (None, "(x)")])]
+    fn should_parse_argument_lists(#[case] args: &str, #[case] expectation: Vec<(Option<&str>, &str)>) {
+        let arguments = parse_arguments(args).unwrap();
+        let actual: Vec<_> = arguments.0.iter().map(|(name, value)| (*name, value.as_ref())).collect();
+        assert_eq!(actual, expectation);
+    }
+
+    #[rstest]
+    #[case(r#""a.txt" "b.txt""#, r#"missing comma before `"b.txt"`"#)]
+    #[case(r#""a.txt" x"#, "missing comma before `x`")]
+    #[case(r#""a.txt", "b.txt"#, r#"unbalanced quote in `"b.txt`"#)]
+    #[case(r#""a.txt\""#, r#"unbalanced quote in `"a.txt\"`"#)]
+    #[case(r#""a.txt",,"#, "empty argument")]
+    #[case(r#","#, "empty argument")]
+    #[case(r#""a.txt", indent="#, "missing value for argument 'indent'")]
+    #[case(r#""a.txt", indent=, "b""#, "missing value for argument 'indent'")]
+    #[case(r#""a.txt", indent=x"#, "expected a quoted string or a number at `x`")]
+    #[case(r#"a.txt"#, "expected a quoted string or a number at `a.txt`")]
+    #[case(r#""row.txt", 5x"#, "expected a quoted string or a number at `5x`")]
+    #[case(r#""row.txt", 1.5"#, "missing comma before `.5`")]
+    fn should_describe_malformed_argument_lists(#[case] args: &str, #[case] expectation: &str) {
+        assert_eq!(parse_arguments(args).unwrap_err(), expectation);
+    }
+}
@@ -0,0 +1,187 @@
+use std::{io::{self, ErrorKind}, path::Path};
+
+use crate::Error;
+
+const UTF16_LE_BOM: [u8; 2] = [0xFF, 0xFE];
+const UTF16_BE_BOM: [u8; 2] = [0xFE, 0xFF];
+
+/// Decode the raw bytes of a file into a `String`.
+///
+/// Files starting with a UTF-16 byte order mark are decoded as UTF-16 in the
+/// indicated byte order, everything else is read as UTF-8. Invalid UTF-8 is
+/// reported as an [Error::InvalidUtf8] for the file at `path`.
+pub(crate) fn decode(path: &Path, bytes: Vec<u8>) -> Result<String, Error> {
+    decode_with(path, bytes, false)
+}
+
+/// Like [decode], but with `lossy`, invalid sequences are replaced with U+FFFD
+/// instead of failing.
+pub(crate) fn decode_with(path: &Path, bytes: Vec<u8>, lossy: bool) -> Result<String, Error> {
+    match bytes.get(0..2) {
+        Some(bom) if bom == UTF16_LE_BOM => decode_utf16(&bytes[2..], u16::from_le_bytes, lossy),
+        Some(bom) if bom == UTF16_BE_BOM => decode_utf16(&bytes[2..], u16::from_be_bytes, lossy),
+        _ => decode_utf8(path, bytes, lossy),
+    }
+}
+
+/// Decode the bytes with the encoding named by `label`, see
+/// [crate::LoaderBuilder::encoding]. A byte order mark of that encoding is removed,
+/// others are kept as part of the text.
+#[cfg(feature = "encoding")]
+pub(crate) fn decode_as(path: &Path, mut bytes: Vec<u8>, label: &str, lossy: bool) -> Result<String, Error> {
+    let encoding = encoding_rs::Encoding::for_label(label.as_bytes())
+        .ok_or_else(|| Error::UnknownEncoding(label.to_owned()))?;
+    if let Some((_, bom_length)) = encoding_rs::Encoding::for_bom(&bytes).filter(|(it, _)| *it == encoding) {
+        bytes.drain(..bom_length);
+    }
+
+    match (encoding == encoding_rs::UTF_8, lossy) {
+        (true, _) => decode_utf8(path, bytes, lossy),
+        (false, true) => Ok(encoding.decode_without_bom_handling(&bytes).0.into_owned()),
+        (false, false) => encoding
+            .decode_without_bom_handling_and_without_replacement(&bytes)
+            .map(|it| it.into_owned())
+            .ok_or_else(|| invalid_data(&format!("stream did not contain valid {}", encoding.name()))),
+    }
+}
+
+fn decode_utf8(path: &Path, bytes: Vec<u8>, lossy: bool) -> Result<String, Error> {
+    match lossy {
+        true => Ok(String::from_utf8_lossy(&bytes).into_owned()),
+        false => String::from_utf8(bytes).map_err(|e| Error::InvalidUtf8(path.to_owned(), e.utf8_error().valid_up_to())),
+    }
+}
+
+fn decode_utf16(bytes: &[u8], to_unit: fn([u8; 2]) -> u16, lossy: bool) -> Result<String, Error> {
+    if !lossy && bytes.len() % 2 != 0 {
+        return Err(invalid_data("stream did not contain valid UTF-16"));
+    }
+
+    let units = bytes
+        .chunks_exact(2)
+        .map(|chunk| to_unit([chunk[0], chunk[1]]));
+
+    if lossy {
+        let mut text: String = char::decode_utf16(units)
+            .map(|it| it.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect();
+        if bytes.len() % 2 != 0 {
+            text.push(char::REPLACEMENT_CHARACTER);
+        }
+        return Ok(text);
+    }
+
+    char::decode_utf16(units)
+        .collect::<Result<String, _>>()
+        .map_err(|_| invalid_data("stream did not contain valid UTF-16"))
+}
+
+fn invalid_data(msg: &str) -> Error {
+    Error::IOError(io::Error::new(ErrorKind::InvalidData, msg))
+}
+
+#[cfg(test)]
+mod test_decode {
+    use super::{decode, decode_with};
+    use crate::Error;
+    use rstest::rstest;
+    use std::path::Path;
+
+    #[rstest]
+    #[case(b"hello".to_vec(), "hello")]
+    #[case(vec![0xFF, 0xFE, b'h', 0, b'i', 0], "hi")]
+    #[case(vec![0xFE, 0xFF, 0, b'h', 0, b'i'], "hi")]
+    #[case(vec![0xFF, 0xFE, 0x3D, 0xD8, 0x00, 0xDE], "\u{1F600}")]
+    #[case(vec![0xFF, 0xFE], "")]
+    fn should_decode_supported_encodings(
+        #[case] input: Vec<u8>,
+        #[case] expectation: &str,
+    ) {
+        assert_eq!(decode(Path::new("file.txt"), input).unwrap(), expectation);
+    }
+
+    #[rstest]
+    #[case(vec![0xC3, 0x28])]
+    #[case(vec![0xFF, 0xFE, b'h'])]
+    #[case(vec![0xFF, 0xFE, 0x00, 0xD8])]
+    fn should_reject_invalid_input(#[case] input: Vec<u8>) {
+        assert!(decode(Path::new("file.txt"), input).is_err());
+    }
+
+    #[rstest]
+    #[case(vec![0xC3, 0x28], 0)]
+    #[case(b"abc\xFFdef".to_vec(), 3)]
+    #[case(vec![b'a', 0xE2, 0x82], 1)]
+    fn should_report_offset_of_invalid_utf8(
+        #[case] input: Vec<u8>,
+        #[case] expectation: usize,
+    ) {
+        match decode(Path::new("file.txt"), input) {
+            Err(Error::InvalidUtf8(path, offset)) => {
+                assert_eq!(path, Path::new("file.txt"));
+                assert_eq!(offset, expectation);
+            },
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[rstest]
+    #[case(vec![b'a', 0xC3, 0x28], "a\u{FFFD}(")]
+    #[case(vec![0xFF, 0xFE, b'h', 0, b'i'], "h\u{FFFD}")]
+    fn should_replace_invalid_sequences_when_lossy(
+        #[case] input: Vec<u8>,
+        #[case] expectation: &str,
+    ) {
+        assert_eq!(decode_with(Path::new("file.txt"), input, true).unwrap(), expectation);
+    }
+}
+
+#[cfg(all(test, feature = "encoding"))]
+mod test_decode_as {
+    use super::decode_as;
+    use crate::Error;
+    use rstest::rstest;
+    use std::path::Path;
+
+    #[rstest]
+    #[case(b"caf\xE9 \x80".to_vec(), "windows-1252", "caf\u{E9} \u{20AC}")]
+    #[case(b"caf\xE9".to_vec(), " Latin1 ", "caf\u{E9}")]
+    #[case(vec![0xFF, 0xFE, b'h', 0], "utf-16le", "h")]
+    #[case(vec![b'h', 0], "utf-16", "h")]
+    #[case(vec![0xFE, 0xFF, 0, b'h', 0, b'i'], "utf-16be", "hi")]
+    #[case(vec![0xFE, 0xFF, 0, b'h'], "utf-16le", "\u{FFFE}\u{6800}")]
+    #[case(vec![0xEF, 0xBB, 0xBF, b'h'], "utf-8", "h")]
+    #[case(vec![0x82, 0xA0], "shift_jis", "\u{3042}")]
+    fn should_decode_named_encodings(
+        #[case] input: Vec<u8>,
+        #[case] label: &str,
+        #[case] expectation: &str,
+    ) {
+        assert_eq!(decode_as(Path::new("file.txt"), input, label, false).unwrap(), expectation);
+    }
+
+    #[rstest]
+    #[case(vec![0x00, 0xD8, b'h', 0], "utf-16le", true, Some("\u{FFFD}h"))]
+    #[case(vec![0x00, 0xD8, b'h', 0], "utf-16le", false, None)]
+    #[case(vec![b'a', 0xC3, 0x28], "utf-8", true, Some("a\u{FFFD}("))]
+    fn should_handle_invalid_sequences(
+        #[case] input: Vec<u8>,
+        #[case] label: &str,
+        #[case] lossy: bool,
+        #[case] expectation: Option<&str>,
+    ) {
+        let result = decode_as(Path::new("file.txt"), input, label, lossy);
+        match expectation {
+            Some(expectation) => assert_eq!(result.unwrap(), expectation),
+            None => assert!(matches!(result, Err(Error::IOError(_)))),
+        }
+    }
+
+    #[rstest]
+    fn should_reject_unknown_labels() {
+        match decode_as(Path::new("file.txt"), vec![], "klingon", false) {
+            Err(Error::UnknownEncoding(label)) => assert_eq!(label, "klingon"),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
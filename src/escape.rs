@@ -0,0 +1,109 @@
+use std::str::FromStr;
+
+/// The format the content of an `include_escaped` directive is escaped for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum EscapeFormat {
+    /// Replace `&`, `<`, `>`, `"` and `'` with character references, for text and
+    /// attribute values in HTML and XML.
+    Html,
+
+    /// Escape quotes, backslashes and control characters, for the inside of a JSON
+    /// string.
+    Json,
+
+    /// Put the content in single quotes, for a single word in a POSIX shell command.
+    Shell,
+}
+
+impl FromStr for EscapeFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "html" => Ok(EscapeFormat::Html),
+            "json" => Ok(EscapeFormat::Json),
+            "shell" => Ok(EscapeFormat::Shell),
+            _ => Err(format!("unknown escape format \"{}\", expected html, json or shell", value)),
+        }
+    }
+}
+
+impl EscapeFormat {
+    /// Escape the text split into `segments`, as if they were a single text.
+    pub(crate) fn escape(self, segments: &mut [&mut String]) {
+        for segment in segments.iter_mut() {
+            **segment = match self {
+                EscapeFormat::Html => escape_html(segment),
+                EscapeFormat::Json => escape_json(segment),
+                EscapeFormat::Shell => segment.replace('\'', r"'\''"),
+            };
+        }
+        if self == EscapeFormat::Shell {
+            if let Some(first) = segments.first_mut() {
+                first.insert(0, '\'');
+            }
+            if let Some(last) = segments.last_mut() {
+                last.push('\'');
+            }
+        }
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for char in text.chars() {
+        match char {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            char => escaped.push(char),
+        }
+    }
+
+    escaped
+}
+
+fn escape_json(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for char in text.chars() {
+        match char {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            char if (char as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", char as u32)),
+            char => escaped.push(char),
+        }
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod test_escape {
+    use super::EscapeFormat;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(EscapeFormat::Html, "<a href=\"x\">Tom & Jerry's</a>", "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&#39;s&lt;/a&gt;")]
+    #[case(EscapeFormat::Json, "say \"hi\"\n\tC:\\ \u{1}ä", "say \\\"hi\\\"\\n\\tC:\\\\ \\u0001ä")]
+    #[case(EscapeFormat::Shell, "it's $HOME", "'it'\\''s $HOME'")]
+    #[case(EscapeFormat::Shell, "", "''")]
+    fn should_escape_text(#[case] format: EscapeFormat, #[case] text: &str, #[case] expectation: &str) {
+        let mut text = text.to_owned();
+        format.escape(&mut [&mut text]);
+
+        assert_eq!(text, expectation);
+    }
+
+    #[rstest]
+    fn should_escape_segments_as_one_text() {
+        let (mut a, mut b) = ("a'".to_owned(), "<b>".to_owned());
+        EscapeFormat::Shell.escape(&mut [&mut a, &mut b]);
+
+        assert_eq!(a + &b, "'a'\\''<b>'");
+    }
+}
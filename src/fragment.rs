@@ -0,0 +1,37 @@
+use std::path::{Path, PathBuf};
+
+/// A piece of the output of a load, along with the file it originates from.
+///
+/// Concatenating the text of all fragments returned by [crate::resolve_fragments]
+/// yields the same text as [crate::load_file_recursively].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fragment {
+    path: PathBuf,
+    text: String,
+}
+
+impl Fragment {
+    pub(crate) fn new<P: AsRef<Path>, S: Into<String>>(path: P, text: S) -> Self {
+        Self {
+            path: path.as_ref().to_owned(),
+            text: text.into(),
+        }
+    }
+
+    /// The path of the file this fragment originates from, as it was referenced.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn into_text(self) -> String {
+        self.text
+    }
+
+    pub(crate) fn text_mut(&mut self) -> &mut String {
+        &mut self.text
+    }
+}
@@ -0,0 +1,105 @@
+use std::path::PathBuf;
+
+use crate::{canonical_path::CanonicalPath, load_state::LoadState};
+
+/// An entry of the resolution stack, linking to the entry of the including file
+/// and to the state of the load it belongs to.
+///
+/// Every branch of the include tree only sees its own ancestors, so branches can
+/// be resolved independently of each other, e.g. on different threads.
+#[derive(Debug)]
+pub(crate) struct Frame<'a> {
+    path: CanonicalPath,
+    parent: Option<&'a Frame<'a>>,
+    depth: usize,
+    state: &'a LoadState,
+}
+
+impl<'a> Frame<'a> {
+    pub(crate) fn new(path: CanonicalPath, state: &'a LoadState, parent: Option<&'a Frame<'a>>) -> Self {
+        let depth = parent.map(|it| it.depth + 1).unwrap_or_default();
+
+        Self { path, parent, depth, state }
+    }
+
+    pub(crate) fn path(&self) -> &CanonicalPath {
+        &self.path
+    }
+
+    pub(crate) fn state(&self) -> &'a LoadState {
+        self.state
+    }
+
+    /// The number of ancestors of this frame.
+    pub(crate) fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Iterate over the paths of this frame and all its ancestors, innermost first.
+    pub(crate) fn paths(&self) -> impl Iterator<Item = &CanonicalPath> {
+        std::iter::successors(Some(self), |it| it.parent).map(|it| &it.path)
+    }
+
+    /// The path of the outermost frame, i.e. the loaded root file.
+    pub(crate) fn root(&self) -> &CanonicalPath {
+        self.paths().last().unwrap()
+    }
+
+    pub(crate) fn contains(&self, path: &CanonicalPath) -> bool {
+        self.paths().any(|it| it == path)
+    }
+
+    /// The canonical paths of the frames from the one of `path` down to this one,
+    /// in include order and rotated to start with the smallest, so that the same
+    /// cycle yields the same paths no matter where it was entered.
+    pub(crate) fn cycle(&self, path: &CanonicalPath) -> Box<[PathBuf]> {
+        let length = self.paths().position(|it| it == path).map_or(0, |it| it + 1);
+        let mut members: Vec<PathBuf> = self.paths().take(length).map(|it| it.as_ref().to_owned()).collect();
+        members.reverse();
+        if let Some((smallest, _)) = members.iter().enumerate().min_by_key(|(_, it)| *it) {
+            members.rotate_left(smallest);
+        }
+
+        members.into()
+    }
+}
+
+#[cfg(test)]
+mod test_frame {
+    use super::Frame;
+    use crate::{canonical_path::CanonicalPath, load_state::LoadState};
+    use rstest::rstest;
+    use std::path::PathBuf;
+
+    #[rstest]
+    fn should_only_contain_ancestors() {
+        let state = LoadState::default();
+        let root = Frame::new(CanonicalPath::_new("root", "/root"), &state, None);
+        let left = Frame::new(CanonicalPath::_new("left", "/left"), &state, Some(&root));
+        let right = Frame::new(CanonicalPath::_new("right", "/right"), &state, Some(&root));
+
+        assert!(left.contains(&CanonicalPath::_new("root", "/root")));
+        assert!(left.contains(&CanonicalPath::_new("left", "/left")));
+        assert!(!left.contains(&CanonicalPath::_new("right", "/right")));
+        assert!(!root.contains(right.path()));
+        assert_eq!(root.depth(), 0);
+        assert_eq!(left.depth(), 1);
+        assert_eq!(left.root().source(), std::path::Path::new("root"));
+
+        let paths: Vec<_> = left.paths().map(|it| it.source().to_owned()).collect();
+        assert_eq!(paths, vec![std::path::PathBuf::from("left"), "root".into()]);
+    }
+
+    #[rstest]
+    fn should_rotate_cycles_to_start_with_smallest_path() {
+        let state = LoadState::default();
+        let root = Frame::new(CanonicalPath::_new("root", "/root"), &state, None);
+        let c = Frame::new(CanonicalPath::_new("c", "/c"), &state, Some(&root));
+        let a = Frame::new(CanonicalPath::_new("a", "/a"), &state, Some(&c));
+        let b = Frame::new(CanonicalPath::_new("./b", "/b"), &state, Some(&a));
+
+        assert_eq!(&*b.cycle(&CanonicalPath::_new("c", "/c")), [PathBuf::from("/a"), "/b".into(), "/c".into()]);
+        assert_eq!(&*a.cycle(&CanonicalPath::_new("c", "/c")), [PathBuf::from("/a"), "/c".into()]);
+        assert_eq!(&*a.cycle(&CanonicalPath::_new("a", "/a")), [PathBuf::from("/a")]);
+    }
+}
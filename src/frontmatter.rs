@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+
+use crate::Error;
+
+/// Parse the frontmatter at the start of the text, a block of `key: value` lines
+/// between two lines consisting of `---`. Values may be quoted, other lines are
+/// ignored. Returns `None` if the text doesn't start with frontmatter.
+pub(crate) fn parse(text: &str) -> Option<HashMap<String, String>> {
+    let mut lines = text.lines();
+    if lines.next()?.trim_end() != "---" {
+        return None;
+    }
+
+    let mut fields = HashMap::new();
+    for line in lines {
+        if line.trim_end() == "---" {
+            return Some(fields);
+        }
+        if let Some((key, value)) = line.split_once(':') {
+            fields.insert(key.trim().to_owned(), unquote(value.trim()).to_owned());
+        }
+    }
+
+    None
+}
+
+/// Evaluate a condition like `key == value` or `key != value` against the fields
+/// of a frontmatter. A missing key never equals a value.
+pub(crate) fn matches(condition: &str, fields: &HashMap<String, String>) -> Result<bool, Error> {
+    let (key, value, equal) = match (condition.split_once("=="), condition.split_once("!=")) {
+        (Some((key, value)), None) => (key, value, true),
+        (None, Some((key, value))) => (key, value, false),
+        _ => return Err(Error::InvalidCondition(condition.to_owned())),
+    };
+    let key = key.trim();
+    if key.is_empty() {
+        return Err(Error::InvalidCondition(condition.to_owned()));
+    }
+
+    let is_equal = fields.get(key).is_some_and(|it| it == unquote(value.trim()));
+    Ok(is_equal == equal)
+}
+
+fn unquote(value: &str) -> &str {
+    ['"', '\'']
+        .iter()
+        .find_map(|quote| value.strip_prefix(*quote)?.strip_suffix(*quote))
+        .unwrap_or(value)
+}
+
+#[cfg(test)]
+mod test_frontmatter {
+    use super::{matches, parse};
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("---\ndraft: false\ntitle: \"Hello: world\"\n---\nbody", Some(vec![("draft", "false"), ("title", "Hello: world")]))]
+    #[case("---\r\ntag: 'x'\r\n---\r\n", Some(vec![("tag", "x")]))]
+    #[case("---\n---\n", Some(vec![]))]
+    #[case("body\n---\ndraft: true\n---\n", None)]
+    #[case("---\ndraft: true\n", None)]
+    fn should_parse_frontmatter(
+        #[case] text: &str,
+        #[case] expectation: Option<Vec<(&str, &str)>>,
+    ) {
+        let expectation = expectation.map(|fields| {
+            fields.into_iter().map(|(key, value)| (key.to_owned(), value.to_owned())).collect()
+        });
+        assert_eq!(parse(text), expectation);
+    }
+
+    #[rstest]
+    #[case("draft == false", true)]
+    #[case("draft==\"false\"", true)]
+    #[case("draft == true", false)]
+    #[case("draft != true", true)]
+    #[case("missing == false", false)]
+    #[case("missing != false", true)]
+    fn should_evaluate_conditions(#[case] condition: &str, #[case] expectation: bool) {
+        let fields = parse("---\ndraft: false\n---\n").unwrap();
+        assert_eq!(matches(condition, &fields).unwrap(), expectation);
+    }
+
+    #[rstest]
+    #[case("draft")]
+    #[case("== false")]
+    #[case("a == b != c")]
+    fn should_reject_invalid_conditions(#[case] condition: &str) {
+        assert!(matches(condition, &Default::default()).is_err());
+    }
+}
@@ -0,0 +1,153 @@
+use std::{cmp::Ordering, fs, path::{Path, PathBuf}, str::FromStr};
+
+use crate::Error;
+
+/// The order of the files matching a glob pattern, set with the `sort` argument of
+/// a glob directive.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum GlobOrder {
+    /// Ordered by their paths, character by character.
+    #[default]
+    Lexical,
+
+    /// Ordered by their paths with runs of digits compared by their numeric value,
+    /// so `part2` comes before `part10`.
+    Natural,
+
+    /// Ordered by their modification time, oldest first, with ties and files
+    /// without a modification time ordered lexically.
+    Modified,
+
+    /// In the order the file system lists them.
+    Unsorted,
+}
+
+impl FromStr for GlobOrder {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "lexical" => Ok(GlobOrder::Lexical),
+            "natural" => Ok(GlobOrder::Natural),
+            "mtime" => Ok(GlobOrder::Modified),
+            "none" => Ok(GlobOrder::Unsorted),
+            _ => Err(format!("unknown sort order \"{}\", expected lexical, natural, mtime or none", value)),
+        }
+    }
+}
+
+/// Find the files matching the glob pattern, in the given order. Directories
+/// matching the pattern are skipped.
+pub(crate) fn expand(pattern: &Path, order: GlobOrder) -> Result<Vec<PathBuf>, Error> {
+    let text = pattern.to_string_lossy();
+    let entries = glob::glob(&text)
+        .map_err(|e| Error::InvalidGlob(text.clone().into_owned(), e.msg.to_owned()))?;
+
+    let mut paths = vec![];
+    for entry in entries {
+        let path = entry.map_err(|e| Error::ReadFailed(e.path().to_owned(), e.into()))?;
+        if !path.is_dir() {
+            paths.push(path);
+        }
+    }
+    match order {
+        GlobOrder::Lexical => paths.sort(),
+        GlobOrder::Natural => paths.sort_by(|a, b| natural_cmp(&a.to_string_lossy(), &b.to_string_lossy())),
+        GlobOrder::Modified => {
+            paths.sort();
+            paths.sort_by_cached_key(|it| fs::metadata(it).and_then(|it| it.modified()).ok());
+        },
+        GlobOrder::Unsorted => {},
+    }
+
+    Ok(paths)
+}
+
+/// Compare two strings like [str::cmp], except that runs of ASCII digits are
+/// compared by their numeric value. Runs of equal value, like `7` and `007`, are
+/// ordered by their length.
+pub(crate) fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a, b);
+    loop {
+        let (Some(first_a), Some(first_b)) = (a.chars().next(), b.chars().next()) else {
+            return a.len().cmp(&b.len());
+        };
+        if !(first_a.is_ascii_digit() && first_b.is_ascii_digit()) {
+            match first_a.cmp(&first_b) {
+                Ordering::Equal => {
+                    a = &a[first_a.len_utf8()..];
+                    b = &b[first_b.len_utf8()..];
+                    continue;
+                },
+                ordering => return ordering,
+            }
+        }
+
+        let digits_a = a.find(|it: char| !it.is_ascii_digit()).unwrap_or(a.len());
+        let digits_b = b.find(|it: char| !it.is_ascii_digit()).unwrap_or(b.len());
+        let (number_a, number_b) = (a[..digits_a].trim_start_matches('0'), b[..digits_b].trim_start_matches('0'));
+        let ordering = number_a
+            .len()
+            .cmp(&number_b.len())
+            .then_with(|| number_a.cmp(number_b))
+            .then_with(|| digits_a.cmp(&digits_b));
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+        a = &a[digits_a..];
+        b = &b[digits_b..];
+    }
+}
+
+#[cfg(test)]
+mod test_expand {
+    use super::{expand, natural_cmp, GlobOrder};
+    use crate::Error;
+    use std::{cmp::Ordering, fs::File, time::{Duration, SystemTime}};
+    use rstest::rstest;
+    use temp_dir::TempDir;
+
+    #[rstest]
+    fn should_find_matching_files_in_order() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::create_dir(dir.child("c.txt"))?;
+        for name in ["b.txt", "a.txt", "a.md"] {
+            std::fs::write(dir.child(name), [])?;
+        }
+
+        assert_eq!(expand(&dir.child("*.txt"), GlobOrder::Lexical)?, vec![dir.child("a.txt"), dir.child("b.txt")]);
+        assert_eq!(expand(&dir.child("*.csv"), GlobOrder::Lexical)?, Vec::<std::path::PathBuf>::new());
+        assert!(matches!(expand(&dir.child("***"), GlobOrder::Lexical), Err(Error::InvalidGlob(..))));
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[case(GlobOrder::Lexical, ["part1.txt", "part10.txt", "part2.txt"])]
+    #[case(GlobOrder::Natural, ["part1.txt", "part2.txt", "part10.txt"])]
+    #[case(GlobOrder::Modified, ["part10.txt", "part2.txt", "part1.txt"])]
+    fn should_sort_matching_files(#[case] order: GlobOrder, #[case] expectation: [&str; 3]) -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        let now = SystemTime::now();
+        for (name, age) in [("part2.txt", 2), ("part10.txt", 3), ("part1.txt", 1)] {
+            File::create(dir.child(name))?.set_modified(now - Duration::from_secs(age * 60))?;
+        }
+
+        assert_eq!(expand(&dir.child("part*.txt"), order)?, expectation.map(|it| dir.child(it)));
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[case("part2", "part10", Ordering::Less)]
+    #[case("part10", "part10", Ordering::Equal)]
+    #[case("a10b2", "a10b10", Ordering::Less)]
+    #[case("v007", "v7", Ordering::Greater)]
+    #[case("v08", "v7", Ordering::Greater)]
+    #[case("x", "x1", Ordering::Less)]
+    #[case("b1", "a2", Ordering::Greater)]
+    #[case("1a", "a", Ordering::Less)]
+    fn should_compare_numbers_naturally(#[case] a: &str, #[case] b: &str, #[case] expectation: Ordering) {
+        assert_eq!(natural_cmp(a, b), expectation);
+    }
+}
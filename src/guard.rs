@@ -0,0 +1,27 @@
+/// Find the `${guard("<id>")}` directive declaring the include guard of a file,
+/// which has to precede everything but whitespace in the file. Returns the id and
+/// the text following the directive, without the rest of its line if that's blank.
+pub(crate) fn find(text: &str) -> Option<(&str, &str)> {
+    let (whole, id) = lazy_regex::regex_captures!(r#"^\s*\$\{guard[ \t]*\([ \t]*"([^"\\\n]+)"[ \t]*\)\}(?:[ \t]*\r?\n)?"#, text)?;
+
+    Some((id, &text[whole.len()..]))
+}
+
+#[cfg(test)]
+mod test_find {
+    use super::find;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("${guard(\"A\")}\ncontent", Some(("A", "content")))]
+    #[case("\n  ${guard ( \"HEADER_H\" )}  \r\n\ncontent\n", Some(("HEADER_H", "\ncontent\n")))]
+    #[case("${guard(\"A\")} content", Some(("A", " content")))]
+    #[case("${guard(\"A\")}", Some(("A", "")))]
+    #[case("content\n${guard(\"A\")}", None)]
+    #[case("\\${guard(\"A\")}", None)]
+    #[case("${guard(\"\")}", None)]
+    #[case("${guard(A)}", None)]
+    fn should_find_guards(#[case] text: &str, #[case] expectation: Option<(&str, &str)>) {
+        assert_eq!(find(text), expectation);
+    }
+}
@@ -0,0 +1,78 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
+use sha2::{Digest, Sha256};
+
+/// The text of a load along with hashes identifying it, see
+/// [crate::Loader::load_with_hashes].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoadResult {
+    text: String,
+    content_hash: String,
+    input_hash: String,
+}
+
+impl LoadResult {
+    pub(crate) fn new(text: String, inputs: &BTreeMap<PathBuf, Vec<u8>>) -> Self {
+        let content_hash = hex(Sha256::digest(text.as_bytes()));
+
+        let mut hasher = Sha256::new();
+        for (path, digest) in inputs {
+            hasher.update(path.to_string_lossy().as_bytes());
+            hasher.update([0]);
+            hasher.update(digest);
+        }
+        let input_hash = hex(hasher.finalize());
+
+        Self { text, content_hash, input_hash }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn into_text(self) -> String {
+        self.text
+    }
+
+    /// The hex encoded SHA-256 hash of the loaded text.
+    pub fn content_hash(&self) -> &str {
+        &self.content_hash
+    }
+
+    /// The hex encoded SHA-256 hash of the files read by the load, i.e. of their
+    /// paths relative to the loaded file's directory and their raw content. It
+    /// doesn't depend on the order the files were read in, or on where the files
+    /// are located as a whole.
+    pub fn input_hash(&self) -> &str {
+        &self.input_hash
+    }
+}
+
+/// The SHA-256 hash of a file's raw content, for recording it as an input.
+pub(crate) fn digest(content: &[u8]) -> Vec<u8> {
+    Sha256::digest(content).to_vec()
+}
+
+fn hex(digest: impl AsRef<[u8]>) -> String {
+    digest
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[cfg(test)]
+mod test_load_result {
+    use super::{digest, LoadResult};
+    use rstest::rstest;
+    use std::{collections::BTreeMap, path::PathBuf};
+
+    #[rstest]
+    fn should_hash_text_and_inputs() {
+        let inputs = BTreeMap::from([(PathBuf::from("a.txt"), digest(b"hello"))]);
+        let result = LoadResult::new("hello".to_owned(), &inputs);
+
+        assert_eq!(result.content_hash(), "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824");
+        assert_ne!(result.input_hash(), LoadResult::new("hello".to_owned(), &BTreeMap::new()).input_hash());
+    }
+}
@@ -0,0 +1,179 @@
+use std::{io::{self, ErrorKind, Read}, path::{Path, PathBuf}, time::Duration};
+
+use url::Url;
+
+use crate::Error;
+
+/// How long to wait for a remote file unless configured otherwise.
+pub(crate) const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Whether the path is an HTTP or HTTPS URL.
+pub(crate) fn is_url(path: &Path) -> bool {
+    let path = path.to_string_lossy();
+    path.starts_with("http://") || path.starts_with("https://")
+}
+
+/// Resolve `path` as referenced from the file at the URL `base`.
+pub(crate) fn join(base: &Path, path: &str) -> PathBuf {
+    Url::parse(&base.to_string_lossy())
+        .and_then(|base| base.join(path))
+        .map(|it| PathBuf::from(it.as_str()))
+        .unwrap_or_else(|_| PathBuf::from(path))
+}
+
+/// Normalize a URL, so different spellings of the same URL are considered equal.
+pub(crate) fn normalize(url: &Path) -> io::Result<PathBuf> {
+    Url::parse(&url.to_string_lossy())
+        .map(|it| PathBuf::from(it.as_str()))
+        .map_err(|e| io::Error::new(ErrorKind::InvalidInput, e))
+}
+
+/// Fetch the content at the URL, which must respond with status 200, reading at
+/// most `limit` bytes of it.
+pub(crate) fn fetch(url: &Path, timeout: Duration, limit: u64) -> Result<Vec<u8>, Error> {
+    let url = url.to_string_lossy().into_owned();
+    let agent = ureq::AgentBuilder::new().timeout(timeout).build();
+
+    let response = match agent.get(&url).call() {
+        Ok(response) if response.status() == 200 => response,
+        Ok(response) => return Err(Error::HttpStatus(url, response.status())),
+        Err(ureq::Error::Status(status, _)) => return Err(Error::HttpStatus(url, status)),
+        Err(ureq::Error::Transport(transport)) if is_timeout(&transport) => return Err(Error::HttpTimeout(url)),
+        Err(ureq::Error::Transport(transport)) => return Err(Error::HttpTransport(url, transport.to_string())),
+    };
+
+    let mut bytes = vec![];
+    response.into_reader().take(limit).read_to_end(&mut bytes).map_err(|e| match e.kind() {
+        ErrorKind::TimedOut | ErrorKind::WouldBlock => Error::HttpTimeout(url),
+        _ => Error::IOError(e),
+    })?;
+
+    Ok(bytes)
+}
+
+fn is_timeout(transport: &ureq::Transport) -> bool {
+    std::error::Error::source(transport)
+        .and_then(|it| it.downcast_ref::<io::Error>())
+        .is_some_and(|it| matches!(it.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock))
+}
+
+#[cfg(test)]
+mod test_http {
+    use crate::{Error, Loader, LoaderBuilder};
+    use rstest::rstest;
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+        path::{Path, PathBuf},
+        time::Duration,
+    };
+
+    /// Serve the given paths with status and body on a local port, answering
+    /// requests for other paths with 404. Returns the base URL.
+    fn serve(routes: Vec<(&'static str, u16, &'static str)>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request = String::new();
+                let mut reader = BufReader::new(&stream);
+                reader.read_line(&mut request).unwrap();
+                while reader.read_line(&mut String::new()).unwrap() > 2 {}
+
+                let path = request.split_whitespace().nth(1).unwrap_or_default();
+                if path == "/slow.txt" {
+                    std::thread::sleep(Duration::from_millis(500));
+                }
+                let (status, body) = routes
+                    .iter()
+                    .find(|(route, _, _)| *route == path)
+                    .map(|(_, status, body)| (*status, *body))
+                    .unwrap_or((404, ""));
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 {} X\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body,
+                );
+            }
+        });
+
+        base
+    }
+
+    #[rstest]
+    fn should_include_remote_files() -> Result<(), Error> {
+        let base = serve(vec![
+            ("/dir/base.txt", 200, "base ${include(\"part.txt\")} ${include(\"../other/./end.txt\")}"),
+            ("/dir/part.txt", 200, "part"),
+            ("/other/end.txt", 200, "end"),
+        ]);
+        let dir = temp_dir::TempDir::new()?;
+        std::fs::write(dir.child("start.txt"), format!("${{include(\"{}/dir/base.txt\")}}", base))?;
+
+        let loader = LoaderBuilder::new().allow_http(true).build();
+        assert_eq!(loader.load_file_recursively(dir.child("start.txt"))?, "base part end");
+
+        match Loader::new().load_file_recursively(dir.child("start.txt")) {
+            Err(Error::FileNotFound(path)) => assert_eq!(path, PathBuf::from(format!("{}/dir/base.txt", base))),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_detect_remote_cycles() -> Result<(), Error> {
+        let base = serve(vec![
+            ("/a.txt", 200, "${include(\"dir/../b.txt\")}"),
+            ("/b.txt", 200, "${include(\"a.txt\")}"),
+        ]);
+
+        let loader = LoaderBuilder::new().allow_http(true).build();
+        match loader.load_file_recursively(format!("{}/a.txt", base)) {
+            Err(Error::CyclicDependency { from, to, .. }) => {
+                assert_eq!(from, Path::new(&format!("{}/b.txt", base)));
+                assert_eq!(to, Path::new(&format!("{}/a.txt", base)));
+            },
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_report_http_failures() {
+        let base = serve(vec![("/slow.txt", 200, "slow"), ("/error.txt", 500, "")]);
+        let loader = LoaderBuilder::new()
+            .allow_http(true)
+            .http_timeout(Duration::from_millis(100))
+            .build();
+
+        match loader.load_file_recursively(format!("{}/missing.txt", base)) {
+            Err(Error::HttpStatus(url, 404)) => assert_eq!(url, format!("{}/missing.txt", base)),
+            other => panic!("unexpected result {:?}", other),
+        }
+        match loader.load_file_recursively(format!("{}/error.txt", base)) {
+            Err(Error::HttpStatus(_, 500)) => {},
+            other => panic!("unexpected result {:?}", other),
+        }
+        match loader.load_file_recursively(format!("{}/slow.txt", base)) {
+            Err(Error::HttpTimeout(url)) => assert_eq!(url, format!("{}/slow.txt", base)),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[rstest]
+    fn should_limit_the_size_of_remote_files() {
+        let base = serve(vec![("/large.txt", 200, "x".repeat(10_000).leak())]);
+        let loader = LoaderBuilder::new().allow_http(true).max_file_bytes(100).build();
+
+        match loader.load_file_recursively(format!("{}/large.txt", base)) {
+            Err(Error::FileTooLarge(path, 100)) => assert_eq!(path, PathBuf::from(format!("{}/large.txt", base))),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
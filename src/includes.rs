@@ -1,12 +1,107 @@
-use crate::Error;
+use crate::{escape::EscapeFormat, EscapedOutput, Error, Fragment, IndentMode};
 use std::{ops::Range, path::{PathBuf, Path}};
+#[cfg(feature = "glob")]
+use crate::glob_files::GlobOrder;
 
+/// What an include produces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IncludeKind {
+    /// The recursively loaded content of the file at the path.
+    File,
+    /// The path itself.
+    Name,
+    /// The value of the environment variable named by the path, or the default.
+    Env,
+    /// The standard output of the shell command given as the path.
+    Command,
+    /// The decoded content of the `data:` URI given as the path.
+    Data,
+    /// The content of every file listed in the file at the path, each recursively
+    /// loaded.
+    List,
+    /// The content of the file or `data:` URI at the path as it is, spliced in as
+    /// bytes by [crate::Loader::load_bytes_recursively].
+    RawBytes,
+    /// The content of every file matching the glob pattern given as the path, each
+    /// recursively loaded, optionally filtered by their frontmatter.
+    #[cfg(feature = "glob")]
+    Glob,
+}
+
+impl IncludeKind {
+    /// Whether the include produces the content of several files, one after the other.
+    pub fn is_multi_file(self) -> bool {
+        match self {
+            IncludeKind::List => true,
+            #[cfg(feature = "glob")]
+            IncludeKind::Glob => true,
+            _ => false,
+        }
+    }
+}
+
+/// A text being assembled by replacing includes, see [Include::replace].
 #[derive(Debug)]
+pub struct Segments {
+    /// The text up to the last include replaced.
+    head: String,
+    /// The texts following `head`, in reverse order.
+    reversed_tail: Vec<String>,
+}
+
+impl Segments {
+    pub fn new(text: String) -> Self {
+        Self { head: text, reversed_tail: vec![] }
+    }
+
+    /// Concatenate the segments into a single string.
+    pub fn into_string(self) -> String {
+        let Segments { mut head, reversed_tail } = self;
+        head.reserve(reversed_tail.iter().map(String::len).sum());
+        for segment in reversed_tail.iter().rev() {
+            head.push_str(segment);
+        }
+
+        head
+    }
+}
+
+/// The largest text an `include_repeat` may produce, in bytes.
+pub(crate) const MAX_REPEATED_BYTES: usize = 1 << 30;
+
+#[derive(Debug, Clone)]
 pub struct Include {
+    kind: IncludeKind,
     path: PathBuf,
+    fallback: Option<PathBuf>,
     backslashes: Range<usize>,
     range: Range<usize>,
     indentation: Option<String>,
+    /// Align the produced text with the output in front of the directive on its
+    /// line, normalized by the mode, instead of using `indentation`.
+    align: Option<IndentMode>,
+    wrapping: Option<(String, String)>,
+    annotation: Option<(String, String)>,
+    dedent: bool,
+    /// The first line number and the minimum width of line numbers to prefix lines with.
+    numbering: Option<(usize, usize)>,
+    repeat: Option<usize>,
+    section: Option<String>,
+    default: Option<String>,
+    condition: Option<String>,
+    if_newer: bool,
+    escape: Option<EscapeFormat>,
+    /// The list file and line this include was listed on, for an entry of an
+    /// [IncludeKind::List] include.
+    list_entry: Option<(PathBuf, usize)>,
+    /// The text between the files of a multi-file include, a line break if `None`.
+    separator: Option<String>,
+    #[cfg(feature = "glob")]
+    filter: Option<String>,
+    #[cfg(feature = "glob")]
+    glob_order: GlobOrder,
+    expression: String,
+    line: usize,
 }
 
 impl Include {
@@ -17,51 +112,570 @@ impl Include {
         indentation: Option<String>,
     ) -> Self {
         Include {
+            kind: IncludeKind::File,
             path: path.as_ref().to_owned(),
+            fallback: None,
             backslashes,
             range,
             indentation,
+            align: None,
+            wrapping: None,
+            annotation: None,
+            dedent: false,
+            numbering: None,
+            repeat: None,
+            section: None,
+            default: None,
+            condition: None,
+            if_newer: false,
+            escape: None,
+            list_entry: None,
+            separator: None,
+            #[cfg(feature = "glob")]
+            filter: None,
+            #[cfg(feature = "glob")]
+            glob_order: GlobOrder::default(),
+            expression: String::new(),
+            line: 0,
         }
     }
 
+    /// Set the directive text this include was created from, and the 1-based
+    /// line it starts on.
+    pub fn with_expression<S: Into<String>>(mut self, expression: S, line: usize) -> Self {
+        self.expression = expression.into();
+        self.line = line;
+        self
+    }
+
+    /// Surround the produced text with a prefix and suffix, after stripping its
+    /// trailing newline and before applying indentation.
+    pub fn with_wrapping<S: Into<String>, T: Into<String>>(mut self, prefix: S, suffix: T) -> Self {
+        self.wrapping = Some((prefix.into(), suffix.into()));
+        self
+    }
+
+    /// Replace the indentation applied to every line of the produced text but the first.
+    pub fn with_indentation<S: Into<String>>(mut self, indentation: S) -> Self {
+        self.indentation = Some(indentation.into());
+        self.align = None;
+        self
+    }
+
+    /// Indent every line of the produced text but the first to align with the output
+    /// in front of the directive on its line, see [OutputLine].
+    pub fn with_alignment(mut self, mode: IndentMode) -> Self {
+        self.align = Some(mode);
+        self
+    }
+
+    /// Put the given lines before and after the produced text, after wrapping it
+    /// and before applying indentation.
+    pub fn with_annotation<S: Into<String>, T: Into<String>>(mut self, begin: S, end: T) -> Self {
+        self.annotation = Some((begin.into(), end.into()));
+        self
+    }
+
+    /// Strip the leading whitespace common to all lines from the produced text,
+    /// before wrapping and indenting it.
+    pub fn with_dedent(mut self, dedent: bool) -> Self {
+        self.dedent = dedent;
+        self
+    }
+
+    /// Prefix every line of the produced text with its number, counting from
+    /// `start` and right-aligned to at least `width` characters, before wrapping
+    /// and indenting it.
+    pub fn with_numbering(mut self, start: usize, width: usize) -> Self {
+        self.numbering = Some((start, width));
+        self
+    }
+
+    /// Produce the text the given number of times, separated by line breaks, before
+    /// dedenting it. A count of zero produces nothing.
+    pub fn with_repeat(mut self, count: usize) -> Self {
+        self.repeat = Some(count);
+        self
+    }
+
+    /// Only include the section with the given name of the file.
+    pub fn with_section<S: Into<String>>(mut self, section: S) -> Self {
+        self.section = Some(section.into());
+        self
+    }
+
+    /// Set the path to load instead if the file at the path doesn't exist.
+    pub fn with_fallback<P: AsRef<Path>>(mut self, fallback: P) -> Self {
+        self.fallback = Some(fallback.as_ref().to_owned());
+        self
+    }
+
+    /// Set the value used for an [IncludeKind::Env] include if the variable isn't set.
+    pub fn with_default<S: Into<String>>(mut self, default: S) -> Self {
+        self.default = Some(default.into());
+        self
+    }
+
+    /// Only produce the file's content if the environment variable with the given
+    /// name is set to a non-empty value, and nothing otherwise.
+    pub fn with_condition<S: Into<String>>(mut self, condition: S) -> Self {
+        self.condition = Some(condition.into());
+        self
+    }
+
+    /// Only produce the file's content if it was modified after the loaded root file,
+    /// and nothing otherwise.
+    pub fn with_if_newer(mut self, if_newer: bool) -> Self {
+        self.if_newer = if_newer;
+        self
+    }
+
+    /// Include the file's content as data escaped for the given format, without
+    /// looking for includes in it.
+    pub fn with_escape(mut self, format: EscapeFormat) -> Self {
+        self.escape = Some(format);
+        self
+    }
+
+    /// Only produce the files of an [IncludeKind::Glob] include whose frontmatter
+    /// matches the given condition.
+    #[cfg(feature = "glob")]
+    pub fn with_filter<S: Into<String>>(mut self, filter: S) -> Self {
+        self.filter = Some(filter.into());
+        self
+    }
+
+    /// Set the order of the files produced by an [IncludeKind::Glob] include.
+    #[cfg(feature = "glob")]
+    pub fn with_glob_order(mut self, order: GlobOrder) -> Self {
+        self.glob_order = order;
+        self
+    }
+
+    pub fn with_list_entry<P: AsRef<Path>>(mut self, list: P, line: usize) -> Self {
+        self.list_entry = Some((list.as_ref().to_owned(), line));
+        self
+    }
+
+    /// Set the text put between the files of a multi-file include, see
+    /// [IncludeKind::is_multi_file].
+    pub fn with_separator<S: Into<String>>(mut self, separator: S) -> Self {
+        self.separator = Some(separator.into());
+        self
+    }
+
+    pub fn with_kind(mut self, kind: IncludeKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    pub fn kind(&self) -> IncludeKind {
+        self.kind
+    }
+
     pub fn path(&self) -> &Path {
         &self.path
     }
 
-    fn indentation(&self) -> Option<&String> {
-        self.indentation
-            .as_ref()
-            .filter(|it| !it.is_empty())
+    /// The byte range of the directive in the including file's text.
+    pub fn range(&self) -> &Range<usize> {
+        &self.range
     }
 
-    pub fn replace<S: Into<String>, F: FnOnce() -> Result<S, Error>>(
-        &self,
-        target: &mut String,
-        producer: F,
-    ) -> Result<(), Error> {
-        let is_escaped = self.backslashes.len() % 2 == 1;
-        if !is_escaped {
-            let text = producer()?.into();
-            let text = match self.indentation() {
-                None => text,
-                Some(indentation) => text.lines().enumerate()
-                    .map(|(index, line)| match index {
-                        0 => line.to_owned(),
-                        _ => format!("{}{}", indentation, line),
+    pub fn section(&self) -> Option<&str> {
+        self.section.as_deref()
+    }
+
+    pub fn fallback(&self) -> Option<&Path> {
+        self.fallback.as_deref()
+    }
+
+    pub fn default(&self) -> Option<&str> {
+        self.default.as_deref()
+    }
+
+    pub fn repeat(&self) -> Option<usize> {
+        self.repeat
+    }
+
+    pub fn condition(&self) -> Option<&str> {
+        self.condition.as_deref()
+    }
+
+    pub fn is_if_newer(&self) -> bool {
+        self.if_newer
+    }
+
+    pub fn escape(&self) -> Option<EscapeFormat> {
+        self.escape
+    }
+
+    pub fn list_entry(&self) -> Option<(&Path, usize)> {
+        self.list_entry.as_ref().map(|(list, line)| (list.as_path(), *line))
+    }
+
+    pub fn separator(&self) -> &str {
+        self.separator.as_deref().unwrap_or("\n")
+    }
+
+    #[cfg(feature = "glob")]
+    pub fn filter(&self) -> Option<&str> {
+        self.filter.as_deref()
+    }
+
+    #[cfg(feature = "glob")]
+    pub fn glob_order(&self) -> GlobOrder {
+        self.glob_order
+    }
+
+    /// The exact text of the directive, e.g. `${include_indent("x.txt")}`, without
+    /// the backslashes escaping it.
+    pub fn expression(&self) -> &str {
+        &self.expression
+    }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn is_escaped(&self) -> bool {
+        self.backslashes.len() % 2 == 1
+    }
+
+    /// Repeat the text produced for the include and render it, see [Include::render].
+    /// `line` is the output in front of the directive on its line.
+    pub fn render_text(&self, mut text: String, line: &str) -> Result<String, Error> {
+        if let Some(count) = self.repeat {
+            strip_trailing_newline(&mut [&mut text]);
+            self.check_repeat(text.len(), count)?;
+            text = vec![text; count].join("\n");
+        }
+        self.render(&mut [&mut text], line);
+
+        Ok(text)
+    }
+
+    /// Like [Include::render_text], but keeps the produced text in separate fragments.
+    pub fn render_fragments(&self, mut fragments: Vec<Fragment>, line: &str) -> Result<Vec<Fragment>, Error> {
+        if let Some(count) = self.repeat {
+            strip_trailing_newline(&mut fragments.iter_mut().map(Fragment::text_mut).collect::<Vec<_>>());
+            self.check_repeat(fragments.iter().map(|it| it.text().len()).sum(), count)?;
+            let separator = Fragment::new(self.path(), "\n");
+            fragments = match count {
+                0 => vec![],
+                _ => std::iter::repeat(fragments).take(count)
+                    .reduce(|mut repeated, it| {
+                        repeated.push(separator.clone());
+                        repeated.extend(it);
+                        repeated
                     })
-                    .collect::<Vec<_>>()
-                    .join("\n"),
+                    .unwrap_or_default(),
             };
+        }
+        if fragments.is_empty() {
+            fragments.push(Fragment::new(self.path(), ""));
+        }
+        self.render(&mut fragments.iter_mut().map(Fragment::text_mut).collect::<Vec<_>>(), line);
 
-            let end_index = match text.chars().last() {
-                Some('\n') => text.len() - 1,
-                _ => text.len(),
-            };
-            target.replace_range(self.range.clone(), &text[0..end_index]);
+        Ok(fragments)
+    }
+
+    /// Replace the directive with its rendered text, which is `None` for an escaped
+    /// include. Includes have to be replaced last one first, each one splitting the
+    /// text after it off the head of `target`, so no text is moved more than once.
+    pub fn replace(&self, target: &mut Segments, escaped_output: EscapedOutput, text: Option<String>) {
+        let head = &mut target.head;
+        if let Some(text) = text {
+            target.reversed_tail.push(head.split_off(self.range.end));
+            target.reversed_tail.push(text);
+            head.truncate(self.range.start);
+        }
+        if escaped_output == EscapedOutput::Collapsed {
+            escape_backslashes(head, &self.backslashes);
         }
-        escape_backslashes(target, &self.backslashes);
+    }
 
-        Ok(())
+    /// Like [Include::replace], but keeps the rendered text in separate fragments.
+    ///
+    /// `target` is the text of `source` preceding all includes processed so far and
+    /// `reversed_tail` holds the fragments following it, in reverse order. The text
+    /// after this include is split off `target` into a new fragment of `source`.
+    pub fn replace_fragments(
+        &self,
+        source: &Path,
+        target: &mut String,
+        reversed_tail: &mut Vec<Fragment>,
+        escaped_output: EscapedOutput,
+        fragments: Option<Vec<Fragment>>,
+    ) {
+        if let Some(fragments) = fragments {
+            let rest = target.split_off(self.range.end);
+            target.truncate(self.range.start);
+            reversed_tail.push(Fragment::new(source, rest));
+            reversed_tail.extend(fragments.into_iter().rev());
+        }
+        if escaped_output == EscapedOutput::Collapsed {
+            escape_backslashes(target, &self.backslashes);
+        }
+    }
+
+    /// Fail before repeating a text of `length` bytes `count` times, with line breaks
+    /// between the repetitions, if the result would exceed [MAX_REPEATED_BYTES].
+    fn check_repeat(&self, length: usize, count: usize) -> Result<(), Error> {
+        match length.checked_add(1).and_then(|it| it.checked_mul(count)) {
+            Some(total) if total <= MAX_REPEATED_BYTES => Ok(()),
+            _ => Err(Error::RepeatTooLarge(self.path.clone(), count)),
+        }
+    }
+
+    /// Apply dedenting, numbering, wrapping, annotation and indentation and strip a single
+    /// trailing newline from the produced text, which may be split into several consecutive segments.
+    /// An aligned include is indented by `line`, the output in front of it on its line.
+    fn render(&self, segments: &mut [&mut String], line: &str) {
+        if let Some(format) = self.escape {
+            strip_trailing_newline(segments);
+            format.escape(segments);
+        }
+
+        if self.dedent {
+            dedent_lines(segments);
+        }
+
+        if let Some((start, width)) = self.numbering {
+            number_lines(segments, start, width);
+        }
+
+        if let Some((prefix, suffix)) = &self.wrapping {
+            surround(segments, prefix, suffix);
+        }
+
+        if let Some((begin, end)) = &self.annotation {
+            surround(segments, &format!("{}\n", begin), &format!("\n{}", end));
+        }
+
+        let indentation = match self.align {
+            Some(mode) => Some(mode.normalize(line_indentation(line))),
+            None => self.indentation.clone(),
+        };
+        if let Some(indentation) = indentation.filter(|it| !it.is_empty()) {
+            indent_lines(segments, &indentation);
+        }
+
+        // Raw bytes are included as they are, like they're spliced in as bytes.
+        if self.kind != IncludeKind::RawBytes {
+            strip_trailing_newline(segments);
+        }
+    }
+}
+
+/// The output in front of the includes of a text on their line, which aligned
+/// includes are indented by. The includes have to be passed in the order they
+/// appear in, each followed by its rendered text, so the texts of earlier includes
+/// on the same line are taken into account.
+pub struct OutputLine<'a> {
+    content: &'a str,
+    escaped_output: EscapedOutput,
+    /// The end of the text of `content` in the output so far.
+    position: usize,
+    /// The output since the last line break.
+    line: String,
+}
+
+impl<'a> OutputLine<'a> {
+    pub fn new(content: &'a str, escaped_output: EscapedOutput) -> Self {
+        Self { content, escaped_output, position: 0, line: String::new() }
+    }
+
+    /// Continue the output up to the given include, returning the output in front
+    /// of it on its line. An escaped directive stays in the output as text.
+    pub fn advance(&mut self, include: &Include) -> &str {
+        let content = self.content;
+        let kept = match self.escaped_output {
+            EscapedOutput::Collapsed => include.backslashes.len() / 2,
+            EscapedOutput::Literal => include.backslashes.len(),
+        };
+        self.push(&content[self.position..include.backslashes.start]);
+        self.push(&content[include.backslashes.start..include.backslashes.start + kept]);
+        self.position = match include.is_escaped() {
+            true => include.range.start,
+            false => include.range.end,
+        };
+
+        &self.line
+    }
+
+    /// Continue the output with the rendered text of the include advanced to last.
+    pub fn push(&mut self, text: &str) {
+        match text.rfind('\n') {
+            Some(index) => self.line = text[index + 1..].to_owned(),
+            None => self.line.push_str(text),
+        }
+    }
+}
+
+/// Render the texts produced for the includes of `content`, see [Include::render_text].
+/// The includes are given last one first like they're found, the texts and the result
+/// in the order they appear in, `None` for escaped includes.
+pub fn render_texts(
+    content: &str,
+    includes: &[Include],
+    texts: Vec<Option<String>>,
+    escaped_output: EscapedOutput,
+) -> Result<Vec<Option<String>>, Error> {
+    let mut line = OutputLine::new(content, escaped_output);
+    includes
+        .iter()
+        .rev()
+        .zip(texts)
+        .map(|(include, text)| {
+            let prefix = line.advance(include);
+            let text = text.map(|it| include.render_text(it, prefix)).transpose()?;
+            line.push(text.as_deref().unwrap_or_default());
+            Ok(text)
+        })
+        .collect()
+}
+
+/// The indentation aligning text with `line`: every character but spaces and tabs
+/// replaced by a space.
+fn line_indentation(line: &str) -> String {
+    line.chars()
+        .map(|it| match it {
+            ' ' | '\t' => it,
+            _ => ' ',
+        })
+        .collect()
+}
+
+/// Strip the trailing newline and put `prefix` and `suffix` around the text.
+fn surround(segments: &mut [&mut String], prefix: &str, suffix: &str) {
+    strip_trailing_newline(segments);
+    if let Some(first) = segments.first_mut() {
+        first.insert_str(0, prefix);
+    }
+    if let Some(last) = segments.last_mut() {
+        last.push_str(suffix);
+    }
+}
+
+fn strip_trailing_newline(segments: &mut [&mut String]) {
+    if let Some(last) = segments.iter_mut().rev().find(|it| !it.is_empty()) {
+        if last.ends_with('\n') {
+            last.pop();
+        }
+    }
+}
+
+/// Prefix every line but the first with `indentation`, normalizing line breaks
+/// to `\n` and dropping a line break at the very end of the text.
+fn indent_lines(segments: &mut [&mut String], indentation: &str) {
+    let text: String = segments.iter().map(|it| it.as_str()).collect();
+    let line_breaks: Vec<Range<usize>> = lazy_regex::regex!("\r?\n")
+        .find_iter(&text)
+        .map(|it| it.range())
+        .collect();
+
+    let mut offset = 0;
+    for segment in segments.iter_mut() {
+        let range = offset..offset + segment.len();
+        offset = range.end;
+
+        let mut indented = String::with_capacity(segment.len());
+        let mut position = range.start;
+        for line_break in line_breaks.iter().filter(|it| it.start < range.end && it.end > range.start) {
+            indented.push_str(&text[position..line_break.start.max(range.start)]);
+            if line_break.end <= range.end && line_break.end < text.len() {
+                indented.push('\n');
+                indented.push_str(indentation);
+            }
+            position = line_break.end.min(range.end);
+        }
+        indented.push_str(&text[position..range.end]);
+
+        **segment = indented;
+    }
+}
+
+/// Remove the leading whitespace common to all lines that aren't blank. Blank
+/// lines lose as much of it as they have.
+fn dedent_lines(segments: &mut [&mut String]) {
+    let text: String = segments.iter().map(|it| it.as_str()).collect();
+    let leading_whitespace = |line: &str| line.len() - line.trim_start_matches([' ', '\t']).len();
+
+    let common = text
+        .split('\n')
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| &line[..leading_whitespace(line)])
+        .reduce(|common, it| {
+            let length = common
+                .char_indices()
+                .zip(it.chars())
+                .find(|((_, a), b)| a != b)
+                .map_or(common.len().min(it.len()), |((index, _), _)| index);
+            &common[..length]
+        })
+        .unwrap_or_default();
+    if common.is_empty() {
+        return;
+    }
+
+    let mut removals = vec![];
+    let mut line_start = 0;
+    for line in text.split('\n') {
+        let length = match line.starts_with(common) {
+            true => common.len(),
+            false => leading_whitespace(line).min(common.len()),
+        };
+        removals.push(line_start..line_start + length);
+        line_start += line.len() + 1;
+    }
+
+    let mut offset = 0;
+    for segment in segments.iter_mut() {
+        let range = offset..offset + segment.len();
+        offset = range.end;
+
+        let mut dedented = String::with_capacity(segment.len());
+        let mut position = range.start;
+        for removal in removals.iter().filter(|it| it.start < range.end && it.end > range.start) {
+            dedented.push_str(&text[position..removal.start.max(range.start)]);
+            position = removal.end.min(range.end);
+        }
+        dedented.push_str(&text[position..range.end]);
+
+        **segment = dedented;
+    }
+}
+
+/// Prefix every line with its number and ` | `, numbers being right-aligned to
+/// the width of the largest one, but at least `width`. A line break at the very
+/// end of the text doesn't start another line.
+fn number_lines(segments: &mut [&mut String], start: usize, width: usize) {
+    let text: String = segments.iter().map(|it| it.as_str()).collect();
+    let line_starts: Vec<usize> = std::iter::once(0)
+        .chain(text.match_indices('\n').map(|(index, _)| index + 1))
+        .filter(|it| *it < text.len())
+        .collect();
+    let last = start + line_starts.len().saturating_sub(1);
+    let width = width.max(last.to_string().len());
+
+    let mut offset = 0;
+    let mut number = start;
+    for segment in segments.iter_mut() {
+        let range = offset..offset + segment.len();
+        offset = range.end;
+
+        let mut numbered = String::with_capacity(segment.len());
+        let mut position = range.start;
+        for line_start in line_starts.iter().filter(|it| range.contains(it)) {
+            numbered.push_str(&text[position..*line_start]);
+            numbered.push_str(&format!("{:>width$} | ", number, width = width));
+            number += 1;
+            position = *line_start;
+        }
+        numbered.push_str(&text[position..range.end]);
+
+        **segment = numbered;
     }
 }
 
@@ -79,9 +693,9 @@ fn escape_backslashes(target: &mut String, backslashes: &Range<usize>) {
 #[cfg(test)]
 mod test_replace {
     use rstest::rstest;
-    use crate::{canonical_path::CanonicalPath, Error};
+    use crate::{canonical_path::CanonicalPath, EscapedOutput, Error};
     use std::ops::Range;
-    use super::Include;
+    use super::{Include, Segments};
 
     #[rstest]
     #[case("12345", 0..0, 0..4, "XXX5")]
@@ -101,10 +715,35 @@ mod test_replace {
             backslashes,
             None,
         );
-        let mut input = input.to_owned();
-        include.replace(&mut input, || Ok("XXX"))?;
+        let mut input = Segments::new(input.to_owned());
+        include.replace(&mut input, EscapedOutput::Collapsed, (!include.is_escaped()).then(|| "XXX".to_owned()));
 
-        assert_eq!(&input, expectation);
+        assert_eq!(input.into_string(), expectation);
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[case("12345", 0..0, 0..4, "XXX5")]
+    #[case("/1234", 0..1, 1..3, "/1234")]
+    #[case("//123", 0..2, 2..4, "//XXX3")]
+    #[case("///12", 0..3, 3..4, "///12")]
+    fn should_keep_backslashes_in_literal_mode(
+        #[case] input: &str,
+        #[case] backslashes: Range<usize>,
+        #[case] range: Range<usize>,
+        #[case] expectation: &str,
+    ) -> Result<(), Error>{
+        let include = Include::new(
+            range,
+            CanonicalPath::_new("/source", "/source"),
+            backslashes,
+            None,
+        );
+        let mut input = Segments::new(input.to_owned());
+        include.replace(&mut input, EscapedOutput::Literal, (!include.is_escaped()).then(|| "XXX".to_owned()));
+
+        assert_eq!(input.into_string(), expectation);
 
         Ok(())
     }
@@ -126,15 +765,154 @@ mod test_replace {
             backslashes,
             Some(indentation.to_owned()),
         );
-        let mut input = input.to_owned();
-        include.replace(&mut input, || Ok(replacement))?;
+        let mut input = Segments::new(input.to_owned());
+        let text = include.render_text(replacement.to_owned(), "")?;
+        include.replace(&mut input, EscapedOutput::Collapsed, Some(text));
 
-        assert_eq!(&input, expectation);
+        assert_eq!(input.into_string(), expectation);
 
         Ok(())
     }
 }
 
+#[cfg(test)]
+mod test_render {
+    use rstest::rstest;
+    use crate::canonical_path::CanonicalPath;
+    use super::Include;
+
+    #[rstest]
+    #[case(vec!["a\nb", "c\nd\n"], None, vec!["a\nb", "c\nd"])]
+    #[case(vec!["a\nb\n", ""], None, vec!["a\nb", ""])]
+    #[case(vec!["a\nb", "c\nd\n"], Some("  "), vec!["a\n  b", "c\n  d"])]
+    #[case(vec!["a\r", "\nb"], Some("  "), vec!["a", "\n  b"])]
+    #[case(vec!["a\n", "\n"], Some("  "), vec!["a\n  ", ""])]
+    fn should_render_segments_like_a_single_text(
+        #[case] segments: Vec<&str>,
+        #[case] indentation: Option<&str>,
+        #[case] expectation: Vec<&str>,
+    ) {
+        let include = Include::new(
+            0..0,
+            CanonicalPath::_new("/source", "/source"),
+            0..0,
+            indentation.map(str::to_owned),
+        );
+        let mut segments: Vec<String> = segments.into_iter().map(str::to_owned).collect();
+        include.render(&mut segments.iter_mut().collect::<Vec<_>>(), "");
+
+        assert_eq!(segments, expectation);
+    }
+
+    #[rstest]
+    #[case(vec!["a\n"], None, vec!["<a>"])]
+    #[case(vec!["a\n", "b\n"], None, vec!["<a\n", "b>"])]
+    #[case(vec!["a\n", "b\n"], Some("  "), vec!["<a\n  ", "b>"])]
+    #[case(vec![""], None, vec!["<>"])]
+    fn should_wrap_segments(
+        #[case] segments: Vec<&str>,
+        #[case] indentation: Option<&str>,
+        #[case] expectation: Vec<&str>,
+    ) {
+        let include = Include::new(
+            0..0,
+            CanonicalPath::_new("/source", "/source"),
+            0..0,
+            indentation.map(str::to_owned),
+        ).with_wrapping("<", ">");
+        let mut segments: Vec<String> = segments.into_iter().map(str::to_owned).collect();
+        include.render(&mut segments.iter_mut().collect::<Vec<_>>(), "");
+
+        assert_eq!(segments, expectation);
+    }
+}
+
+#[cfg(test)]
+mod test_render_texts {
+    use rstest::rstest;
+    use crate::{canonical_path::CanonicalPath, EscapedOutput, Error, IndentMode};
+    use std::ops::Range;
+    use super::{render_texts, Include, Segments};
+
+    #[rstest]
+    #[case("\t12345", vec![(1..1, 1..2, "XY\nZ"), (3..3, 3..4, "1\n2")], "\tXY\n\tZ21\n\t  245")]
+    #[case("\\\\1", vec![(0..2, 2..3, "a\nb")], "\\a\n b")]
+    #[case("\\12\n3", vec![(0..1, 1..2, "X"), (2..2, 2..3, "a\nb")], "1a\n b\n3")]
+    fn should_align_with_the_output_in_front(
+        #[case] input: &str,
+        #[case] includes: Vec<(Range<usize>, Range<usize>, &str)>,
+        #[case] expectation: &str,
+    ) -> Result<(), Error> {
+        let (includes, texts): (Vec<_>, Vec<_>) = includes
+            .into_iter()
+            .rev()
+            .map(|(backslashes, range, text)| {
+                let include = Include::new(range, CanonicalPath::_new("/source", "/source"), backslashes, None)
+                    .with_alignment(IndentMode::Literal);
+                let text = (!include.is_escaped()).then(|| text.to_owned());
+                (include, text)
+            })
+            .unzip();
+        let texts = render_texts(input, &includes, texts.into_iter().rev().collect(), EscapedOutput::Collapsed)?;
+
+        let mut output = Segments::new(input.to_owned());
+        for (include, text) in includes.iter().zip(texts.into_iter().rev()) {
+            include.replace(&mut output, EscapedOutput::Collapsed, text);
+        }
+        assert_eq!(output.into_string(), expectation);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test_dedent_lines {
+    use super::dedent_lines;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(vec!["    a\n    b\n"], vec!["a\nb\n"])]
+    #[case(vec!["    a\n      b"], vec!["a\n  b"])]
+    #[case(vec!["  a\n\n  b"], vec!["a\n\nb"])]
+    #[case(vec!["  a\n \n  b"], vec!["a\n\nb"])]
+    #[case(vec!["\ta\n  b"], vec!["\ta\n  b"])]
+    #[case(vec!["  a\n ", " b"], vec!["a\n", "b"])]
+    #[case(vec!["a\n  b"], vec!["a\n  b"])]
+    fn should_remove_common_indentation(
+        #[case] segments: Vec<&str>,
+        #[case] expectation: Vec<&str>,
+    ) {
+        let mut segments: Vec<String> = segments.into_iter().map(str::to_owned).collect();
+        dedent_lines(&mut segments.iter_mut().collect::<Vec<_>>());
+
+        assert_eq!(segments, expectation);
+    }
+}
+
+#[cfg(test)]
+mod test_number_lines {
+    use super::number_lines;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(vec!["a\nb\n"], 1, 0, vec!["1 | a\n2 | b\n"])]
+    #[case(vec!["a\n", "b"], 9, 0, vec![" 9 | a\n", "10 | b"])]
+    #[case(vec!["a", "\nb"], 1, 3, vec!["  1 | a", "\n  2 | b"])]
+    #[case(vec!["", "a"], 1, 0, vec!["", "1 | a"])]
+    #[case(vec![""], 1, 0, vec![""])]
+    fn should_number_lines(
+        #[case] segments: Vec<&str>,
+        #[case] start: usize,
+        #[case] width: usize,
+        #[case] expectation: Vec<&str>,
+    ) {
+        let mut segments: Vec<String> = segments.into_iter().map(str::to_owned).collect();
+        number_lines(&mut segments.iter_mut().collect::<Vec<_>>(), start, width);
+
+        assert_eq!(segments, expectation);
+    }
+}
+
 #[cfg(test)]
 mod test_escape_backslashes {
     use super::escape_backslashes;
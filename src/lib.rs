@@ -1,4 +1,5 @@
 #![doc = include_str!("../README.md")]
+#![doc = include_str!("../docs/directives.md")]
 #[cfg(test)]
 extern crate indoc;
 #[cfg(test)]
@@ -7,22 +8,82 @@ extern crate rstest;
 extern crate temp_dir;
 extern crate thiserror;
 
+mod balance;
+mod builder;
+#[cfg(feature = "cache")]
+mod cache;
 mod canonical_path;
+mod case;
+mod chain;
+mod data;
 mod dependency_path;
+mod diagnostics;
+mod directives;
+mod encoding;
+mod escape;
+mod fragment;
+mod frame;
+#[cfg(feature = "glob")]
+mod frontmatter;
+#[cfg(feature = "glob")]
+mod glob_files;
+mod guard;
+#[cfg(feature = "hash")]
+mod hash;
+#[cfg(feature = "http")]
+mod http;
 mod includes;
+mod list;
+mod load_state;
 mod loader;
+#[cfg(feature = "manifest")]
+mod manifest;
+mod options;
+mod raw_bytes;
+mod section;
+#[cfg(feature = "serde")]
+mod serialize;
+mod source;
+mod stats;
+mod syntax;
+mod tree;
+mod variables;
+mod warnings;
 
-use loader::Loader;
-use std::path::{Path, PathBuf};
+pub use balance::Delimiters;
+pub use builder::LoaderBuilder;
+pub use chain::ChainedError;
+#[cfg(feature = "cache")]
+pub use cache::{CacheStats, DirectoryCache, DiskCache};
+pub use diagnostics::{Diagnostic, DiagnosticKind, Diagnostics};
+pub use fragment::Fragment;
+#[cfg(feature = "hash")]
+pub use hash::LoadResult;
+pub use loader::Loader;
+#[cfg(feature = "manifest")]
+pub use manifest::{Manifest, ManifestEntry};
+pub use options::{CommentStyle, CycleKey, EscapedOutput, IndentMode, LoadOptions, RelativeBase, LITERAL_MAX_DEPTH};
+pub use source::FileSource;
+#[cfg(feature = "tar")]
+pub use source::TarSource;
+pub use stats::LoadStats;
+pub use syntax::SyntaxIssue;
+pub use tree::IncludeTree;
+pub use warnings::{LoadWarnings, RedundantPath};
+use std::{path::{Path, PathBuf}, time::Duration};
 
 /// Load the given file path and recursively follow references to other files
 /// inside it, inserting the text from references.
 ///
 /// References are either `${include("<path>")}` or `${include_indent("<path>")}`,
 /// with the latter preserving local indentation for each new line in the referenced
-/// file. Paths can be relative or absolute.
+/// file. Paths can be relative or absolute. These are the only directives recognized
+/// by default; the others have to be enabled with
+/// [LoaderBuilder::allowed_directives] or [LoaderBuilder::allow_all_directives]. See
+/// the [directive reference](crate#directives) for all of them.
 ///
-/// The function will check references for cyclic dependencies and will return a [Error::CyclicDependency] should it detect one.
+/// The function will check references for cyclic dependencies and will return a
+/// [Error::CyclicDependency] should it detect one.
 ///
 /// # Example
 ///
@@ -82,19 +143,408 @@ use std::path::{Path, PathBuf};
 /// );
 /// ```
 ///
-/// Note that the indentation in `start.txt` has been applied to everything `start.txt` included.
+/// Note that the indentation in `start.txt` has been applied to everything `start.txt`
+/// included.
 pub fn load_file_recursively<P: AsRef<Path>>(origin: P) -> Result<String, Error> {
     Loader::new().load_file_recursively(origin)
 }
 
+/// Like [load_file_recursively], but with the given [LoadOptions].
+///
+/// ```
+/// use recursive_file_loader::{load_file_recursively_with, LoadOptions};
+/// # use temp_dir::TempDir;
+/// # let dir = TempDir::new().unwrap();
+/// # let start = dir.child("start.txt");
+/// # std::fs::write(&start, "hello, ${include(\"world.txt\")}!".as_bytes()).unwrap();
+/// # std::fs::write(dir.child("world.txt"), "world".as_bytes()).unwrap();
+///
+/// let path = "start.txt";
+/// # let path = &start;
+///
+/// let result = load_file_recursively_with(&path, LoadOptions {
+///     max_depth: Some(1),
+///     ..Default::default()
+/// }).unwrap();
+///
+/// assert_eq!(result, "hello, world!");
+/// ```
+pub fn load_file_recursively_with<P: AsRef<Path>>(origin: P, options: LoadOptions) -> Result<String, Error> {
+    LoaderBuilder::from(options).build().load_file_recursively(origin)
+}
+
+/// Like [load_file_recursively], but writes the output to the file at `out`, see
+/// [Loader::load_file_recursively_to_path].
+///
+/// ```
+/// use recursive_file_loader::{load_file_recursively_to_path, Error};
+/// # use temp_dir::TempDir;
+/// # let dir = TempDir::new().unwrap();
+/// # let start = dir.child("start.txt");
+/// # std::fs::write(&start, "hello, ${include(\"world.txt\")}!".as_bytes()).unwrap();
+/// # std::fs::write(dir.child("world.txt"), "world".as_bytes()).unwrap();
+///
+/// let path = "start.txt";
+/// # let path = &start;
+/// let out = "build/hello.txt";
+/// # let out = dir.child("build/hello.txt");
+///
+/// load_file_recursively_to_path(&path, &out).unwrap();
+/// assert_eq!(std::fs::read_to_string(&out).unwrap(), "hello, world!");
+///
+/// let clobbering = load_file_recursively_to_path(&path, &path);
+/// assert!(matches!(clobbering, Err(Error::OverwritesInput(_))));
+/// ```
+pub fn load_file_recursively_to_path<P: AsRef<Path>, Q: AsRef<Path>>(origin: P, out: Q) -> Result<(), Error> {
+    Loader::new().load_file_recursively_to_path(origin, out)
+}
+
+/// Like [load_file_recursively], but returns the output as a list of [Fragment]s in
+/// output order, each carrying the path of the file its text originates from.
+///
+/// ```
+/// use recursive_file_loader::resolve_fragments;
+/// # use temp_dir::TempDir;
+/// # let dir = TempDir::new().unwrap();
+/// # let start = dir.child("start.txt");
+/// # std::fs::write(&start, "hello, ${include(\"world.txt\")}!".as_bytes()).unwrap();
+/// # std::fs::write(dir.child("world.txt"), "world".as_bytes()).unwrap();
+///
+/// let path = "start.txt";
+/// # let path = &start;
+///
+/// let fragments = resolve_fragments(&path).unwrap();
+/// let texts: Vec<&str> = fragments.iter().map(|it| it.text()).collect();
+///
+/// assert_eq!(texts, vec!["hello, ", "world", "!"]);
+/// ```
+pub fn resolve_fragments<P: AsRef<Path>>(origin: P) -> Result<Vec<Fragment>, Error> {
+    Loader::new().resolve_fragments(origin)
+}
+
+/// Build the hierarchy of files included by the given file, following references
+/// like [load_file_recursively] does, but without assembling their content.
+///
+/// A file included in several places appears at each of them. Cyclic dependencies
+/// are reported as [Error::CyclicDependency].
+pub fn build_tree<P: AsRef<Path>>(origin: P) -> Result<IncludeTree, Error> {
+    Loader::new().build_tree(origin)
+}
+
+/// Render the hierarchy of files included by the given file like the `tree` command
+/// does, with paths relative to the directory of the given file.
+///
+/// ```text
+/// start.txt
+/// ├── a.txt
+/// │   └── parts/b.txt
+/// └── ../shared.txt
+/// ```
+///
+/// Cyclic dependencies are reported as [Error::CyclicDependency]. A loader in lenient
+/// mode marks the file closing the cycle with `(cycle)` instead, see
+/// [LoaderBuilder::lenient].
+pub fn print_tree<P: AsRef<Path>>(origin: P) -> Result<String, Error> {
+    Loader::new().print_tree(origin)
+}
+
+/// Walk the hierarchy of files included by the given file like [build_tree] does,
+/// but instead of stopping at the first error, collect every issue found on the
+/// way: missing files, cyclic dependencies and malformed directives as reported
+/// by [check_syntax]. Files that can be read are diagnosed even if others fail,
+/// and every file is diagnosed once, no matter how often it's included.
+///
+/// Issues with a directive are reported in the file containing it, at the line
+/// and column where the directive starts. A root file that can't be read is
+/// reported without a position.
+///
+/// ```
+/// use recursive_file_loader::{diagnose, DiagnosticKind};
+/// # let dir = temp_dir::TempDir::new().unwrap();
+/// # let start = dir.child("start.txt");
+/// # std::fs::write(&start, "${include(\"a.txt\")}\n${inclde(\"b.txt\")}").unwrap();
+/// # std::fs::write(dir.child("a.txt"), "${include(\"missing.txt\")}").unwrap();
+///
+/// let path = "start.txt";
+/// # let path = &start;
+///
+/// let diagnostics = diagnose(&path);
+/// let kinds: Vec<_> = diagnostics.iter().map(|it| (it.kind(), it.position())).collect();
+///
+/// assert_eq!(kinds, vec![
+///     (DiagnosticKind::MissingFile, Some((1, 1))),
+///     (DiagnosticKind::UnknownDirective, Some((2, 1))),
+/// ]);
+/// ```
+pub fn diagnose<P: AsRef<Path>>(origin: P) -> Diagnostics {
+    Loader::new().diagnose(origin)
+}
+
+/// Resolve the references in `content` without touching the file system, asking
+/// `resolver` for the content of every referenced path.
+///
+/// Paths are passed to the resolver as written in the reference. The content it
+/// returns is inserted as is, so it's up to the resolver to resolve references in
+/// it, e.g. by calling this function again. Escaping, fallbacks, indentation and
+/// the other reference flavors work like in [load_file_recursively], all of them
+/// being recognized, while `${self_path}` is left as is since the content has no path.
+///
+/// ```
+/// use recursive_file_loader::{resolve_includes, Error};
+///
+/// let content = "hello, ${include(\"world.txt\")}!";
+/// let resolved = resolve_includes(content, |path| match path.to_str() {
+///     Some("world.txt") => Ok(String::from("world\n")),
+///     _ => Err(Error::FileNotFound(path.to_owned())),
+/// });
+///
+/// assert_eq!(resolved.unwrap(), "hello, world!");
+/// ```
+pub fn resolve_includes<F>(content: &str, resolver: F) -> Result<String, Error>
+where
+    F: FnMut(&Path) -> Result<String, Error>,
+{
+    directives::resolve_includes(content, resolver)
+}
+
+/// Find malformed directives in `content` without resolving them or touching the
+/// file system: directives with an empty path, unknown directive names, known
+/// directives with unsupported arguments and directives that aren't closed on
+/// their line. Escaped directives are ignored, as are expressions like `${name}`
+/// that don't look like includes.
+///
+/// ```
+/// use recursive_file_loader::{check_syntax, SyntaxIssue};
+///
+/// let issues = check_syntax("${include(\"a.txt\")} ${inclde(\"b.txt\")}");
+///
+/// assert_eq!(issues, vec![SyntaxIssue::UnknownDirective(20..38)]);
+/// ```
+pub fn check_syntax(content: &str) -> Vec<SyntaxIssue> {
+    syntax::check(content)
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error("file not found: '{0}'")]
     FileNotFound(PathBuf),
 
-    #[error("cyclic dependency detected between '{0}' and '{1}'")]
-    CyclicDependency(PathBuf, PathBuf),
+    /// None of the paths tried for an include exist, see [LoaderBuilder::try_extensions].
+    #[error("file not found, tried {}", .0.iter().map(|it| format!("'{}'", it.to_string_lossy())).collect::<Vec<_>>().join(", "))]
+    FilesNotFound(Vec<PathBuf>),
+
+    /// A file includes itself, directly or indirectly.
+    #[error("cyclic dependency detected: {}, closed by `{directive}` on line {line} of '{}'", .chain.iter().chain(.chain.first()).map(|it| format!("'{}'", it.to_string_lossy())).collect::<Vec<_>>().join(" -> "), .from.to_string_lossy())]
+    CyclicDependency {
+        /// The file containing the include closing the loop.
+        from: PathBuf,
+        /// The included file, as referenced by `from`.
+        to: PathBuf,
+        /// The directive closing the loop.
+        directive: String,
+        /// The line of the directive in `from`.
+        line: usize,
+        /// The canonical paths of the files forming the cycle, in include order and
+        /// starting with the smallest path, so they're the same for every traversal of
+        /// the cycle. Which directive closes the loop depends on where the cycle was
+        /// entered, which is the same for every load of the same file.
+        chain: Box<[PathBuf]>,
+    },
+
+    /// A file contains text shaped like a directive that isn't a recognized one,
+    /// see [LoaderBuilder::strict_directives].
+    #[error("unknown directive `{0}` in '{1}'")]
+    UnknownDirective(String, PathBuf),
+
+    /// A directive was found that isn't enabled, see [LoaderBuilder::allowed_directives].
+    /// Holds the file, the line and the directive.
+    #[error("directive `{2}` on line {1} of '{0}' is not allowed")]
+    DirectiveNotAllowed(PathBuf, usize, String),
+
+    /// A directive is opened but not closed on the same line, see
+    /// [LoaderBuilder::warn_unclosed_directives]. Holds the file and the line.
+    #[error("unclosed directive on line {1} of '{0}'")]
+    UnclosedDirective(PathBuf, usize),
+
+    /// A directive's arguments are malformed, e.g. an unknown named argument.
+    /// Holds the file, the line and a description of the problem quoting the directive.
+    #[error("invalid argument on line {1} of '{0}': {2}")]
+    InvalidArgument(PathBuf, usize, String),
+
+    /// An `include_section` directive named a section the file doesn't contain.
+    #[error("'{0}' has no section '{1}'")]
+    SectionNotFound(PathBuf, String),
+
+    /// A file contains more directives than allowed, see [LoaderBuilder::max_includes_per_file].
+    #[error("'{0}' contains {1} includes, more than allowed")]
+    TooManyIncludes(PathBuf, usize),
+
+    /// A load replaced more includes than allowed, see [LoaderBuilder::max_expansions].
+    /// Holds the limit.
+    #[error("more than {0} includes expanded")]
+    ExpansionBudgetExceeded(usize),
+
+    /// An `include_repeat` would produce more than 1 GiB of text. Holds the repeated
+    /// file and the count.
+    #[error("repeating '{0}' {1} times produces too much text")]
+    RepeatTooLarge(PathBuf, usize),
+
+    /// Includes are nested deeper than allowed, see [LoaderBuilder::max_depth].
+    /// Holds the file that would exceed the limit and the limit.
+    #[error("'{0}' is nested deeper than {1} levels")]
+    MaxDepthExceeded(PathBuf, usize),
+
+    /// A load took longer than allowed, see [LoaderBuilder::max_duration]. Holds
+    /// the limit.
+    #[error("load took longer than {0:?}")]
+    Timeout(Duration),
+
+    /// A file lies outside of the directory loading is restricted to, see
+    /// [LoaderBuilder::restrict_to]. Holds the file and the directory.
+    #[error("'{0}' is outside of '{1}'")]
+    OutsideRoot(PathBuf, PathBuf),
+
+    /// A line of the loaded text is longer than allowed, see
+    /// [LoaderBuilder::max_line_length]. Holds the loaded file, the line and its
+    /// length in characters.
+    #[error("line {1} of the text loaded from '{0}' is {2} characters long, more than allowed")]
+    LineTooLong(PathBuf, usize, usize),
+
+    /// The delimiters of the loaded text aren't balanced, see
+    /// [LoaderBuilder::validate_balanced]. Holds the loaded file, the byte offset of
+    /// the first unbalanced delimiter in the text and a description of the problem.
+    #[error("unbalanced delimiters at byte {1} of the text loaded from '{0}': {2}")]
+    Unbalanced(PathBuf, usize, String),
+
+    /// A file was included more than once, see [LoaderBuilder::forbid_duplicate_includes].
+    /// Holds the included file and the files including it first and second.
+    #[error("'{0}' is included by both '{1}' and '{2}'")]
+    DuplicateInclude(PathBuf, PathBuf, PathBuf),
+
+    /// A path starting with `@/` was used, but no directory containing the root
+    /// marker was found above the including file, see [LoaderBuilder::root_marker].
+    #[error("no '{1}' found in any directory containing '{0}'")]
+    RootMarkerNotFound(PathBuf, String),
+
+    /// A file listed by an `include_list` directive can't be loaded. Holds the list
+    /// file, the line of the entry and the error loading the listed file.
+    #[error("entry on line {1} of '{0}': {2}")]
+    ListEntry(PathBuf, usize, Box<Error>),
+
+    /// The pattern of an `include_glob` or `include_where` directive is malformed.
+    /// Holds the pattern and a description of the problem.
+    #[cfg(feature = "glob")]
+    #[error("invalid glob pattern '{0}': {1}")]
+    InvalidGlob(String, String),
+
+    /// The condition of an `include_where` directive isn't of the form `key == value`
+    /// or `key != value`.
+    #[cfg(feature = "glob")]
+    #[error("invalid condition '{0}', expected 'key == value' or 'key != value'")]
+    InvalidCondition(String),
+
+    /// A path starting with `~` couldn't be expanded because it names another
+    /// user's home directory, or the home directory is unknown, see
+    /// [LoaderBuilder::expand_tilde].
+    #[error("cannot expand '~' in '{0}'")]
+    TildeNotExpanded(String),
+
+    /// A file isn't valid UTF-8. Holds the file and the offset of the first
+    /// invalid byte.
+    #[error("'{0}' is not valid UTF-8 at byte {1}")]
+    InvalidUtf8(PathBuf, usize),
+
+    /// An `include_env` directive without a default named an unset environment variable.
+    #[error("environment variable '{0}' is not defined")]
+    UndefinedEnvVar(String),
+
+    /// A `${var:NAME}` placeholder without a default named a variable that isn't
+    /// defined, see [LoaderBuilder::variables]. Holds the file, the line and the name.
+    #[error("variable '{2}' on line {1} of '{0}' is not defined")]
+    UndefinedVariable(PathBuf, usize, String),
+
+    /// An include refers to a `data:` URI that can't be decoded. Holds the URI and
+    /// the reason.
+    #[error("invalid data URI '{0}': {1}")]
+    InvalidDataUri(String, String),
+
+    /// An `include_cmd` directive was found while commands aren't allowed, see
+    /// [LoaderBuilder::allow_commands].
+    #[error("running command `{0}` is not allowed")]
+    CommandNotAllowed(String),
+
+    /// A command run by an `include_cmd` directive didn't succeed. Holds the command,
+    /// its exit status and its standard error output.
+    #[error("command `{0}` failed with {1}: {2}")]
+    CommandFailed(String, std::process::ExitStatus, String),
+
+    /// A path to load, or the path of an include, names a directory. Holds the path.
+    #[error("'{0}' is a directory, not a file")]
+    IsADirectory(PathBuf),
+
+    /// A file is larger than allowed, see [LoaderBuilder::max_file_bytes]. Holds the
+    /// file and the limit in bytes.
+    #[error("file '{0}' is larger than the limit of {1} bytes")]
+    FileTooLarge(PathBuf, u64),
+
+    /// A remote file responded with a status other than 200, see [LoaderBuilder::allow_http].
+    #[cfg(feature = "http")]
+    #[error("'{0}' responded with status {1}")]
+    HttpStatus(String, u16),
+
+    /// A remote file didn't respond in time, see [LoaderBuilder::http_timeout].
+    #[cfg(feature = "http")]
+    #[error("timed out fetching '{0}'")]
+    HttpTimeout(String),
+
+    /// A remote file couldn't be fetched, e.g. because the host is unreachable.
+    #[cfg(feature = "http")]
+    #[error("failed to fetch '{0}': {1}")]
+    HttpTransport(String, String),
+
+    /// A file read again differs from the manifest it's verified against, see
+    /// [Loader::verify_against]. Holds the first file that differs.
+    #[cfg(feature = "manifest")]
+    #[error("file '{0}' differs from the manifest")]
+    ManifestMismatch(PathBuf),
+
+    /// A manifest couldn't be converted from or to JSON. Holds the reason.
+    #[cfg(feature = "manifest")]
+    #[error("invalid manifest: {0}")]
+    InvalidManifest(String),
+
+    /// The label given to [LoaderBuilder::encoding] doesn't name an encoding. Holds
+    /// the label.
+    #[cfg(feature = "encoding")]
+    #[error("unknown encoding '{0}'")]
+    UnknownEncoding(String),
+
+    /// An include's spelling differs in case from the file on disk. Holds the
+    /// requested path and the path as it's spelled on disk.
+    #[error("'{0}' differs in case from the file on disk: '{1}'")]
+    CaseMismatch(PathBuf, PathBuf),
+
+    /// An include only matches files on disk when ignoring case, and matches more
+    /// than one, see [LoaderBuilder::resolve_case_insensitive]. Holds the requested
+    /// path and the matching paths.
+    #[error("'{}' matches several files when ignoring case: {}", .0.to_string_lossy(), .1.iter().map(|it| format!("'{}'", it.to_string_lossy())).collect::<Vec<_>>().join(", "))]
+    AmbiguousCase(PathBuf, Vec<PathBuf>),
+
+    /// A file exists but couldn't be read, e.g. for lack of permissions. Holds the
+    /// file and the underlying error.
+    #[error("failed to read '{0}': {1}")]
+    ReadFailed(PathBuf, #[source] std::io::Error),
+
+    /// The output file of [Loader::load_file_recursively_to_path] couldn't be written.
+    /// Holds the file and the underlying error.
+    #[error("failed to write '{0}': {1}")]
+    WriteFailed(PathBuf, #[source] std::io::Error),
+
+    /// The output file of [Loader::load_file_recursively_to_path] is one of the files
+    /// read while loading. Holds the output file.
+    #[error("refusing to overwrite '{0}', which is read while loading")]
+    OverwritesInput(PathBuf),
 
-    #[error("IO Error")]
+    #[error("IO error: {0}")]
     IOError(#[from] std::io::Error),
 }
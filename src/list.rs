@@ -0,0 +1,22 @@
+/// The entries of the list file of an `include_list` directive: the trimmed lines
+/// with their 1-based line numbers, skipping blank lines and lines starting with `#`.
+pub(crate) fn entries(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+}
+
+#[cfg(test)]
+mod test_entries {
+    use super::entries;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("a.txt\nparts/b.txt\n", vec![(1, "a.txt"), (2, "parts/b.txt")])]
+    #[case("# header\n\n  a.txt  \r\n\t# indented comment\n/abs/c.txt", vec![(3, "a.txt"), (5, "/abs/c.txt")])]
+    #[case("", vec![])]
+    fn should_list_entries(#[case] text: &str, #[case] expectation: Vec<(usize, &str)>) {
+        assert_eq!(entries(text).collect::<Vec<_>>(), expectation);
+    }
+}
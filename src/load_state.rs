@@ -0,0 +1,92 @@
+use std::{collections::{BTreeMap, HashMap, HashSet}, fs, io, path::{Path, PathBuf}, sync::Mutex, time::Instant};
+
+use crate::{canonical_path::CanonicalPath, LoadStats};
+#[cfg(feature = "manifest")]
+use crate::manifest::ManifestEntry;
+
+/// The state of a single load, shared by the frames of the files it loads.
+///
+/// Every call loading files through a [crate::Loader] has its own, so loads running
+/// on several threads with the same loader don't affect each other. The optional
+/// parts are only collected while loading with the methods returning them.
+#[derive(Debug)]
+pub(crate) struct LoadState {
+    /// When the load started.
+    pub(crate) started: Instant,
+    pub(crate) stats: Mutex<Option<LoadStats>>,
+    /// The files included so far, with the file including them.
+    pub(crate) visited: Mutex<HashMap<CanonicalPath, PathBuf>>,
+    /// The paths the files included so far were referenced by, keyed by their
+    /// spelling relative to the including file.
+    pub(crate) references: Mutex<HashMap<CanonicalPath, BTreeMap<PathBuf, PathBuf>>>,
+    /// The number of includes replaced so far.
+    pub(crate) expansions: Mutex<usize>,
+    /// The ids of the include guards declared by the files read so far.
+    pub(crate) guards: Mutex<HashSet<String>>,
+    /// The canonical paths resolved so far, by the path they were resolved from, to
+    /// hit the file system only once for files referenced many times.
+    pub(crate) canonical: Mutex<HashMap<PathBuf, PathBuf>>,
+    /// The directory containing the root marker for each directory searched so far.
+    pub(crate) marked_roots: Mutex<HashMap<PathBuf, Option<PathBuf>>>,
+    /// The chain of files leading to the first error, while loading with
+    /// [crate::Loader::load_with_chain].
+    pub(crate) error_chain: Mutex<Option<Vec<PathBuf>>>,
+    /// The content of the `include_raw_bytes` includes, each standing in the text
+    /// as a marker with its index, while loading with
+    /// [crate::Loader::load_bytes_recursively].
+    pub(crate) raw_bytes: Mutex<Option<Vec<Vec<u8>>>>,
+    /// The symlink-resolved paths of the files read so far, while loading with
+    /// [crate::Loader::load_file_recursively_to_path].
+    pub(crate) read_files: Mutex<Option<HashSet<PathBuf>>>,
+    #[cfg(feature = "manifest")]
+    pub(crate) manifest: Mutex<Option<Vec<ManifestEntry>>>,
+    /// The hash of every file read so far by [crate::Loader::load_with_hashes], by
+    /// its path relative to the directory of the loaded file.
+    #[cfg(feature = "hash")]
+    pub(crate) inputs: Mutex<Option<BTreeMap<PathBuf, Vec<u8>>>>,
+}
+
+impl Default for LoadState {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            stats: Mutex::default(),
+            visited: Mutex::default(),
+            references: Mutex::default(),
+            expansions: Mutex::default(),
+            guards: Mutex::default(),
+            canonical: Mutex::default(),
+            marked_roots: Mutex::default(),
+            error_chain: Mutex::default(),
+            raw_bytes: Mutex::default(),
+            read_files: Mutex::default(),
+            #[cfg(feature = "manifest")]
+            manifest: Mutex::default(),
+            #[cfg(feature = "hash")]
+            inputs: Mutex::default(),
+        }
+    }
+}
+
+impl LoadState {
+    /// Resolve a path like [fs::canonicalize], reusing the result for paths already
+    /// resolved in this load.
+    pub(crate) fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        if let Some(canonical) = self.canonical.lock().unwrap().get(path) {
+            return Ok(canonical.to_owned());
+        }
+
+        let canonical = fs::canonicalize(path)?;
+        self.canonical.lock().unwrap().insert(path.to_owned(), canonical.clone());
+
+        Ok(canonical)
+    }
+
+    /// Remember that the file at `path` was read, while loading with
+    /// [crate::Loader::load_file_recursively_to_path].
+    pub(crate) fn record_read_file(&self, path: &Path) {
+        if let Some(read_files) = self.read_files.lock().unwrap().as_mut() {
+            read_files.insert(self.canonicalize(path).unwrap_or_else(|_| path.to_owned()));
+        }
+    }
+}
@@ -1,173 +1,4357 @@
-use crate::{canonical_path::CanonicalPath, includes::Include, Error, dependency_path::DependencyPath};
-use std::{cell::RefCell, fs, path::Path};
+use crate::{balance, chain::{self, ChainedError}, case, data, guard, directives::{self, load_with_fallback, not_found, produce_value}, section, canonical_path::{self, CanonicalPath}, encoding, frame::Frame, load_state::LoadState, includes::{render_texts, Include, IncludeKind, OutputLine, Segments}, list, options::{CycleKey, Options, RelativeBase, LITERAL_MAX_DEPTH}, raw_bytes, Error, Fragment, IncludeTree, LoadStats, LoadWarnings, RedundantPath, dependency_path::{self, DependencyPath}, diagnostics::{self, Diagnostic, Diagnostics}, syntax, variables};
+use std::{borrow::Cow, collections::HashSet, fs, io::{self, Read}, path::{Path, PathBuf}, sync::Mutex, time::SystemTime};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+#[cfg(feature = "http")]
+use crate::http;
+#[cfg(feature = "cache")]
+use crate::cache::{CacheStats, DiskCache, HashedFile};
+#[cfg(feature = "cache")]
+use std::sync::PoisonError;
+#[cfg(feature = "manifest")]
+use crate::manifest::{Manifest, ManifestEntry};
+#[cfg(feature = "hash")]
+use crate::{hash::{self, LoadResult}, tree::relative_path};
+#[cfg(feature = "hash")]
+use std::collections::BTreeMap;
+#[cfg(feature = "glob")]
+use crate::{frontmatter, glob_files};
+#[cfg(feature = "glob")]
+use std::collections::HashMap;
 
-#[derive(Default)]
+/// The file name standing in for content read by [Loader::load_from_reader].
+const READER_ROOT: &str = "<reader>";
+
+/// Loads files recursively, see [crate::load_file_recursively].
+///
+/// Use [crate::LoaderBuilder] to create a loader with non-default settings.
+///
+/// A loader can be shared by several threads loading files at the same time. Every
+/// load keeps the files, guards and counters it depends on to itself.
+#[derive(Debug, Default)]
 pub struct Loader {
-    resolution_stack: RefCell<Vec<CanonicalPath>>,
+    options: Options,
+    #[cfg(feature = "cache")]
+    cache_stats: Mutex<CacheStats>,
 }
 
-impl Loader {
-    pub(crate) fn new() -> Self {
-        Self::default()
+impl Loader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn with_options(options: Options) -> Self {
+        Self {
+            options,
+            #[cfg(feature = "cache")]
+            cache_stats: Mutex::default(),
+        }
+    }
+
+    /// Load the given file path and recursively follow references to other files
+    /// inside it, see [crate::load_file_recursively].
+    pub fn load_file_recursively<P: AsRef<Path>>(&self, path: P) -> Result<String, Error> {
+        self.get_root_text(path, &LoadState::default())
+    }
+
+    /// Like [Loader::load_file_recursively], but writes the loaded text to the file at
+    /// `out`, creating its parent directories if needed. Fails with an
+    /// [Error::OverwritesInput] without writing anything if `out` is the loaded file
+    /// or any other file read while loading it, including through symlinks.
+    pub fn load_file_recursively_to_path<P: AsRef<Path>, Q: AsRef<Path>>(&self, path: P, out: Q) -> Result<(), Error> {
+        let out = out.as_ref();
+        let state = LoadState { read_files: Mutex::new(Some(HashSet::new())), ..LoadState::default() };
+        let text = self.get_root_text(path, &state)?;
+        let read_files = state.read_files.into_inner().unwrap().unwrap_or_default();
+
+        let write_failed = |e| Error::WriteFailed(out.to_owned(), e);
+        // The target is checked before creating any directories, so a refused call
+        // leaves no trace.
+        let target = resolve_target(out).map_err(write_failed)?;
+        if read_files.contains(&target) {
+            return Err(Error::OverwritesInput(out.to_owned()));
+        }
+
+        let dir = out.parent().filter(|it| !it.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        fs::create_dir_all(dir).map_err(write_failed)?;
+        fs::write(out, text).map_err(write_failed)
+    }
+
+    /// Load several files with this loader, one after the other, returning the
+    /// results in the order of the given paths. Settings and caches are shared,
+    /// while every file is loaded as if on its own: a cycle or other error while
+    /// loading one file only fails its own result.
+    pub fn load_many<P: AsRef<Path>>(&self, origins: &[P]) -> Vec<Result<String, Error>> {
+        origins
+            .iter()
+            .map(|origin| self.load_file_recursively(origin))
+            .collect()
+    }
+
+    /// Like [Loader::load_file_recursively], but returns the output as a list of
+    /// fragments attributed to the files they originate from.
+    pub fn resolve_fragments<P: AsRef<Path>>(&self, path: P) -> Result<Vec<Fragment>, Error> {
+        self.get_fragments_for_path(path, None, &LoadState::default(), None)
+    }
+
+    /// Like [Loader::load_file_recursively], but additionally returns a [Manifest]
+    /// of all files that were read.
+    #[cfg(feature = "manifest")]
+    pub fn load_with_manifest<P: AsRef<Path>>(&self, path: P) -> Result<(String, Manifest), Error> {
+        let state = LoadState { manifest: Mutex::new(Some(vec![])), ..LoadState::default() };
+        let text = self.get_root_text(&path, &state)?;
+        let files = state.manifest.into_inner().unwrap().unwrap_or_default();
+
+        Ok((text, Manifest::new(path, files)))
+    }
+
+    /// Load the root of the given manifest again and check that exactly the same
+    /// files with the same content were read, returning an [Error::ManifestMismatch]
+    /// for the first file that differs.
+    #[cfg(feature = "manifest")]
+    pub fn verify_against(&self, manifest: &Manifest) -> Result<(), Error> {
+        let (_, actual) = self.load_with_manifest(manifest.root())?;
+        manifest.compare(&actual)
+    }
+
+    /// Like [Loader::load_file_recursively], but additionally computes hashes of the
+    /// loaded text and of the files it was loaded from, e.g. as keys for caching the
+    /// output. Unlike a manifest, these are single digests for quick comparisons.
+    #[cfg(feature = "hash")]
+    pub fn load_with_hashes<P: AsRef<Path>>(&self, path: P) -> Result<LoadResult, Error> {
+        let state = LoadState { inputs: Mutex::new(Some(BTreeMap::new())), ..LoadState::default() };
+        let text = self.get_root_text(&path, &state)?;
+        let inputs = state.inputs.into_inner().unwrap().unwrap_or_default();
+
+        Ok(LoadResult::new(text, &inputs))
+    }
+
+    /// Like [Loader::load_file_recursively], but additionally returns [LoadStats]
+    /// about the load.
+    pub fn load_with_stats<P: AsRef<Path>>(&self, path: P) -> Result<(String, LoadStats), Error> {
+        let state = LoadState { stats: Mutex::new(Some(LoadStats::default())), ..LoadState::default() };
+        let text = self.get_root_text(&path, &state)?;
+        let stats = state.stats.into_inner().unwrap().unwrap_or_default();
+
+        Ok((text, stats))
+    }
+
+    /// Like [Loader::load_file_recursively], but additionally returns the
+    /// [LoadWarnings] collected during the load, see
+    /// [crate::LoaderBuilder::warn_redundant_paths].
+    pub fn load_with_warnings<P: AsRef<Path>>(&self, path: P) -> Result<(String, LoadWarnings), Error> {
+        let state = LoadState::default();
+        let text = self.get_root_text(path, &state)?;
+
+        let references = state.references.into_inner().unwrap();
+        let mut redundant_paths: Vec<_> = references
+            .iter()
+            .filter(|(_, sources)| sources.len() > 1)
+            .map(|(path, sources)| RedundantPath::new(path.as_ref().to_owned(), sources.values().cloned().collect()))
+            .collect();
+        redundant_paths.sort_by(|a, b| a.canonical().cmp(b.canonical()));
+
+        Ok((text, LoadWarnings::new(redundant_paths)))
+    }
+
+    /// Like [Loader::load_file_recursively], but uses the given content instead of
+    /// reading the file at the path. The path must still exist, it's used to resolve
+    /// relative includes and detect cycles. The content is trimmed and transformed
+    /// like that of a file read, and included files are read as usual.
+    pub fn load_resolved<P: AsRef<Path>>(&self, content: String, path: P) -> Result<String, Error> {
+        let state = LoadState::default();
+        let frame = Frame::new(self.enter(&path, None, &state, None)?, &state, None);
+        let content = self.prepare(frame.path().source(), content)?;
+
+        self.resolve_text(&frame, content).and_then(|it| self.finish(path.as_ref(), it))
+    }
+
+    /// Like [Loader::load_file_recursively], but reads the content of the loaded file
+    /// from the reader. Relative includes are resolved against `base_dir`, and errors
+    /// concerning the content itself refer to it as `<reader>` in `base_dir`.
+    pub fn load_from_reader<R: Read, P: AsRef<Path>>(&self, mut reader: R, base_dir: P) -> Result<String, Error> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes)?;
+
+        let root = base_dir.as_ref().join(READER_ROOT);
+        let key = canonical_path::clean(&std::env::current_dir()?.join(&root));
+        let state = LoadState::default();
+        let frame = Frame::new(CanonicalPath::with_canonicalizer(&root, |_| Ok(key))?, &state, None);
+        let content = self.prepare(&root, self.decode(&root, bytes)?)?;
+
+        self.resolve_text(&frame, content).and_then(|it| self.finish(&root, it))
+    }
+
+    /// Like [Loader::load_file_recursively], but errors carry the chain of files
+    /// that led to them. When loading in parallel and several files fail, the chain
+    /// may belong to a different failure than the returned error.
+    pub fn load_with_chain<P: AsRef<Path>>(&self, path: P) -> Result<String, ChainedError> {
+        let state = LoadState { error_chain: Mutex::new(Some(vec![])), ..LoadState::default() };
+        let result = self.get_root_text(&path, &state);
+        let chain = state.error_chain.into_inner().unwrap().unwrap_or_default();
+
+        result.map_err(|error| ChainedError::new(error, chain))
+    }
+
+    /// Like [Loader::load_file_recursively], but returns bytes, with the content of
+    /// `include_raw_bytes` includes spliced in as it is. Everything else is loaded
+    /// as text and encoded as UTF-8.
+    ///
+    /// Raw bytes aren't adjusted like text: indentation, wrapping and the other
+    /// settings only apply to the text around them, and they don't count as lines
+    /// or delimiters of the loaded text. The disk cache isn't used.
+    pub fn load_bytes_recursively<P: AsRef<Path>>(&self, path: P) -> Result<Vec<u8>, Error> {
+        let path = path.as_ref();
+        let state = LoadState { raw_bytes: Mutex::new(Some(vec![])), ..LoadState::default() };
+        let text = self.record_chain(&state, self.get_text_for_path(path, None, &state, None), None, Some(path))
+            .and_then(|it| self.finish(path, it))?;
+        let parts = state.raw_bytes.into_inner().unwrap().unwrap_or_default();
+
+        Ok(raw_bytes::splice(&text, &parts))
+    }
+
+    /// Build the hierarchy of files included by the given file, without
+    /// assembling their content.
+    pub fn build_tree<P: AsRef<Path>>(&self, path: P) -> Result<IncludeTree, Error> {
+        self.get_tree_for_path(path, None, &LoadState::default(), None)
+    }
+
+    /// Render the hierarchy of files included by the given file like the `tree`
+    /// command does, see [crate::print_tree].
+    pub fn print_tree<P: AsRef<Path>>(&self, path: P) -> Result<String, Error> {
+        Ok(self.build_tree(path)?.render())
+    }
+
+    /// Walk the hierarchy of files included by the given file and collect every
+    /// issue found on the way, see [crate::diagnose].
+    pub fn diagnose<P: AsRef<Path>>(&self, path: P) -> Diagnostics {
+        // Issues reported by these settings are found by checking the syntax instead.
+        let loader = Loader::with_options(Options {
+            strict_directives: false,
+            warn_unclosed_directives: false,
+            lenient: false,
+            ..self.options.clone()
+        });
+
+        let path = path.as_ref();
+        let mut diagnostics = vec![];
+        let state = LoadState::default();
+        if let Err(error) = loader.diagnose_file(path, None, &state, None, &mut HashSet::new(), &mut diagnostics) {
+            diagnostics.push(Diagnostic::from_error(path, None, &error));
+        }
+
+        Diagnostics::new(diagnostics)
+    }
+
+    /// Usage of the configured [DiskCache] since the loader was created or the
+    /// cache was last cleared.
+    #[cfg(feature = "cache")]
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache_stats.lock().unwrap().clone()
+    }
+
+    /// Clear the configured [DiskCache] along with the cache statistics, so the
+    /// next load behaves like that of a new loader.
+    #[cfg(feature = "cache")]
+    pub fn clear_cache(&self) {
+        if let Some(cache) = &self.options.disk_cache {
+            cache.0.clear();
+        }
+        reset(&self.cache_stats);
+    }
+
+    /// Clear the loader's caches, i.e. the configured disk cache and its statistics,
+    /// so the next load behaves like that of a new loader with the same settings.
+    ///
+    /// Every load keeps the files it's resolving to itself, so a load leaves no state
+    /// behind whether it succeeds or fails, and a loader can be reused without this.
+    /// It's meant for starting over, e.g. after a panic while loading. Without the
+    /// `cache` feature a loader keeps nothing between loads and this does nothing.
+    pub fn reset(&self) {
+        #[cfg(feature = "cache")]
+        self.clear_cache();
+    }
+
+    fn get_root_text<P: AsRef<Path>>(&self, path: P, state: &LoadState) -> Result<String, Error> {
+        let path = path.as_ref();
+
+        #[cfg(feature = "cache")]
+        if let Some(cache) = &self.options.disk_cache {
+            let result = self.get_hashed_file(path, None, state, None)
+                .and_then(|file| self.assemble(file, cache.0.as_ref()));
+            return self.record_chain(state, result, None, Some(path)).and_then(|it| self.finish(path, it));
+        }
+
+        self.record_chain(state, self.get_text_for_path(path, None, state, None), None, Some(path))
+            .and_then(|it| self.finish(path, it))
+    }
+
+    /// Apply the settings concerning the assembled text of the file at `root` as a whole.
+    fn finish(&self, root: &Path, mut content: String) -> Result<String, Error> {
+        match self.options.ensure_final_newline {
+            Some(true) => {
+                let line_break = match content.ends_with("\r\n") {
+                    true => "\r\n",
+                    false => "\n",
+                };
+                content.truncate(content.trim_end_matches(['\r', '\n']).len());
+                content.push_str(line_break);
+            },
+            Some(false) => content.truncate(content.trim_end_matches(['\r', '\n']).len()),
+            None => {},
+        }
+
+        if let Some(max_line_length) = self.options.max_line_length {
+            let long_line = content
+                .lines()
+                .map(|line| line.chars().count())
+                .enumerate()
+                .find(|(_, length)| *length > max_line_length);
+            if let Some((index, length)) = long_line {
+                return Err(Error::LineTooLong(root.to_owned(), index + 1, length));
+            }
+        }
+
+        if let Some(delimiters) = self.options.validate_balanced {
+            balance::check(&content, delimiters)
+                .map_err(|(offset, description)| Error::Unbalanced(root.to_owned(), offset, description))?;
+        }
+
+        Ok(content)
+    }
+
+    /// In lenient mode, replace the result of an include whose file doesn't exist
+    /// with the failure placeholder.
+    fn lenient<T, F>(&self, include: &Include, result: Result<T, Error>, placeholder: F) -> Result<T, Error>
+    where
+        F: FnOnce(String) -> T,
+    {
+        match result {
+            Err(error) if self.options.lenient && not_found(&error, include.path()).is_some() => {
+                let text = match &self.options.failure_placeholder {
+                    Some(template) => template.replace("{path}", &include.path().to_string_lossy()),
+                    None => include.expression().to_owned(),
+                };
+                Ok(placeholder(text))
+            },
+            result => result,
+        }
+    }
+
+    /// Remember the chain of files leading to an error if it's the first one
+    /// while loading with [Loader::load_with_chain].
+    fn record_chain<T>(&self, state: &LoadState, result: Result<T, Error>, frame: Option<&Frame>, entered: Option<&Path>) -> Result<T, Error> {
+        if result.is_err() {
+            if let Some(recorded) = state.error_chain.lock().unwrap().as_mut().filter(|it| it.is_empty()) {
+                let paths = frame.into_iter().flat_map(|it| it.paths()).map(CanonicalPath::source);
+                *recorded = chain::collect(paths, entered);
+            }
+        }
+
+        result
+    }
+
+    fn get_text_for_path<P: AsRef<Path>>(
+        &self,
+        path: P,
+        via: Option<&Include>,
+        state: &LoadState,
+        parent: Option<&Frame>,
+    ) -> Result<String, Error> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!(
+            "load_file",
+            path = %path.as_ref().display(),
+            depth = parent.map_or(0, |it| it.depth() + 1),
+        ).entered();
+
+        let frame = Frame::new(self.enter(path, via, state, parent)?, state, parent);
+        let content = self.read_included(&frame, via)?;
+        #[cfg(feature = "tracing")]
+        tracing::debug!(bytes = content.len(), "read");
+
+        let includes = self.find_includes(&frame, &content, via)?;
+        #[cfg(feature = "tracing")]
+        tracing::debug!(includes = includes.iter().filter(|it| !it.is_escaped()).count(), "found includes");
+
+        let text = self.replace_includes(&frame, content, includes)?;
+        #[cfg(feature = "tracing")]
+        tracing::debug!(bytes = text.len(), "loaded");
+
+        Ok(text)
+    }
+
+    /// Replace the variables and includes in the content of the file of the frame.
+    fn resolve_text(&self, frame: &Frame, content: String) -> Result<String, Error> {
+        let content = self.substitute_variables(frame, content)?;
+        let includes = self.find_includes(frame, &content, None)?;
+        self.replace_includes(frame, content, includes)
+    }
+
+    /// Replace the given includes, last one first, in text attributed to the file
+    /// of the frame.
+    fn replace_includes(&self, frame: &Frame, content: String, includes: Vec<Include>) -> Result<String, Error> {
+        let texts = self.produce_texts(frame, &includes)?;
+        let texts = render_texts(&content, &includes, texts, self.options.escaped_output)?;
+        let mut content = Segments::new(content);
+        for (include, text) in includes.iter().zip(texts.into_iter().rev()) {
+            include.replace(&mut content, self.options.escaped_output, text);
+        }
+
+        Ok(content.into_string())
+    }
+
+    /// Produce the texts of the given includes, last one first, that aren't escaped.
+    /// They're produced and returned in the order they appear in, so that files are
+    /// loaded in document order, e.g. for include guards, and the first failing
+    /// include is reported.
+    fn produce_texts(&self, frame: &Frame, includes: &[Include]) -> Result<Vec<Option<String>>, Error> {
+        #[cfg(feature = "parallel")]
+        if self.options.parallel {
+            // Worker threads don't inherit the current subscriber and span, so files
+            // loaded on them are explicitly nested under the including file's span.
+            #[cfg(feature = "tracing")]
+            let (dispatch, span) = (tracing::dispatcher::get_default(Clone::clone), tracing::Span::current());
+            let texts: Vec<Option<Result<String, Error>>> = includes
+                .par_iter()
+                .rev()
+                .map(|include| {
+                    let produce = || (!include.is_escaped()).then(|| self.produce_text(include, frame));
+                    #[cfg(feature = "tracing")]
+                    let produce = || tracing::dispatcher::with_default(&dispatch, || span.in_scope(produce));
+                    produce()
+                })
+                .collect();
+
+            return texts.into_iter().map(Option::transpose).collect();
+        }
+
+        includes
+            .iter()
+            .rev()
+            .map(|include| (!include.is_escaped()).then(|| self.produce_text(include, frame)).transpose())
+            .collect()
+    }
+
+    fn get_fragments_for_path<P: AsRef<Path>>(
+        &self,
+        path: P,
+        via: Option<&Include>,
+        state: &LoadState,
+        parent: Option<&Frame>,
+    ) -> Result<Vec<Fragment>, Error> {
+        let frame = Frame::new(self.enter(path, via, state, parent)?, state, parent);
+
+        let content = self.read_included(&frame, via)?;
+        let includes = self.find_includes(&frame, &content, via)?;
+        self.replace_fragments(&frame, content, includes)
+    }
+
+    /// Like [Loader::replace_includes], but keeps the produced text in separate fragments.
+    fn replace_fragments(&self, frame: &Frame, mut content: String, includes: Vec<Include>) -> Result<Vec<Fragment>, Error> {
+        let path = frame.path();
+        let produced = includes
+            .iter()
+            .rev()
+            .map(|include| (!include.is_escaped()).then(|| self.produce_fragments(include, frame)).transpose())
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut line = OutputLine::new(&content, self.options.escaped_output);
+        let rendered = includes
+            .iter()
+            .rev()
+            .zip(produced)
+            .map(|(include, fragments)| {
+                let prefix = line.advance(include);
+                let fragments = fragments.map(|it| include.render_fragments(it, prefix)).transpose()?;
+                for fragment in fragments.iter().flatten() {
+                    line.push(fragment.text());
+                }
+                Ok(fragments)
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        let mut reversed_tail = vec![];
+        for (include, fragments) in includes.iter().zip(rendered.into_iter().rev()) {
+            include.replace_fragments(
+                path.source(),
+                &mut content,
+                &mut reversed_tail,
+                self.options.escaped_output,
+                fragments,
+            );
+        }
+
+        let fragments = std::iter::once(Fragment::new(path.source(), content))
+            .chain(reversed_tail.into_iter().rev())
+            .filter(|it| !it.text().is_empty())
+            .collect();
+
+        Ok(fragments)
+    }
+
+    fn get_tree_for_path<P: AsRef<Path>>(
+        &self,
+        path: P,
+        via: Option<&Include>,
+        state: &LoadState,
+        parent: Option<&Frame>,
+    ) -> Result<IncludeTree, Error> {
+        let frame = Frame::new(self.enter(path, via, state, parent)?, state, parent);
+
+        let content = self.read_included(&frame, via)?;
+        let children = self.find_includes(&frame, &content, via)?
+            .iter()
+            .rev()
+            .filter(|it| !it.is_escaped() && self.is_emitted(it, &frame))
+            .map(|it| match it.kind() {
+                IncludeKind::File => self.get_tree_for_include(it, &frame).map(|it| vec![it]),
+                kind if kind.is_multi_file() => self.expand_files(it, &frame)?.1
+                    .iter()
+                    .rev()
+                    .map(|it| self.get_tree_for_include(it, &frame))
+                    .collect(),
+                IncludeKind::RawBytes if !data::is_data_uri(&it.path().to_string_lossy()) => {
+                    Ok(vec![IncludeTree::new(it.path(), vec![])])
+                },
+                _ => Ok(vec![]),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(IncludeTree::new(frame.path().source(), children.into_iter().flatten().collect()))
+    }
+
+    fn get_tree_for_include(&self, include: &Include, frame: &Frame) -> Result<IncludeTree, Error> {
+        load_with_fallback(include, |path| match self.get_tree_for_path(path, Some(include), frame.state(), Some(frame)) {
+            Err(Error::CyclicDependency { .. }) if self.options.lenient => Ok(IncludeTree::cycle(path)),
+            result => result,
+        })
+    }
+
+    /// Collect the issues of the file and everything it includes into `diagnostics`,
+    /// failing only if the file itself can't be entered or read. Files already
+    /// diagnosed, tracked in `seen` by path and section, aren't diagnosed again.
+    fn diagnose_file(
+        &self,
+        path: &Path,
+        via: Option<&Include>,
+        state: &LoadState,
+        parent: Option<&Frame>,
+        seen: &mut HashSet<(CanonicalPath, Option<String>)>,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) -> Result<(), Error> {
+        let frame = Frame::new(self.enter(path, via, state, parent)?, state, parent);
+        let content = self.read_included(&frame, via)?;
+        if !seen.insert((frame.path().clone(), via.and_then(Include::section).map(str::to_owned))) {
+            return Ok(());
+        }
+
+        let source = frame.path().source();
+        let issues = syntax::check(&content);
+        for issue in &issues {
+            diagnostics.push(Diagnostic::from_syntax(source, &content, issue));
+        }
+
+        let mut invalid = vec![];
+        let includes = match self.find_valid_includes(&frame, &content, via, Some(&mut invalid)) {
+            Ok(includes) => includes,
+            Err(error) => {
+                diagnostics.push(Diagnostic::from_error(source, None, &error));
+                return Ok(());
+            },
+        };
+        // Directives the syntax check already reported aren't reported twice.
+        for (offset, error) in invalid.iter().filter(|(offset, _)| !issues.iter().any(|it| it.range().start == *offset)) {
+            diagnostics.push(Diagnostic::from_error(source, Some(diagnostics::position(&content, *offset)), error));
+        }
+
+        // Includes reported by the syntax check, like those with an empty path, aren't followed.
+        let is_diagnosed = |include: &Include| {
+            !include.is_escaped()
+                && !issues.iter().any(|it| it.range().start == include.range().start)
+                && self.is_emitted(include, &frame)
+        };
+        for include in includes.iter().rev().filter(|it| is_diagnosed(it)) {
+            let position = Some(diagnostics::position(&content, include.range().start));
+            let files = match include.kind() {
+                IncludeKind::File => vec![include.clone()],
+                kind if kind.is_multi_file() => match self.expand_files(include, &frame) {
+                    Ok((_, files)) => files.into_iter().rev().collect(),
+                    Err(error) => {
+                        diagnostics.push(Diagnostic::from_error(source, position, &error));
+                        continue;
+                    },
+                },
+                _ => vec![],
+            };
+            for file in &files {
+                let result = load_with_fallback(file, |path| self.diagnose_file(path, Some(file), state, Some(&frame), seen, diagnostics));
+                if let Err(error) = result {
+                    diagnostics.push(Diagnostic::from_error(source, position, &error));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read the file and everything it includes, computing the hashes used as
+    /// [DiskCache] keys.
+    #[cfg(feature = "cache")]
+    fn get_hashed_file<P: AsRef<Path>>(
+        &self,
+        path: P,
+        via: Option<&Include>,
+        state: &LoadState,
+        parent: Option<&Frame>,
+    ) -> Result<HashedFile, Error> {
+        let frame = Frame::new(self.enter(path, via, state, parent)?, state, parent);
+
+        let content = self.read_included(&frame, via)?;
+        let includes = self.find_includes(&frame, &content, via)?;
+        let children = self.hash_includes(&includes, &frame)?;
+
+        Ok(HashedFile::new(frame.path().source(), content, includes, children))
+    }
+
+    /// Read the files of the includes, see [Loader::get_hashed_file].
+    #[cfg(feature = "cache")]
+    fn hash_includes(&self, includes: &[Include], frame: &Frame) -> Result<Vec<Option<HashedFile>>, Error> {
+        for include in includes.iter().filter(|it| !it.is_escaped()) {
+            self.count_expansion(include, frame)?;
+        }
+
+        let mut hashed = includes
+            .iter()
+            .rev()
+            .map(|include| match include.kind() {
+                _ if include.is_escaped() || !self.is_emitted(include, frame) => Ok(None),
+                IncludeKind::File => self.record_chain(
+                    frame.state(),
+                    self.lenient(
+                        include,
+                        load_with_fallback(include, |path| self.get_hashed_file(path, Some(include), frame.state(), Some(frame))),
+                        |placeholder| HashedFile::new(include.path(), placeholder, vec![], vec![]),
+                    ),
+                    Some(frame),
+                    Some(include.path()),
+                ).map(Some),
+                kind if kind.is_multi_file() => {
+                    let (content, includes) = self.record_chain(frame.state(), self.expand_files(include, frame), Some(frame), Some(include.path()))?;
+                    let children = self.hash_includes(&includes, frame)?;
+                    Ok(Some(HashedFile::new(include.path(), content, includes, children)))
+                },
+                IncludeKind::RawBytes => self.record_chain(frame.state(), self.produce_raw_bytes(include, frame), Some(frame), Some(include.path()))
+                    .map(|content| Some(HashedFile::new(include.path(), content, vec![], vec![]))),
+                // Values like those of environment variables and commands are part of
+                // the hash, so the cached text changes along with them.
+                _ => self.record_chain(frame.state(), self.produce_value(include, frame.path().source()), Some(frame), None)
+                    .map(|value| Some(HashedFile::new(include.path(), value, vec![], vec![]))),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        hashed.reverse();
+
+        Ok(hashed)
+    }
+
+    /// Assemble the text of a hashed file, taking it and the texts of its
+    /// includes from the cache where available.
+    #[cfg(feature = "cache")]
+    fn assemble(&self, file: HashedFile, cache: &dyn DiskCache) -> Result<String, Error> {
+        let cached = cache.get(&file.hash);
+        self.cache_stats.lock().unwrap().record_lookup(cached.is_some());
+        if let Some(text) = cached {
+            return Ok(text);
+        }
+
+        let HashedFile { path, content, includes, children, hash } = file;
+        let texts = includes
+            .iter()
+            .zip(children)
+            .rev()
+            .map(|(include, child)| (!include.is_escaped()).then(|| match child {
+                Some(child) => self.assemble(child, cache),
+                None => self.produce_value(include, &path),
+            }).transpose())
+            .collect::<Result<Vec<_>, Error>>()?;
+        let texts = render_texts(&content, &includes, texts, self.options.escaped_output)?;
+        let mut content = Segments::new(content);
+        for (include, text) in includes.iter().zip(texts.into_iter().rev()) {
+            include.replace(&mut content, self.options.escaped_output, text);
+        }
+        let content = content.into_string();
+        cache.put(&hash, content.clone());
+        self.cache_stats.lock().unwrap().record_entry();
+
+        Ok(content)
+    }
+
+    fn produce_text(&self, include: &Include, frame: &Frame) -> Result<String, Error> {
+        self.count_expansion(include, frame)?;
+        match include.kind() {
+            IncludeKind::File if self.is_emitted(include, frame) => self.record_chain(
+                frame.state(),
+                self.lenient(
+                    include,
+                    load_with_fallback(include, |path| self.get_text_for_path(path, Some(include), frame.state(), Some(frame))),
+                    |placeholder| placeholder,
+                ),
+                Some(frame),
+                Some(include.path()),
+            ),
+            kind if kind.is_multi_file() && self.is_emitted(include, frame) => self.record_chain(
+                frame.state(),
+                self.expand_files(include, frame).and_then(|(content, includes)| self.replace_includes(frame, content, includes)),
+                Some(frame),
+                Some(include.path()),
+            ),
+            IncludeKind::RawBytes if self.is_emitted(include, frame) => {
+                self.record_chain(frame.state(), self.produce_raw_bytes(include, frame), Some(frame), Some(include.path()))
+            },
+            _ => self.record_chain(frame.state(), self.produce_value(include, frame.path().source()), Some(frame), None),
+        }
+    }
+
+    fn produce_fragments(&self, include: &Include, frame: &Frame) -> Result<Vec<Fragment>, Error> {
+        self.count_expansion(include, frame)?;
+        match include.kind() {
+            IncludeKind::File if self.is_emitted(include, frame) => self.lenient(
+                include,
+                load_with_fallback(include, |path| self.get_fragments_for_path(path, Some(include), frame.state(), Some(frame))),
+                |placeholder| vec![Fragment::new(frame.path().source(), placeholder)],
+            ),
+            kind if kind.is_multi_file() && self.is_emitted(include, frame) => {
+                let (content, includes) = self.expand_files(include, frame)?;
+                self.replace_fragments(frame, content, includes)
+            },
+            IncludeKind::RawBytes if self.is_emitted(include, frame) => {
+                Ok(vec![Fragment::new(include.path(), self.produce_raw_bytes(include, frame)?)])
+            },
+            _ => Ok(vec![Fragment::new(frame.path().source(), self.produce_value(include, frame.path().source())?)]),
+        }
+    }
+
+    /// Produce the content of an `include_raw_bytes` include. While loading with
+    /// [Loader::load_bytes_recursively] the bytes are kept aside and a marker
+    /// standing in for them is returned, otherwise they're decoded like a file.
+    fn produce_raw_bytes(&self, include: &Include, frame: &Frame) -> Result<String, Error> {
+        let uri = include.path().to_string_lossy();
+        let (path, bytes) = match data::is_data_uri(&uri) {
+            true => (include.path().to_owned(), data::decode_bytes(&uri)?),
+            false => {
+                let frame = Frame::new(self.enter(include.path(), Some(include), frame.state(), Some(frame))?, frame.state(), Some(frame));
+                (frame.path().source().to_owned(), self.read_recorded(&frame)?)
+            },
+        };
+
+        match frame.state().raw_bytes.lock().unwrap().as_mut() {
+            Some(parts) => {
+                parts.push(bytes);
+                Ok(raw_bytes::marker(parts.len() - 1))
+            },
+            None => self.decode(&path, bytes),
+        }
+    }
+
+    /// Turn an include of several files into includes of a text separating them with
+    /// line breaks, see [Loader::concatenate].
+    fn expand_files(&self, include: &Include, frame: &Frame) -> Result<(String, Vec<Include>), Error> {
+        match include.kind() {
+            #[cfg(feature = "glob")]
+            IncludeKind::Glob => self.expand_glob(include, frame),
+            _ => self.expand_list(include, frame),
+        }
+    }
+
+    /// Find the files listed in the list file of an `include_list`, resolved against
+    /// its directory, and concatenate them. Their includes refer to the lines of the
+    /// list file they're listed on. The list file is read like an included file.
+    fn expand_list(&self, include: &Include, frame: &Frame) -> Result<(String, Vec<Include>), Error> {
+        let list_frame = Frame::new(self.enter(include.path(), Some(include), frame.state(), Some(frame))?, frame.state(), Some(frame));
+        let list = list_frame.path().source();
+        let bytes = self.read_recorded(&list_frame)?;
+        let text = self.decode(list, bytes)?;
+
+        let document_root = frame.root().source().parent();
+        let entries: Vec<(usize, &str)> = list::entries(&text).collect();
+        let paths = entries
+            .iter()
+            .map(|(_, entry)| list.get_dependency_path(entry, RelativeBase::SourceFile, None, document_root))
+            .collect();
+
+        Ok(self.concatenate(frame, paths, include.separator(), |index, file| {
+            let (line, entry) = entries[index];
+            file.with_expression(entry, line).with_list_entry(list, line)
+        }))
+    }
+
+    /// Find the files matching the pattern of a glob include and concatenate them.
+    #[cfg(feature = "glob")]
+    fn expand_glob(&self, include: &Include, frame: &Frame) -> Result<(String, Vec<Include>), Error> {
+        let mut paths = glob_files::expand(include.path(), include.glob_order())?;
+        if let Some(condition) = include.filter() {
+            frontmatter::matches(condition, &HashMap::new())?;
+            let mut matching = vec![];
+            for path in paths {
+                // Only read here, the files matching are entered when they're included.
+                let bytes = self.read_bytes(&self.enter(&path, Some(include), frame.state(), None)?)?;
+                let content = self.decode(&path, bytes)?;
+                let is_match = match frontmatter::parse(&content) {
+                    Some(fields) => frontmatter::matches(condition, &fields)?,
+                    None => self.options.include_without_frontmatter,
+                };
+                if is_match {
+                    matching.push(path);
+                }
+            }
+            paths = matching;
+        }
+
+        Ok(self.concatenate(frame, paths, include.separator(), |_, file| {
+            file.with_expression(include.expression(), include.line())
+        }))
+    }
+
+    /// Turn the files into includes of a text putting `separator` between them, as
+    /// if referenced from the file of the frame, with `customize` adjusting the
+    /// include of the file at the given index. Returns the text and the includes,
+    /// last one first.
+    fn concatenate<F>(&self, frame: &Frame, paths: Vec<PathBuf>, separator: &str, mut customize: F) -> (String, Vec<Include>)
+    where
+        F: FnMut(usize, Include) -> Include,
+    {
+        let mut content = String::new();
+        let mut includes = vec![];
+        for (index, path) in paths.into_iter().enumerate() {
+            if index > 0 {
+                content.push_str(separator);
+            }
+            let start = content.len();
+            // Stands in for the file's content, replaced by the include.
+            content.push('*');
+
+            let file = Include::new(start..content.len(), path, start..start, None)
+                .with_dedent(self.options.dedent_included);
+            let file = customize(index, file);
+            includes.push(match &self.options.annotate_boundaries {
+                Some(style) => directives::annotate(file, Some(frame.path().source()), style),
+                None => file,
+            });
+        }
+        includes.reverse();
+
+        (content, includes)
+    }
+
+    /// Count an include being replaced, failing if that exceeds the configured budget.
+    /// Every repetition of an `include_repeat` counts, and includes that aren't
+    /// emitted don't.
+    fn count_expansion(&self, include: &Include, frame: &Frame) -> Result<(), Error> {
+        let Some(max_expansions) = self.options.max_expansions else {
+            return Ok(());
+        };
+        if !self.is_emitted(include, frame) {
+            return Ok(());
+        }
+
+        let mut expansions = frame.state().expansions.lock().unwrap();
+        *expansions = expansions.saturating_add(include.repeat().unwrap_or(1));
+        match *expansions > max_expansions {
+            true => Err(Error::ExpansionBudgetExceeded(max_expansions)),
+            false => Ok(()),
+        }
+    }
+
+    /// Whether an include produces anything, see [directives::is_emitted]. An
+    /// `include_if_newer` is only emitted if its file was modified after the loaded
+    /// root file, or either modification time is unavailable.
+    fn is_emitted(&self, include: &Include, frame: &Frame) -> bool {
+        let is_newer = || match (self.modified(include.path()), self.modified(frame.root().source())) {
+            (Some(modified), Some(root_modified)) => modified > root_modified,
+            _ => true,
+        };
+
+        directives::is_emitted(include) && (!include.is_if_newer() || is_newer())
+    }
+
+    /// The modification time of the file at the path, resolved like the path of an
+    /// include. Overridden files and files that aren't read from the file system
+    /// have none.
+    fn modified(&self, path: &Path) -> Option<SystemTime> {
+        let path = self.apply_extensions(&self.resolve_case(path).ok()?).ok()?;
+        if self.override_key(&path).is_some() || self.options.file_source.is_some() || self.is_remote(&path) {
+            return None;
+        }
+
+        fs::metadata(path).and_then(|it| it.modified()).ok()
+    }
+
+    /// Produce the text of an include that doesn't refer to a file, running commands
+    /// in the directory of the file at `source` if allowed.
+    fn produce_value(&self, include: &Include, source: &Path) -> Result<String, Error> {
+        match include.kind() {
+            // Files are only left to this when they aren't emitted.
+            IncludeKind::File if include.is_if_newer() => Ok(String::new()),
+            IncludeKind::Command if self.options.allow_commands => {
+                run_command(&include.path().to_string_lossy(), source.parent().unwrap_or(Path::new("")))
+            },
+            _ => produce_value(include),
+        }
+    }
+
+    /// Resolve the path of a file about to be loaded, failing if it's already being
+    /// loaded by the given frame or its ancestors. `via` is the include that
+    /// referenced the path, if any.
+    fn enter<P: AsRef<Path>>(&self, path: P, via: Option<&Include>, state: &LoadState, parent: Option<&Frame>) -> Result<CanonicalPath, Error> {
+        in_list_entry(via, self.enter_path(path.as_ref(), via, state, parent))
+    }
+
+    fn enter_path(&self, path: &Path, via: Option<&Include>, state: &LoadState, parent: Option<&Frame>) -> Result<CanonicalPath, Error> {
+        let path = self.resolve_case(path)?;
+        let path = self.apply_extensions(&path)?;
+        if self.options.case_insensitive && self.options.file_source.is_none() && !self.is_remote(&path) {
+            if let Some(actual) = case::find_case_mismatch(&path)? {
+                return Err(Error::CaseMismatch(path, actual));
+            }
+        }
+
+        let path = match (self.override_key(&path), &self.options.file_source, self.options.cycle_detection) {
+            (Some(key), _, _) => CanonicalPath::with_canonicalizer(path, |_| Ok(key))?,
+            #[cfg(feature = "http")]
+            _ if self.is_remote(&path) => CanonicalPath::with_canonicalizer(path, http::normalize)?,
+            (None, Some(source), _) => CanonicalPath::with_canonicalizer(path, |it| source.0.canonicalize(it))?,
+            (None, None, CycleKey::Canonical) => match &self.options.canonicalizer {
+                Some(canonicalizer) => CanonicalPath::with_canonicalizer(path, |it| (canonicalizer.0)(it))?,
+                None => CanonicalPath::with_canonicalizer(path, |it| state.canonicalize(it))?,
+            },
+            (None, None, CycleKey::Literal) => CanonicalPath::literal(path)?,
+        };
+        let path = match self.options.case_insensitive {
+            true => path.into_lowercase(),
+            false => path,
+        };
+        // Remote files aren't located in any directory.
+        if let Some(root) = self.options.restrict_to.as_ref().filter(|_| !self.is_remote(path.source())) {
+            let cwd = std::env::current_dir()?;
+            let canonical_root = || state.canonicalize(root).unwrap_or_else(|_| canonical_path::clean(&cwd.join(root)));
+            let (resolved, root) = match (self.override_key(path.source()), &self.options.file_source) {
+                // Files that aren't read from the file system are compared by the path
+                // they're referenced by, with `.` and `..` resolved lexically.
+                (Some(key), _) => (key, canonical_root()),
+                (None, Some(_)) => (canonical_path::clean(&cwd.join(path.source())), canonical_path::clean(&cwd.join(root))),
+                // Symlinks are resolved even if the path isn't keyed on its resolved form,
+                // so they can't point out of the root.
+                (None, None) => (state.canonicalize(path.as_ref()).unwrap_or_else(|_| path.as_ref().to_owned()), canonical_root()),
+            };
+            if !resolved.starts_with(&root) {
+                return Err(Error::OutsideRoot(path.source().to_owned(), root));
+            }
+        }
+        // Cycles through symlinks aren't detected with literal keys, but they end here.
+        let default_max_depth = (self.options.cycle_detection == CycleKey::Literal).then_some(LITERAL_MAX_DEPTH);
+        if let Some(max_depth) = self.options.max_depth.or(default_max_depth) {
+            if parent.map_or(0, |it| it.depth() + 1) > max_depth {
+                return Err(Error::MaxDepthExceeded(path.source().to_owned(), max_depth));
+            }
+        }
+        if let Some(parent) = parent.filter(|it| it.contains(&path)) {
+            return Err(Error::CyclicDependency {
+                from: parent.path().source().to_owned(),
+                to: path.source().to_owned(),
+                directive: via.map(|it| it.expression().to_owned()).unwrap_or_default(),
+                line: via.map(Include::line).unwrap_or_default(),
+                chain: parent.cycle(&path),
+            });
+        }
+
+        if let Some(parent) = parent.filter(|_| self.options.forbid_duplicate_includes) {
+            let mut visited = state.visited.lock().unwrap();
+            match visited.get(&path) {
+                Some(first) => return Err(Error::DuplicateInclude(
+                    path.source().to_owned(),
+                    first.to_owned(),
+                    parent.path().source().to_owned(),
+                )),
+                None => {
+                    visited.insert(path.clone(), parent.path().source().to_owned());
+                },
+            }
+        }
+        if let Some(max_duration) = self.options.max_duration {
+            if state.started.elapsed() > max_duration {
+                return Err(Error::Timeout(max_duration));
+            }
+        }
+        if let Some(parent) = parent.filter(|_| self.options.warn_redundant_paths) {
+            let source = path.source();
+            let spelling = parent.path().source().parent()
+                .and_then(|dir| source.strip_prefix(dir).ok())
+                .unwrap_or(source);
+            state.references.lock().unwrap()
+                .entry(path.clone())
+                .or_default()
+                .entry(spelling.to_owned())
+                .or_insert_with(|| source.to_owned());
+        }
+
+        Ok(path)
+    }
+
+    /// Find the file a path that doesn't exist refers to when ignoring case, if
+    /// enabled. Other paths are returned as is.
+    fn resolve_case(&self, path: &Path) -> Result<PathBuf, Error> {
+        let applies = self.options.resolve_case_insensitive && self.options.file_source.is_none();
+        if !applies || self.is_remote(path) || self.exists(path) {
+            return Ok(path.to_owned());
+        }
+
+        Ok(case::resolve_case_insensitive(path)?.unwrap_or_else(|| path.to_owned()))
+    }
+
+    /// Find the first existing path among the given path and the path with each
+    /// of the configured extensions appended.
+    fn apply_extensions(&self, path: &Path) -> Result<PathBuf, Error> {
+        if self.options.try_extensions.is_empty() || self.is_remote(path) || self.exists(path) {
+            return Ok(path.to_owned());
+        }
+
+        let candidates: Vec<PathBuf> = self.options.try_extensions
+            .iter()
+            .map(|extension| {
+                let mut candidate = path.as_os_str().to_owned();
+                candidate.push(".");
+                candidate.push(extension);
+                PathBuf::from(candidate)
+            })
+            .collect();
+
+        match candidates.iter().find(|it| self.exists(it)) {
+            Some(candidate) => Ok(candidate.to_owned()),
+            None => Err(Error::FilesNotFound(std::iter::once(path.to_owned()).chain(candidates).collect())),
+        }
+    }
+
+    /// Whether the path is a URL to be fetched over HTTP.
+    fn is_remote(&self, _path: &Path) -> bool {
+        #[cfg(feature = "http")]
+        if self.options.allow_http {
+            return http::is_url(_path);
+        }
+
+        false
+    }
+
+    /// The key of the override for the path, if there is one.
+    fn override_key(&self, path: &Path) -> Option<PathBuf> {
+        if self.options.overrides.is_empty() {
+            return None;
+        }
+
+        let key = canonical_path::clean(&self.options.override_dir.join(path));
+        self.options.overrides.contains_key(&key).then_some(key)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        if self.override_key(path).is_some() {
+            return true;
+        }
+
+        match &self.options.file_source {
+            Some(source) => source.0.canonicalize(path).is_ok(),
+            None => path.exists(),
+        }
+    }
+
+    fn check_size(&self, path: &CanonicalPath, size: u64) -> Result<(), Error> {
+        match self.options.max_file_bytes {
+            Some(max_file_bytes) if size > max_file_bytes => Err(Error::FileTooLarge(path.source().to_owned(), max_file_bytes)),
+            _ => Ok(()),
+        }
+    }
+
+    /// The number of bytes to read at most, one past the configured maximum file
+    /// size so larger files are recognized without reading them completely.
+    fn read_limit(&self) -> u64 {
+        self.options.max_file_bytes.map_or(u64::MAX, |it| it.saturating_add(1))
+    }
+
+    fn read_bytes(&self, path: &CanonicalPath) -> Result<Vec<u8>, Error> {
+        if let Some(content) = self.options.overrides.get(path.as_ref()) {
+            self.check_size(path, content.len() as u64)?;
+            return Ok(content.as_bytes().to_vec());
+        }
+
+        #[cfg(feature = "http")]
+        if self.is_remote(path.as_ref()) {
+            let timeout = self.options.http_timeout.unwrap_or(http::DEFAULT_TIMEOUT);
+            let bytes = http::fetch(path.as_ref(), timeout, self.read_limit())?;
+            self.check_size(path, bytes.len() as u64)?;
+            return Ok(bytes);
+        }
+
+        match &self.options.file_source {
+            Some(source) => {
+                let mut bytes = vec![];
+                source.0.open(path.as_ref())
+                    .and_then(|it| it.take(self.read_limit()).read_to_end(&mut bytes))
+                    .map_err(|e| canonical_path::to_error(path.source(), e))?;
+                self.check_size(path, bytes.len() as u64)?;
+                Ok(bytes)
+            },
+            None => {
+                let metadata = fs::metadata(path).map_err(|e| canonical_path::to_error(path.source(), e))?;
+                if metadata.is_dir() {
+                    return Err(Error::IsADirectory(path.source().to_owned()));
+                }
+                self.check_size(path, metadata.len())?;
+                fs::read(path).map_err(|e| canonical_path::to_error(path.source(), e))
+            },
+        }
+    }
+
+    /// Read the file of the frame, limited to the section selected by the include.
+    fn read_included(&self, frame: &Frame, via: Option<&Include>) -> Result<String, Error> {
+        let content = in_list_entry(via, self.read(frame))?;
+        let content = match guard::find(&content).filter(|_| via.and_then(Include::escape).is_none()) {
+            Some((id, _)) if !frame.state().guards.lock().unwrap().insert(id.to_owned()) => return Ok(String::new()),
+            Some((_, rest)) => rest.to_owned(),
+            None => content,
+        };
+        let content = match via.and_then(Include::section) {
+            Some(name) => {
+                let (start, end) = match &self.options.region_markers {
+                    Some((start, end)) => (start.as_str(), end.as_str()),
+                    None => section::DEFAULT_MARKERS,
+                };
+                section::extract(&content, name, start, end)
+                    .ok_or_else(|| Error::SectionNotFound(frame.path().source().to_owned(), name.to_owned()))?
+            },
+            None => content,
+        };
+
+        match via.and_then(Include::escape) {
+            Some(_) => Ok(content),
+            None => self.substitute_variables(frame, content),
+        }
+    }
+
+    /// Replace the variables in the content of the file of the frame, if any are set.
+    fn substitute_variables(&self, frame: &Frame, content: String) -> Result<String, Error> {
+        let Some(variables) = &self.options.variables else {
+            return Ok(content);
+        };
+
+        match variables::substitute(&content, variables) {
+            Ok(Cow::Borrowed(_)) => Ok(content),
+            Ok(Cow::Owned(substituted)) => Ok(substituted),
+            Err((offset, name)) => Err(Error::UndefinedVariable(
+                frame.path().source().to_owned(),
+                diagnostics::position(&content, offset).0,
+                name,
+            )),
+        }
+    }
+
+    fn read(&self, frame: &Frame) -> Result<String, Error> {
+        let path = frame.path();
+        let bytes = self.read_recorded(frame)?;
+
+        self.prepare(path.source(), self.decode(path.source(), bytes)?)
+    }
+
+    /// Adjust the decoded content of the file at `path` before looking for
+    /// directives in it, trimming trailing whitespace and applying the transform.
+    fn prepare(&self, path: &Path, mut content: String) -> Result<String, Error> {
+        if self.options.trim_trailing_whitespace {
+            content = trim_trailing_whitespace(&content);
+        }
+
+        match &self.options.transform {
+            Some(transform) => (transform.0)(path, content),
+            None => Ok(content),
+        }
+    }
+
+    /// Read the file of the frame, recording it for the stats, the manifest or the
+    /// hashes of the current load.
+    fn read_recorded(&self, frame: &Frame) -> Result<Vec<u8>, Error> {
+        let path = frame.path();
+        let bytes = self.read_bytes(path)?;
+        frame.state().record_read_file(path.as_ref());
+
+        if let Some(stats) = frame.state().stats.lock().unwrap().as_mut() {
+            stats.record_read(bytes.len(), frame.depth());
+            if frame.depth() == 0 {
+                stats.record_root(path.as_ref());
+            }
+        }
+
+        #[cfg(feature = "manifest")]
+        if let Some(files) = frame.state().manifest.lock().unwrap().as_mut() {
+            if !files.iter().any(|it| it.path() == path.source()) {
+                files.push(ManifestEntry::new(path.source(), &bytes));
+            }
+        }
+
+        #[cfg(feature = "hash")]
+        if let Some(inputs) = frame.state().inputs.lock().unwrap().as_mut() {
+            let root_dir = frame.root().source().parent().unwrap_or(Path::new(""));
+            inputs.insert(relative_path(path.source(), root_dir), hash::digest(&bytes));
+        }
+
+        Ok(bytes)
+    }
+
+    fn decode(&self, path: &Path, bytes: Vec<u8>) -> Result<String, Error> {
+        #[cfg(feature = "encoding")]
+        if let Some(label) = &self.options.encoding {
+            return encoding::decode_as(path, bytes, label, self.options.lossy_decoding);
+        }
+
+        encoding::decode_with(path, bytes, self.options.lossy_decoding)
+    }
+
+    /// Find the includes in `text`, the content of the file of the frame. Files
+    /// included as data by `via`, e.g. by `include_escaped`, have none.
+    fn find_includes(
+        &self,
+        frame: &Frame,
+        text: &str,
+        via: Option<&Include>,
+    ) -> Result<Vec<Include>, Error> {
+        self.find_valid_includes(frame, text, via, None)
+    }
+
+    /// Like [Loader::find_includes], but collects the errors of directives with
+    /// invalid arguments into `invalid` and leaves them out, if given, see
+    /// [directives::find_includes].
+    fn find_valid_includes(
+        &self,
+        frame: &Frame,
+        text: &str,
+        via: Option<&Include>,
+        invalid: Option<&mut Vec<(usize, Error)>>,
+    ) -> Result<Vec<Include>, Error> {
+        if via.is_some_and(|it| it.escape().is_some()) {
+            return Ok(vec![]);
+        }
+
+        let source_path = frame.path();
+        let document_root = frame.root().source().parent();
+        let marked_root = self.options.root_marker.as_ref().and_then(|marker| {
+            let dir = source_path.as_ref().parent()?;
+            dependency_path::find_marked_root(dir, marker, &mut frame.state().marked_roots.lock().unwrap())
+        });
+
+        // The root file resolves against the overridden directory as if it was located there.
+        let origin = match (&self.options.base_dir_override, frame.depth()) {
+            (Some(dir), 0) => dir.join(source_path.source().file_name().unwrap_or_default()),
+            _ => source_path.as_ref().to_owned(),
+        };
+
+        let layered_roots: Vec<PathBuf> = self.options.layered_roots
+            .iter()
+            .map(|root| fs::canonicalize(root).unwrap_or_else(|_| canonical_path::clean(root)))
+            .collect();
+
+        directives::find_includes(text, Some(source_path.source()), &self.options, invalid, |path| {
+            if self.options.expand_tilde {
+                if let Some(expanded) = dependency_path::expand_tilde(path, dependency_path::home_dir())? {
+                    return Ok(expanded);
+                }
+            }
+            match &self.options.root_marker {
+                Some(marker) if marked_root.is_none() && dependency_path::is_marked(path) => {
+                    Err(Error::RootMarkerNotFound(source_path.source().to_owned(), marker.to_owned()))
+                },
+                _ => {
+                    let resolved = origin.get_dependency_path(
+                        path,
+                        self.options.relative_to,
+                        marked_root.as_deref(),
+                        document_root,
+                    );
+                    let is_relative = Path::new(path).is_relative()
+                        && !dependency_path::is_marked(path)
+                        && !dependency_path::is_document_relative(path)
+                        && !self.is_remote(&resolved);
+                    match is_relative && !layered_roots.is_empty() {
+                        true => Ok(dependency_path::find_in_layers(&resolved, &layered_roots, |it| self.exists(it)).unwrap_or(resolved)),
+                        false => Ok(resolved),
+                    }
+                },
+            }
+        })
+    }
+}
+
+/// Attribute an error entering or reading the file of an entry of a list file to
+/// the entry's line, see [Error::ListEntry].
+fn in_list_entry<T>(via: Option<&Include>, result: Result<T, Error>) -> Result<T, Error> {
+    match via.and_then(Include::list_entry) {
+        Some((list, line)) => result.map_err(|error| Error::ListEntry(list.to_owned(), line, Box::new(error))),
+        None => result,
+    }
+}
+
+/// Reset the value of the mutex to its default, even if a thread panicked while
+/// holding it.
+#[cfg(feature = "cache")]
+fn reset<T: Default>(mutex: &Mutex<T>) {
+    *mutex.lock().unwrap_or_else(PoisonError::into_inner) = T::default();
+    mutex.clear_poison();
+}
+
+/// Resolve the path of a file to be written like [fs::canonicalize], without
+/// requiring it or its directory to exist: the deepest existing ancestor is resolved,
+/// and the rest of the path appended with `.` and `..` resolved lexically.
+fn resolve_target(path: &Path) -> io::Result<PathBuf> {
+    let path = canonical_path::clean(&std::env::current_dir()?.join(path));
+    let mut existing = path.as_path();
+    loop {
+        match fs::canonicalize(existing) {
+            Ok(resolved) => return match path.strip_prefix(existing) {
+                Ok(rest) if !rest.as_os_str().is_empty() => Ok(resolved.join(rest)),
+                _ => Ok(resolved),
+            },
+            Err(error) => existing = existing.parent().ok_or(error)?,
+        }
+    }
+}
+
+/// Remove spaces and tabs at the end of every line, keeping the line breaks.
+fn trim_trailing_whitespace(text: &str) -> String {
+    lazy_regex::regex!(r"[ \t]+(\r?\n|\z)")
+        .replace_all(text, "$1")
+        .into_owned()
+}
+
+/// Run a command with the system shell in the given directory and return its
+/// standard output.
+fn run_command(command: &str, dir: &Path) -> Result<String, Error> {
+    let (shell, flag) = match cfg!(windows) {
+        true => ("cmd", "/C"),
+        false => ("sh", "-c"),
+    };
+    let dir = match dir.as_os_str().is_empty() {
+        true => Path::new("."),
+        false => dir,
+    };
+
+    let output = std::process::Command::new(shell).args([flag, command]).current_dir(dir).output()?;
+    match output.status.success() {
+        true => Ok(String::from_utf8_lossy(&output.stdout).into_owned()),
+        false => Err(Error::CommandFailed(
+            command.to_owned(),
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim_end().to_owned(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test_loader {
+    use crate::{CommentStyle, CycleKey, Delimiters, DiagnosticKind, EscapedOutput, Error, FileSource, Fragment, IncludeTree, IndentMode, LoadOptions, LoaderBuilder, RelativeBase, LITERAL_MAX_DEPTH, dependency_path, loader::Loader};
+    use rstest::rstest;
+    use std::{collections::HashMap, io, path::{Path, PathBuf}, sync::{Arc, Mutex}, time::Duration};
+    use temp_dir::TempDir;
+
+    #[rstest]
+    fn should_load_single_files() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+
+        std::fs::write(
+            dir.child("start.txt"),
+            "hello, world!".as_bytes(),
+        )?;
+
+        let result = Loader::new().load_file_recursively(dir.child("start.txt"))?;
+        assert_eq!(result, "hello, world!");
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_load_recursively() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+
+        std::fs::write(
+            dir.child("start.txt"),
+            r#"hello, ${include("world.txt")}!"#.as_bytes(),
+        )?;
+        std::fs::write(
+            dir.child("world.txt"),
+            "world".as_bytes(),
+        )?;
+
+        let result = Loader::new().load_file_recursively(dir.child("start.txt"))?;
+        assert_eq!(result, "hello, world!");
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[case(r#"hello, ${include( "world.txt" )}!"#)]
+    #[case(r#"hello, ${include ("world.txt")}!"#)]
+    #[case("hello, ${include\t(\t\"world.txt\"\t)}!")]
+    #[case(r#"hello, ${include_indent  (  "world.txt"  )}!"#)]
+    fn should_allow_whitespace_around_arguments(
+        #[case] input: &str,
+    ) -> Result<(), Error> {
+        let dir = TempDir::new()?;
+
+        std::fs::write(dir.child("start.txt"), input.as_bytes())?;
+        std::fs::write(dir.child("world.txt"), "world".as_bytes())?;
+
+        let result = Loader::new().load_file_recursively(dir.child("start.txt"))?;
+        assert_eq!(result, "hello, world!");
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[case(r#"hello, ${include("world.txt" # legacy header)}!"#, "hello, world!")]
+    #[case(r##"hello, ${include("world.txt"# (old) header)}!"##, "hello, world!")]
+    #[case(r#"hello, ${include("world.txt" #)}!"#, "hello, world!")]
+    #[case(r##"hello, ${include("#world.txt")}!"##, "hello, hash!")]
+    #[case(r##"hello, ${include("#world.txt" # comment)}!"##, "hello, hash!")]
+    #[case(r##"hello, ${include_with("#", "world.txt" # comment)}!"##, "hello, world!")]
+    fn should_ignore_comments_in_directives(
+        #[case] input: &str,
+        #[case] expectation: &str,
+    ) -> Result<(), Error> {
+        let dir = TempDir::new()?;
+
+        std::fs::write(dir.child("start.txt"), input.as_bytes())?;
+        std::fs::write(dir.child("world.txt"), "world".as_bytes())?;
+        std::fs::write(dir.child("#world.txt"), "hash".as_bytes())?;
+
+        let result = LoaderBuilder::new().allow_all_directives().build().load_file_recursively(dir.child("start.txt"))?;
+        assert_eq!(result, expectation);
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[case(r#"${ include("world.txt")}"#)]
+    #[case(r#"${include("world.txt" x)}"#)]
+    #[case(r#"${include( world.txt )}"#)]
+    #[case(r#"${include("world.txt") }"#)]
+    #[case("${include(\n\"world.txt\")}")]
+    #[case(r##"${include(# comment "world.txt")}"##)]
+    #[case("${include(\"world.txt\" # comment\n)}")]
+    fn should_not_match_malformed_directives(
+        #[case] input: &str,
+    ) -> Result<(), Error> {
+        let dir = TempDir::new()?;
+
+        std::fs::write(dir.child("start.txt"), input.as_bytes())?;
+        std::fs::write(dir.child("world.txt"), "world".as_bytes())?;
+
+        let result = Loader::new().load_file_recursively(dir.child("start.txt"))?;
+        assert_eq!(result, input);
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[case(RelativeBase::SourceFile, None)]
+    #[case(RelativeBase::Cwd, Some("root"))]
+    fn should_resolve_relative_to_configured_base(
+        #[case] relative_to: RelativeBase,
+        #[case] expectation: Option<&str>,
+    ) -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::create_dir(dir.child("nested"))?;
+
+        // the temp dir, relative to the current working directory
+        let relative_dir = std::env::current_dir()?
+            .components()
+            .skip(1)
+            .map(|_| "..")
+            .collect::<std::path::PathBuf>()
+            .join(dir.path().strip_prefix("/").unwrap());
+
+        std::fs::write(
+            dir.child("start.txt"),
+            format!(r#"${{include("{}")}}"#, dir.child("nested/mid.txt").to_string_lossy()).as_bytes(),
+        )?;
+        std::fs::write(
+            dir.child("nested/mid.txt"),
+            format!(r#"${{include("{}")}}"#, relative_dir.join("end.txt").to_string_lossy()).as_bytes(),
+        )?;
+        std::fs::write(dir.child("end.txt"), "root".as_bytes())?;
+
+        let loader = LoaderBuilder::new()
+            .relative_to(relative_to)
+            .build();
+        let result = loader.load_file_recursively(dir.child("start.txt"));
+        match (result, expectation) {
+            (Ok(text), Some(expectation)) => assert_eq!(text, expectation),
+            (Err(Error::FileNotFound(path)), None) => assert!(path.starts_with(dir.child("nested"))),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[rstest]
+    #[case(CycleKey::Canonical, "deeper")]
+    #[case(CycleKey::Literal, "root")]
+    fn should_resolve_symlinks_according_to_cycle_key(
+        #[case] cycle_detection: CycleKey,
+        #[case] expectation: &str,
+    ) -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::create_dir_all(dir.child("sub/deeper"))?;
+        std::os::unix::fs::symlink(dir.child("sub/deeper"), dir.child("link"))?;
+
+        std::fs::write(
+            dir.child("start.txt"),
+            r#"${include("link/x.txt")}"#.as_bytes(),
+        )?;
+        std::fs::write(
+            dir.child("sub/deeper/x.txt"),
+            r#"${include("../y.txt")}"#.as_bytes(),
+        )?;
+        std::fs::write(dir.child("sub/y.txt"), "deeper".as_bytes())?;
+        std::fs::write(dir.child("y.txt"), "root".as_bytes())?;
+
+        let loader = LoaderBuilder::new()
+            .cycle_detection(cycle_detection)
+            .build();
+        let result = loader.load_file_recursively(dir.child("start.txt"))?;
+        assert_eq!(result, expectation);
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[rstest]
+    #[case(CycleKey::Canonical, "start.txt")]
+    #[case(CycleKey::Literal, "link.txt")]
+    fn should_detect_cycles_according_to_cycle_key(
+        #[case] cycle_detection: CycleKey,
+        #[case] expected_including_file: &str,
+    ) -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::os::unix::fs::symlink(dir.child("start.txt"), dir.child("link.txt"))?;
+
+        std::fs::write(
+            dir.child("start.txt"),
+            r#"${include("link.txt")}"#.as_bytes(),
+        )?;
+
+        let loader = LoaderBuilder::new()
+            .cycle_detection(cycle_detection)
+            .build();
+        match loader.load_file_recursively(dir.child("start.txt")) {
+            Err(Error::CyclicDependency { from, to, .. }) => {
+                assert_eq!(from, dir.child(expected_including_file));
+                assert_eq!(to, dir.child("link.txt"));
+            },
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[case("${include(\"world.txt\")}\n", "world\n", "world\n")]
+    #[case("${include(\"world.txt\")}", "world\n", "world")]
+    #[case("${include(\"world.txt\")}\n\n", "world\n\n", "world\n\n\n")]
+    #[case("  ${include_indent(\"world.txt\")}\n", "a\nb\n", "  a\n  b\n")]
+    fn should_only_strip_trailing_newlines_of_included_files(
+        #[case] root: &str,
+        #[case] included: &str,
+        #[case] expectation: &str,
+    ) -> Result<(), Error> {
+        let dir = TempDir::new()?;
+
+        std::fs::write(dir.child("start.txt"), root.as_bytes())?;
+        std::fs::write(dir.child("world.txt"), included.as_bytes())?;
+
+        let result = Loader::new().load_file_recursively(dir.child("start.txt"))?;
+        assert_eq!(result, expectation);
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[case("$dollar.txt")]
+    #[case("{brace}.txt")]
+    #[case("${fake}.txt")]
+    #[case("${include(x)}.txt")]
+    #[case("a)}.txt")]
+    #[case("#hash.txt")]
+    #[cfg_attr(unix, case(r"back\slash.txt"))]
+    #[cfg_attr(unix, case(r"\${include(x)}.txt"))]
+    fn should_resolve_unusual_file_names(
+        #[case] file_name: &str,
+    ) -> Result<(), Error> {
+        let dir = TempDir::new()?;
+
+        std::fs::write(
+            dir.child("start.txt"),
+            format!(r#"<${{include("{}")}}>"#, file_name).as_bytes(),
+        )?;
+        std::fs::write(
+            dir.child(file_name),
+            r#"${include("inner.txt")} \${include("inner.txt")}"#.as_bytes(),
+        )?;
+        std::fs::write(dir.child("inner.txt"), "inner".as_bytes())?;
+
+        let result = Loader::new().load_file_recursively(dir.child("start.txt"))?;
+        assert_eq!(result, r#"<inner ${include("inner.txt")}>"#);
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_preserve_indentation() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+
+        std::fs::write(
+            dir.child("start.txt"),
+            "start\n  ${include_indent(\"1.txt\")}".as_bytes(),
+        )?;
+        std::fs::write(
+            dir.child("1.txt"),
+            "1\n\t${include(\"2.txt\")}".as_bytes(),
+        )?;
+        std::fs::write(
+            dir.child("2.txt"),
+            "2\n2".as_bytes(),
+        )?;
+
+        let result = Loader::new().load_file_recursively(dir.child("start.txt"))?;
+        assert_eq!(result, "start\n  1\n  \t2\n  2");
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_apply_fixed_indentation() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+
+        std::fs::write(
+            dir.child("start.txt"),
+            "start\n\t${include_with(\"  \", \"1.txt\")}".as_bytes(),
+        )?;
+        std::fs::write(
+            dir.child("1.txt"),
+            "1\n1\n1".as_bytes(),
+        )?;
+
+        let result = LoaderBuilder::new().allow_all_directives().build().load_file_recursively(dir.child("start.txt"))?;
+        assert_eq!(result, "start\n\t1\n  1\n  1");
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[case(r#"${include("  ", "world.txt")}"#)]
+    #[case(r#"${include_with("world.txt")}"#)]
+    #[case(r#"${include_unknown("world.txt")}"#)]
+    fn should_ignore_unknown_directive_signatures(
+        #[case] input: &str,
+    ) -> Result<(), Error> {
+        let dir = TempDir::new()?;
+
+        std::fs::write(dir.child("start.txt"), input.as_bytes())?;
+        std::fs::write(dir.child("world.txt"), "world".as_bytes())?;
+
+        let result = Loader::new().load_file_recursively(dir.child("start.txt"))?;
+        assert_eq!(result, input);
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_wrap_content_in_fences() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+
+        std::fs::write(
+            dir.child("start.txt"),
+            "- example:\n  ${include_fenced(\"rust\", \"example.rs\")}\nend".as_bytes(),
+        )?;
+        std::fs::write(
+            dir.child("example.rs"),
+            "fn main() {\n    ${include(\"body.rs\")}\n}\n".as_bytes(),
+        )?;
+        std::fs::write(
+            dir.child("body.rs"),
+            "todo!()".as_bytes(),
+        )?;
+
+        let result = LoaderBuilder::new().allow_all_directives().build().load_file_recursively(dir.child("start.txt"))?;
+        assert_eq!(result, indoc::indoc!("
+            - example:
+              ```rust
+              fn main() {
+                  todo!()
+              }
+              ```
+            end")
+        );
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_transform_content_before_finding_includes() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+
+        std::fs::write(
+            dir.child("start.txt"),
+            "hello, {{name}}!".as_bytes(),
+        )?;
+        std::fs::write(
+            dir.child("world.txt"),
+            "{{world}}".as_bytes(),
+        )?;
+
+        let loader = LoaderBuilder::new()
+            .transform(|path, content| {
+                let name = path.file_name().unwrap().to_string_lossy().to_uppercase();
+                Ok(content
+                    .replace("{{name}}", r#"${include("world.txt")}"#)
+                    .replace("{{world}}", &name))
+            })
+            .build();
+        let result = loader.load_file_recursively(dir.child("start.txt"))?;
+        assert_eq!(result, "hello, WORLD.TXT!");
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_expand_path_names() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+
+        std::fs::write(
+            dir.child("start.txt"),
+            r#"${include("header.txt")}: ${include_name("config.txt")}"#.as_bytes(),
+        )?;
+        std::fs::write(
+            dir.child("header.txt"),
+            "${self_path}".as_bytes(),
+        )?;
+
+        let result = LoaderBuilder::new().allow_all_directives().build().load_file_recursively(dir.child("start.txt"))?;
+        assert_eq!(result, format!(
+            "{}: {}",
+            dir.child("header.txt").to_string_lossy(),
+            dir.child("config.txt").to_string_lossy(),
+        ));
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_resolve_fragments_in_output_order() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+
+        std::fs::write(
+            dir.child("start.txt"),
+            "start\n  ${include_indent(\"1.txt\")}\n\\${include(\"2.txt\")}".as_bytes(),
+        )?;
+        std::fs::write(
+            dir.child("1.txt"),
+            "1\n${include(\"2.txt\")}\n".as_bytes(),
+        )?;
+        std::fs::write(
+            dir.child("2.txt"),
+            "2\n2".as_bytes(),
+        )?;
+
+        let loader = Loader::new();
+        let fragments = loader.resolve_fragments(dir.child("start.txt"))?;
+        assert_eq!(fragments, vec![
+            Fragment::new(dir.child("start.txt"), "start\n  "),
+            Fragment::new(dir.child("1.txt"), "1\n  "),
+            Fragment::new(dir.child("2.txt"), "2\n  2"),
+            Fragment::new(dir.child("start.txt"), "\n${include(\"2.txt\")}"),
+        ]);
+
+        let text: String = fragments.into_iter().map(Fragment::into_text).collect();
+        assert_eq!(text, loader.load_file_recursively(dir.child("start.txt"))?);
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_collect_stats() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+
+        std::fs::write(
+            dir.child("start.txt"),
+            "${include(\"a.txt\")}${include(\"b.txt\")}".as_bytes(),
+        )?;
+        std::fs::write(dir.child("a.txt"), "a\n${include(\"b.txt\")}\n".as_bytes())?;
+        std::fs::write(dir.child("b.txt"), "b\n".as_bytes())?;
+
+        let (text, stats) = Loader::new().load_with_stats(dir.child("start.txt"))?;
+        assert_eq!(text, "a\nbb");
+        assert_eq!(stats.bytes_read(), 40 + 20 + 2 + 2);
+        assert_eq!(stats.files_read(), 4);
+        assert_eq!(stats.max_depth(), 2);
+        assert_eq!(stats.canonical_root(), Some(dir.child("start.txt").canonicalize()?.as_path()));
+
+        std::fs::create_dir(dir.child("sub"))?;
+        let loader = LoaderBuilder::new().cycle_detection(CycleKey::Literal).build();
+        let (_, stats) = loader.load_with_stats(dir.child("sub/../start.txt"))?;
+        assert_eq!(stats.canonical_root(), Some(dir.child("start.txt").as_path()));
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_build_include_tree() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+
+        std::fs::write(
+            dir.child("start.txt"),
+            "${include(\"a.txt\")}\n${include_name(\"b.txt\")}\\${include(\"b.txt\")}${include(\"b.txt\")}".as_bytes(),
+        )?;
+        std::fs::write(dir.child("a.txt"), "${include(\"b.txt\")}".as_bytes())?;
+        std::fs::write(dir.child("b.txt"), "b".as_bytes())?;
+
+        let tree = Loader::new().build_tree(dir.child("start.txt"))?;
+        assert_eq!(tree, IncludeTree::new(dir.child("start.txt"), vec![
+            IncludeTree::new(dir.child("a.txt"), vec![
+                IncludeTree::new(dir.child("b.txt"), vec![]),
+            ]),
+            IncludeTree::new(dir.child("b.txt"), vec![]),
+        ]));
+
+        std::fs::write(dir.child("b.txt"), "${include(\"a.txt\")}".as_bytes())?;
+        let result = Loader::new().build_tree(dir.child("start.txt"));
+        assert!(matches!(result, Err(Error::CyclicDependency { .. })));
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_print_include_tree() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::create_dir(dir.child("sub"))?;
+        std::fs::write(dir.child("sub/start.txt"), "${include(\"a.txt\")}${include(\"../b.txt\")}".as_bytes())?;
+        std::fs::write(dir.child("sub/a.txt"), "${include(\"../b.txt\")}".as_bytes())?;
+        std::fs::write(dir.child("b.txt"), "b".as_bytes())?;
+
+        assert_eq!(
+            Loader::new().print_tree(dir.child("sub/start.txt"))?,
+            "start.txt\n├── a.txt\n│   └── ../b.txt\n└── ../b.txt",
+        );
+
+        std::fs::write(dir.child("b.txt"), "${include(\"sub/start.txt\")}".as_bytes())?;
+        let result = Loader::new().print_tree(dir.child("sub/start.txt"));
+        assert!(matches!(result, Err(Error::CyclicDependency { .. })));
+
+        let loader = LoaderBuilder::new().lenient(true).build();
+        assert_eq!(
+            loader.print_tree(dir.child("sub/start.txt"))?,
+            "start.txt\n├── a.txt\n│   └── ../b.txt\n│       └── start.txt (cycle)\n└── ../b.txt\n    └── start.txt (cycle)",
+        );
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_respect_escapes() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+
+        std::fs::write(
+            dir.child("start.txt"),
+            "hello, \\${include(\"world.txt\")}!".as_bytes(),
+        )?;
+
+        let result = Loader::new().load_file_recursively(dir.child("start.txt"))?;
+        assert_eq!(result, "hello, ${include(\"world.txt\")}!");
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_load_utf16_files() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+
+        let mut bytes = vec![0xFF, 0xFE];
+        bytes.extend("hello, ${include(\"world.txt\")}!".encode_utf16().flat_map(u16::to_le_bytes));
+        std::fs::write(dir.child("start.txt"), bytes)?;
+
+        let mut bytes = vec![0xFE, 0xFF];
+        bytes.extend("world".encode_utf16().flat_map(u16::to_be_bytes));
+        std::fs::write(dir.child("world.txt"), bytes)?;
+
+        let result = Loader::new().load_file_recursively(dir.child("start.txt"))?;
+        assert_eq!(result, "hello, world!");
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[case(EscapedOutput::Collapsed, "hello, \\world!")]
+    #[case(EscapedOutput::Literal, "hello, \\\\world!")]
+    fn should_apply_escaped_output_mode(
+        #[case] escaped_output: EscapedOutput,
+        #[case] expectation: &str,
+    ) -> Result<(), Error> {
+        let dir = TempDir::new()?;
+
+        std::fs::write(
+            dir.child("start.txt"),
+            "hello, \\\\${include(\"world.txt\")}!".as_bytes(),
+        )?;
+        std::fs::write(
+            dir.child("world.txt"),
+            "world".as_bytes(),
+        )?;
+
+        let loader = LoaderBuilder::new()
+            .escaped_output(escaped_output)
+            .build();
+        let result = loader.load_file_recursively(dir.child("start.txt"))?;
+        assert_eq!(result, expectation);
+
+        Ok(())
+    }
+
+    #[cfg(feature = "manifest")]
+    #[rstest]
+    fn should_verify_against_manifest() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+
+        std::fs::write(
+            dir.child("start.txt"),
+            r#"${include("a.txt")}${include("b.txt")}${include("a.txt")}"#.as_bytes(),
+        )?;
+        std::fs::write(dir.child("a.txt"), "a".as_bytes())?;
+        std::fs::write(dir.child("b.txt"), "b".as_bytes())?;
+
+        let loader = Loader::new();
+        let (text, manifest) = loader.load_with_manifest(dir.child("start.txt"))?;
+        assert_eq!(text, "aba");
+        let paths: Vec<_> = manifest.files().iter().map(|it| it.path().to_owned()).collect();
+        assert_eq!(paths, vec![dir.child("start.txt"), dir.child("a.txt"), dir.child("b.txt")]);
+
+        loader.verify_against(&manifest)?;
+
+        std::fs::write(dir.child("b.txt"), "B".as_bytes())?;
+        match loader.verify_against(&manifest) {
+            Err(Error::ManifestMismatch(path)) => assert_eq!(path, dir.child("b.txt")),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_use_custom_canonicalizer() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+
+        std::fs::write(
+            dir.child("start.txt"),
+            r#"${include("a.txt")}"#.as_bytes(),
+        )?;
+        std::fs::write(dir.child("a.txt"), r#"${include("b.txt")}"#.as_bytes())?;
+        std::fs::write(dir.child("b.txt"), "b".as_bytes())?;
+
+        // consider a.txt and b.txt the same file
+        let loader = LoaderBuilder::new()
+            .canonicalize(|path| Ok(path.to_string_lossy().replace("b.txt", "a.txt").into()))
+            .build();
+        match loader.load_file_recursively(dir.child("start.txt")) {
+            Err(Error::CyclicDependency { from, to, .. }) => {
+                assert_eq!(from, dir.child("a.txt"));
+                assert_eq!(to, dir.child("b.txt"));
+            },
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        // a lexical canonicalizer doesn't check for existence
+        std::fs::remove_file(dir.child("b.txt"))?;
+        let loader = LoaderBuilder::new()
+            .canonicalize(|path| Ok(path.to_owned()))
+            .build();
+        match loader.load_file_recursively(dir.child("start.txt")) {
+            Err(Error::FileNotFound(path)) => assert_eq!(path, dir.child("b.txt")),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_report_case_mismatches() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+
+        std::fs::write(
+            dir.child("start.txt"),
+            r#"${include("Header.txt")}"#.as_bytes(),
+        )?;
+        std::fs::write(dir.child("header.txt"), "header".as_bytes())?;
+
+        let loader = LoaderBuilder::new()
+            .case_insensitive(true)
+            .build();
+        match loader.load_file_recursively(dir.child("start.txt")) {
+            Err(Error::CaseMismatch(requested, actual)) => {
+                assert_eq!(requested, dir.child("Header.txt"));
+                assert_eq!(actual, dir.child("header.txt"));
+            },
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_detect_cycles_ignoring_case() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+
+        std::fs::write(
+            dir.child("A.txt"),
+            r#"${include("a.txt")}"#.as_bytes(),
+        )?;
+        std::fs::write(dir.child("a.txt"), "a".as_bytes())?;
+
+        let result = Loader::new().load_file_recursively(dir.child("A.txt"))?;
+        assert_eq!(result, "a");
+
+        let loader = LoaderBuilder::new()
+            .case_insensitive(true)
+            .build();
+        let result = loader.load_file_recursively(dir.child("A.txt"));
+        assert!(matches!(result, Err(Error::CyclicDependency { .. })), "{:?}", result);
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[case(&["header.md", "header.txt"], "txt")]
+    #[case(&["header.md"], "md")]
+    #[case(&["header", "header.txt"], "exact")]
+    fn should_try_extensions(
+        #[case] files: &[&str],
+        #[case] expectation: &str,
+    ) -> Result<(), Error> {
+        let dir = TempDir::new()?;
+
+        std::fs::write(
+            dir.child("start.txt"),
+            r#"${include("header")}"#.as_bytes(),
+        )?;
+        for file in files {
+            let content = file.split_once('.').map(|it| it.1).unwrap_or("exact");
+            std::fs::write(dir.child(file), content.as_bytes())?;
+        }
+
+        let loader = LoaderBuilder::new()
+            .try_extensions(["txt", ".md"])
+            .build();
+        let result = loader.load_file_recursively(dir.child("start.txt"))?;
+        assert_eq!(result, expectation);
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_list_attempted_paths() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+
+        std::fs::write(
+            dir.child("start.txt"),
+            r#"${include("header")}"#.as_bytes(),
+        )?;
+
+        let loader = LoaderBuilder::new()
+            .try_extensions(["txt", "md"])
+            .build();
+        let error = loader.load_file_recursively(dir.child("start.txt")).unwrap_err();
+        assert_eq!(error.to_string(), format!(
+            "file not found, tried '{0}/header', '{0}/header.txt', '{0}/header.md'",
+            dir.path().to_string_lossy(),
+        ));
+
+        Ok(())
+    }
+
+    #[cfg(feature = "parallel")]
+    #[rstest]
+    fn should_load_in_parallel_like_sequentially() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+
+        std::fs::write(
+            dir.child("start.txt"),
+            "start\n  ${include_indent(\"a.txt\")}\n${include(\"b.txt\")}\n\\${include(\"c.txt\")}".as_bytes(),
+        )?;
+        std::fs::write(dir.child("a.txt"), "a\n${include(\"b.txt\")}\n${include(\"b.txt\")}".as_bytes())?;
+        std::fs::write(dir.child("b.txt"), "b1\nb2\n".as_bytes())?;
+
+        let sequential = Loader::new().load_file_recursively(dir.child("start.txt"))?;
+        let parallel = LoaderBuilder::new()
+            .parallel(true)
+            .build()
+            .load_file_recursively(dir.child("start.txt"))?;
+        assert_eq!(parallel, sequential);
+
+        std::fs::write(dir.child("b.txt"), "${include(\"missing.txt\")}".as_bytes())?;
+        std::fs::write(dir.child("a.txt"), "${include(\"start.txt\")}".as_bytes())?;
+        for _ in 0..10 {
+            let sequential = Loader::new().load_file_recursively(dir.child("start.txt")).unwrap_err();
+            let parallel = LoaderBuilder::new()
+                .parallel(true)
+                .build()
+                .load_file_recursively(dir.child("start.txt"))
+                .unwrap_err();
+            assert_eq!(parallel.to_string(), sequential.to_string());
+        }
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_report_file_not_found() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        let file = dir.child("non-existent.txt");
+
+        let result = Loader::new().load_file_recursively(&file);
+        if let Err(e) = result {
+            let msg = e.to_string();
+            assert!(msg.contains(&format!("file not found: '{}'", file.to_string_lossy())));
+        } else {
+            panic!("expected an err");
+        }
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[case(100, true)]
+    #[case(99, false)]
+    fn should_limit_file_size(
+        #[case] max_file_bytes: u64,
+        #[case] expect_ok: bool,
+    ) -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        let world = dir.child("world.txt");
+
+        std::fs::write(
+            dir.child("start.txt"),
+            r#"${include("world.txt")}"#.as_bytes(),
+        )?;
+        std::fs::write(
+            &world,
+            "w".repeat(100).as_bytes(),
+        )?;
+
+        let loader = LoaderBuilder::new()
+            .max_file_bytes(max_file_bytes)
+            .build();
+        let result = loader.load_file_recursively(dir.child("start.txt"));
+        match result {
+            Ok(text) if expect_ok => assert_eq!(text, "w".repeat(100)),
+            Err(Error::FileTooLarge(path, limit)) if !expect_ok => assert_eq!((path, limit), (world, max_file_bytes)),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "cache")]
+    #[rstest]
+    fn should_use_disk_cache() -> Result<(), Error> {
+        use crate::DiskCache;
+        use std::{collections::HashMap, sync::{Arc, Mutex}};
+
+        #[derive(Clone, Default)]
+        struct MemoryCache(Arc<Mutex<HashMap<String, String>>>);
+
+        impl DiskCache for MemoryCache {
+            fn get(&self, hash: &str) -> Option<String> {
+                self.0.lock().unwrap().get(hash).cloned()
+            }
+
+            fn put(&self, hash: &str, text: String) {
+                self.0.lock().unwrap().insert(hash.to_owned(), text);
+            }
+        }
+
+        let dir = TempDir::new()?;
+        std::fs::write(dir.child("start.txt"), "${include(\"mid.txt\")}".as_bytes())?;
+        std::fs::write(dir.child("mid.txt"), "mid ${include(\"leaf.txt\")}".as_bytes())?;
+        std::fs::write(dir.child("leaf.txt"), "leaf".as_bytes())?;
+
+        let cache = MemoryCache::default();
+        let loader = LoaderBuilder::new().disk_cache(cache.clone()).build();
+        assert_eq!(loader.load_file_recursively(dir.child("start.txt"))?, "mid leaf");
+        assert_eq!(cache.0.lock().unwrap().len(), 3);
+
+        cache.0.lock().unwrap().values_mut().for_each(|it| *it = format!("cached {}", it));
+        assert_eq!(loader.load_file_recursively(dir.child("start.txt"))?, "cached mid leaf");
+
+        std::fs::write(dir.child("leaf.txt"), "changed".as_bytes())?;
+        assert_eq!(loader.load_file_recursively(dir.child("start.txt"))?, "mid changed");
+        assert_eq!(cache.0.lock().unwrap().len(), 6);
+
+        Ok(())
+    }
+
+    #[cfg(feature = "cache")]
+    #[rstest]
+    fn should_report_and_clear_cache_stats() -> Result<(), Error> {
+        use crate::{CacheStats, DirectoryCache};
+
+        let dir = TempDir::new()?;
+        std::fs::write(dir.child("start.txt"), "${include(\"leaf.txt\")}".as_bytes())?;
+        std::fs::write(dir.child("leaf.txt"), "leaf".as_bytes())?;
+
+        let loader = LoaderBuilder::new()
+            .disk_cache(DirectoryCache::new(dir.child("cache")))
+            .build();
+        loader.load_file_recursively(dir.child("start.txt"))?;
+        loader.load_file_recursively(dir.child("start.txt"))?;
+
+        let stats = loader.cache_stats();
+        assert_eq!((stats.hits(), stats.misses(), stats.entries()), (1, 2, 2));
+
+        loader.clear_cache();
+        assert_eq!(loader.cache_stats(), CacheStats::default());
+        assert!(!dir.child("cache").exists());
+
+        loader.load_file_recursively(dir.child("start.txt"))?;
+        let stats = loader.cache_stats();
+        assert_eq!((stats.hits(), stats.misses(), stats.entries()), (0, 2, 2));
+
+        Ok(())
+    }
+
+    #[cfg(feature = "cache")]
+    #[rstest]
+    fn should_key_disk_cache_on_produced_values() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::write(dir.child("start.txt"), "v=${include_env(\"RFL_TEST_CACHED_VALUE\")}".as_bytes())?;
+
+        let loader = LoaderBuilder::new().allow_all_directives().disk_cache(crate::DirectoryCache::new(dir.child("cache"))).build();
+        std::env::set_var("RFL_TEST_CACHED_VALUE", "one");
+        let first = loader.load_file_recursively(dir.child("start.txt"));
+        std::env::set_var("RFL_TEST_CACHED_VALUE", "two");
+        let second = loader.load_file_recursively(dir.child("start.txt"));
+        std::env::remove_var("RFL_TEST_CACHED_VALUE");
+
+        assert_eq!(first?, "v=one");
+        assert_eq!(second?, "v=two");
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[case("RFL_TEST_DEFINED_1", "${include_env(\"RFL_TEST_DEFINED_1\")}", "a\nb")]
+    #[case("RFL_TEST_DEFINED_2", "${include_env(\"RFL_TEST_UNDEFINED\", \"dev\")}", "dev")]
+    #[case("RFL_TEST_DEFINED_3", "${include_env(\"RFL_TEST_DEFINED_3\", \"dev\")}", "a\nb")]
+    #[case("RFL_TEST_DEFINED_4", "  ${include_env_indent(\"RFL_TEST_DEFINED_4\")}", "  a\n  b")]
+    #[case("RFL_TEST_DEFINED_5", "\\${include_env(\"RFL_TEST_UNDEFINED\")}", "${include_env(\"RFL_TEST_UNDEFINED\")}")]
+    fn should_include_env_vars(
+        #[case] defined: &str,
+        #[case] input: &str,
+        #[case] expectation: &str,
+    ) -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::write(dir.child("start.txt"), input.as_bytes())?;
+        std::env::set_var(defined, "a\nb");
+
+        let result = LoaderBuilder::new().allow_all_directives().build().load_file_recursively(dir.child("start.txt"));
+        std::env::remove_var(defined);
+        assert_eq!(&result?, expectation);
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_report_undefined_env_vars() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::write(dir.child("start.txt"), "${include_env(\"RFL_TEST_UNDEFINED\")}".as_bytes())?;
+
+        match LoaderBuilder::new().allow_all_directives().build().load_file_recursively(dir.child("start.txt")) {
+            Err(Error::UndefinedEnvVar(name)) => assert_eq!(name, "RFL_TEST_UNDEFINED"),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_resolve_paths_against_root_marker() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::create_dir_all(dir.child("repo/shared"))?;
+        std::fs::create_dir_all(dir.child("repo/app/pages"))?;
+        std::fs::write(dir.child("repo/.loaderroot"), [])?;
+        std::fs::write(dir.child("repo/shared/header.txt"), "header".as_bytes())?;
+        std::fs::write(
+            dir.child("repo/app/pages/start.txt"),
+            "${include(\"@/shared/header.txt\")}".as_bytes(),
+        )?;
+
+        let loader = LoaderBuilder::new().root_marker(".loaderroot").build();
+        assert_eq!(loader.load_file_recursively(dir.child("repo/app/pages/start.txt"))?, "header");
+
+        std::fs::remove_file(dir.child("repo/.loaderroot"))?;
+        let loader = LoaderBuilder::new().root_marker(".loaderroot").build();
+        match loader.load_file_recursively(dir.child("repo/app/pages/start.txt")) {
+            Err(Error::RootMarkerNotFound(path, marker)) => {
+                assert_eq!(path, dir.child("repo/app/pages/start.txt"));
+                assert_eq!(marker, ".loaderroot");
+            },
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[case(true, true, "custom")]
+    #[case(false, true, "default")]
+    #[case(true, false, "custom")]
+    fn should_include_fallback_if_file_is_missing(
+        #[case] custom: bool,
+        #[case] default: bool,
+        #[case] expectation: &str,
+    ) -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::write(dir.child("start.txt"), "${include(\"custom.txt\" else \"default.txt\")}".as_bytes())?;
+        if custom {
+            std::fs::write(dir.child("custom.txt"), "custom".as_bytes())?;
+        }
+        if default {
+            std::fs::write(dir.child("default.txt"), "default".as_bytes())?;
+        }
+
+        let result = Loader::new().load_file_recursively(dir.child("start.txt"))?;
+        assert_eq!(&result, expectation);
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_report_missing_fallback() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::write(dir.child("start.txt"), "${include(\"custom.txt\" else \"default.txt\")}".as_bytes())?;
+
+        match Loader::new().load_file_recursively(dir.child("start.txt")) {
+            Err(Error::FilesNotFound(paths)) => assert_eq!(paths, vec![dir.child("custom.txt"), dir.child("default.txt")]),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        std::fs::write(dir.child("custom.txt"), "${include(\"missing.txt\")}".as_bytes())?;
+        std::fs::write(dir.child("default.txt"), "default".as_bytes())?;
+        match Loader::new().load_file_recursively(dir.child("start.txt")) {
+            Err(Error::FileNotFound(path)) => assert_eq!(path, dir.child("missing.txt")),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_dedent_included_files() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::write(dir.child("start.txt"), "list:\n  ${include_indent(\"snippet.txt\")}\nend".as_bytes())?;
+        std::fs::write(dir.child("snippet.txt"), "    - a\n    - b\n      - c\n".as_bytes())?;
+
+        let loader = LoaderBuilder::new().dedent_included(true).build();
+        assert_eq!(
+            loader.load_file_recursively(dir.child("start.txt"))?,
+            "list:\n  - a\n  - b\n    - c\nend",
+        );
+        assert_eq!(
+            Loader::new().load_file_recursively(dir.child("start.txt"))?,
+            "list:\n      - a\n      - b\n        - c\nend",
+        );
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[case("${include(\"missing.txt\")}", vec!["start.txt", "nested.txt", "missing.txt"])]
+    #[case("${include_env(\"RFL_TEST_UNDEFINED\")}", vec!["start.txt", "nested.txt"])]
+    #[case("${include(\"start.txt\")}", vec!["start.txt", "nested.txt", "start.txt"])]
+    fn should_report_error_chain(
+        #[case] nested: &str,
+        #[case] chain: Vec<&str>,
+    ) -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::write(dir.child("start.txt"), "${include(\"ok.txt\")}${include(\"nested.txt\")}".as_bytes())?;
+        std::fs::write(dir.child("ok.txt"), "ok".as_bytes())?;
+        std::fs::write(dir.child("nested.txt"), nested.as_bytes())?;
+
+        let error = LoaderBuilder::new().allow_all_directives().build().load_with_chain(dir.child("start.txt")).unwrap_err();
+        let chain: Vec<_> = chain.into_iter().map(|it| dir.child(it)).collect();
+        assert_eq!(error.chain(), chain.as_slice());
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_report_error_chain_for_root() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+
+        let error = Loader::new().load_with_chain(dir.child("start.txt")).unwrap_err();
+        assert_eq!(error.chain(), &[dir.child("start.txt")]);
+        assert!(matches!(error.into_error(), Error::FileNotFound(_)));
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[case(None, "a\n  ${include_indent(\"missing.txt\")}\nb")]
+    #[case(Some("<!-- missing: {path} -->"), "a\n  <!-- missing: DIR/missing.txt -->\nb")]
+    #[case(Some("<!--\n{path}\n-->"), "a\n  <!--\n  DIR/missing.txt\n  -->\nb")]
+    fn should_substitute_placeholder_in_lenient_mode(
+        #[case] placeholder: Option<&str>,
+        #[case] expectation: &str,
+    ) -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::write(dir.child("start.txt"), "${include(\"nested.txt\")}".as_bytes())?;
+        std::fs::write(dir.child("nested.txt"), "a\n  ${include_indent(\"missing.txt\")}\nb".as_bytes())?;
+
+        let mut builder = LoaderBuilder::new().lenient(true);
+        if let Some(placeholder) = placeholder {
+            builder = builder.failure_placeholder(placeholder);
+        }
+        let expectation = expectation.replace("DIR", &dir.path().to_string_lossy());
+
+        assert_eq!(builder.build().load_file_recursively(dir.child("start.txt"))?, expectation);
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_only_substitute_missing_files_in_lenient_mode() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::write(dir.child("start.txt"), "${include(\"start.txt\")}".as_bytes())?;
+
+        let loader = LoaderBuilder::new().lenient(true).build();
+        match loader.load_file_recursively(dir.child("start.txt")) {
+            Err(Error::CyclicDependency { .. }) => {},
+            other => panic!("unexpected result {:?}", other),
+        }
+        match loader.load_file_recursively(dir.child("missing.txt")) {
+            Err(Error::FileNotFound(_)) => {},
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_use_overridden_contents() -> Result<(), Error> {
+        use std::collections::HashMap;
+
+        let dir = TempDir::new()?;
+        std::fs::create_dir(dir.child("sub"))?;
+        std::fs::write(
+            dir.child("start.txt"),
+            "${include(\"sub/../existing.txt\")} ${include(\"./virtual.txt\")}".as_bytes(),
+        )?;
+        std::fs::write(dir.child("existing.txt"), "existing".as_bytes())?;
+        std::fs::write(dir.child("leaf.txt"), "leaf".as_bytes())?;
+
+        let loader = LoaderBuilder::new()
+            .overrides(HashMap::from([
+                (dir.child("existing.txt"), "patched".to_owned()),
+                (dir.child("virtual.txt"), "virtual ${include(\"leaf.txt\")}".to_owned()),
+            ]))
+            .build();
+        assert_eq!(loader.load_file_recursively(dir.child("start.txt"))?, "patched virtual leaf");
+        assert_eq!(Loader::new().load_file_recursively(dir.child("existing.txt"))?, "existing");
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_number_included_lines() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::write(dir.child("start.txt"), "listing:\n  ${include_numbered(\"code.txt\")}\nend".as_bytes())?;
+        std::fs::write(dir.child("code.txt"), "fn main() {\n}\n".as_bytes())?;
+
+        assert_eq!(
+            LoaderBuilder::new().allow_all_directives().build().load_file_recursively(dir.child("start.txt"))?,
+            "listing:\n  1 | fn main() {\n  2 | }\nend",
+        );
+
+        let loader = LoaderBuilder::new().allow_all_directives()
+            .line_number_start(9)
+            .line_number_width(3)
+            .build();
+        assert_eq!(
+            loader.load_file_recursively(dir.child("start.txt"))?,
+            "listing:\n    9 | fn main() {\n   10 | }\nend",
+        );
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[case(2, "a a")]
+    #[case(3, "a a")]
+    fn should_allow_includes_up_to_maximum(
+        #[case] max_includes: usize,
+        #[case] expectation: &str,
+    ) -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::write(
+            dir.child("start.txt"),
+            "${include(\"a.txt\")} ${include(\"a.txt\")}\\${include(\"a.txt\")}".as_bytes(),
+        )?;
+        std::fs::write(dir.child("a.txt"), "a".as_bytes())?;
+
+        let loader = LoaderBuilder::new().max_includes_per_file(max_includes).build();
+        let result = loader.load_file_recursively(dir.child("start.txt"))?;
+        assert_eq!(result, format!("{}${{include(\"a.txt\")}}", expectation));
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_reject_too_many_includes() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::write(dir.child("start.txt"), "${include(\"nested.txt\")}".as_bytes())?;
+        std::fs::write(dir.child("nested.txt"), "${include(\"a.txt\")}".repeat(4).as_bytes())?;
+        std::fs::write(dir.child("a.txt"), "a".as_bytes())?;
+
+        let loader = LoaderBuilder::new().max_includes_per_file(3).build();
+        match loader.load_file_recursively(dir.child("start.txt")) {
+            Err(Error::TooManyIncludes(path, count)) => {
+                assert_eq!(path, dir.child("nested.txt"));
+                assert_eq!(count, 4);
+            },
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[case(r#"${include("a.txt")}${include("b.txt")}"#, "AB")]
+    #[case(r#"${include("a.txt")}-${include("b.txt")}"#, "A-B")]
+    #[case(r#"${include("a.txt")}${include("b.txt")}${include("a.txt")}"#, "ABA")]
+    #[case(r#"${include("a.txt")} middle ${include("b.txt")}"#, "A middle B")]
+    #[case(r#"\${include("a.txt")}${include("b.txt")}"#, r#"${include("a.txt")}B"#)]
+    #[case(r#"${include("a.txt")}\${include("b.txt")}"#, r#"A${include("b.txt")}"#)]
+    #[case(r#"${include("a.txt")}\\${include("b.txt")}"#, r#"A\B"#)]
+    #[case(r#"\\${include("a.txt")}\\\${include("b.txt")}"#, r#"\A\${include("b.txt")}"#)]
+    #[case(r#"${include("multi.txt")}${include_indent("multi.txt")}"#, "1\n21\n 2")]
+    #[case(r#"${include("a.txt")}"#, "A")]
+    #[case("${include(\"a.txt\")}\n", "A\n")]
+    fn should_replace_adjacent_directives(
+        #[case] input: &str,
+        #[case] expectation: &str,
+    ) -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::write(dir.child("start.txt"), input.as_bytes())?;
+        std::fs::write(dir.child("a.txt"), "A".as_bytes())?;
+        std::fs::write(dir.child("b.txt"), "B".as_bytes())?;
+        std::fs::write(dir.child("multi.txt"), "1\n2\n".as_bytes())?;
+
+        let loader = Loader::new();
+        assert_eq!(loader.load_file_recursively(dir.child("start.txt"))?, expectation);
+        let fragments = loader.resolve_fragments(dir.child("start.txt"))?;
+        assert_eq!(fragments.iter().map(Fragment::text).collect::<String>(), expectation);
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[case(r#"${inclde("a.txt")}"#, Some(r#"${inclde("a.txt")}"#))]
+    #[case(r#"${include("a.txt", "b.txt")}"#, Some(r#"${include("a.txt", "b.txt")}"#))]
+    #[case(r#"${include(a.txt)}"#, Some(r#"${include(a.txt)}"#))]
+    #[case(r#"${HOME}"#, Some(r#"${HOME}"#))]
+    #[case(r#"${include("a.txt")} ${ self_path }"#, Some(r#"${ self_path }"#))]
+    #[case(r#"\${inclde("a.txt")} ${include("a.txt")}"#, None)]
+    #[case(r#"${include("${fake}.txt")} ${self_path}"#, None)]
+    #[case(r#"${include("a)}.txt")} $HOME {x}"#, None)]
+    fn should_reject_unknown_directives_in_strict_mode(
+        #[case] input: &str,
+        #[case] unknown: Option<&str>,
+    ) -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::write(dir.child("start.txt"), input.as_bytes())?;
+        std::fs::write(dir.child("a.txt"), "a".as_bytes())?;
+        std::fs::write(dir.child("${fake}.txt"), "fake".as_bytes())?;
+        std::fs::write(dir.child("a)}.txt"), "paren".as_bytes())?;
+
+        let loader = LoaderBuilder::new().allow_all_directives().strict_directives(true).build();
+        match (loader.load_file_recursively(dir.child("start.txt")), unknown) {
+            (Err(Error::UnknownDirective(expression, path)), Some(unknown)) => {
+                assert_eq!(expression, unknown);
+                assert_eq!(path, dir.child("start.txt"));
+            },
+            (Ok(_), None) => {},
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert!(LoaderBuilder::new().allow_all_directives().build().load_file_recursively(dir.child("start.txt")).is_ok());
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[case("${include(\"a.txt\"\n)}", Some(1))]
+    #[case("a\nb ${include_indent(\n\"a.txt\")}", Some(2))]
+    #[case("${include(\"a.txt\")} ${include(\"a.txt\"\n)}", Some(1))]
+    #[case("${include(\"a.txt\" ${include(\"a.txt\")}", Some(1))]
+    #[case("\\${include(\"a.txt\"\n)}", None)]
+    #[case("${include(\"a.txt\")}\n${include(\"a).txt\")}", None)]
+    fn should_report_unclosed_directives(
+        #[case] input: &str,
+        #[case] line: Option<usize>,
+    ) -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::write(dir.child("start.txt"), input.as_bytes())?;
+        std::fs::write(dir.child("a.txt"), "a".as_bytes())?;
+        std::fs::write(dir.child("a).txt"), "a".as_bytes())?;
+
+        let loader = LoaderBuilder::new().warn_unclosed_directives(true).build();
+        match (loader.load_file_recursively(dir.child("start.txt")), line) {
+            (Err(Error::UnclosedDirective(path, actual)), Some(line)) => {
+                assert_eq!(path, dir.child("start.txt"));
+                assert_eq!(actual, line);
+            },
+            (Ok(_), None) => {},
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert!(Loader::new().load_file_recursively(dir.child("start.txt")).is_ok());
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_load_includes_of_given_content() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::create_dir(dir.child("sub"))?;
+        std::fs::write(dir.child("sub/start.txt"), "on disk".as_bytes())?;
+        std::fs::write(dir.child("sub/a.txt"), "a".as_bytes())?;
+        std::fs::write(dir.child("sub/cycle.txt"), "${include(\"start.txt\")}".as_bytes())?;
+
+        let loader = Loader::new();
+        let result = loader.load_resolved("buffer ${include(\"a.txt\")}".to_owned(), dir.child("sub/start.txt"))?;
+        assert_eq!(result, "buffer a");
+
+        match loader.load_resolved("${include(\"cycle.txt\")}".to_owned(), dir.child("sub/start.txt")) {
+            Err(Error::CyclicDependency { from, to, .. }) => {
+                assert_eq!(from, dir.child("sub/cycle.txt"));
+                assert_eq!(to, dir.child("sub/start.txt"));
+            },
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_include_sections() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::write(
+            dir.child("start.txt"),
+            "${include_section(\"doc.txt\", \"intro\")}|${include_section(\"doc.txt\", \"outro\")}".as_bytes(),
+        )?;
+        std::fs::write(
+            dir.child("doc.txt"),
+            "# region intro\nintro ${include(\"a.txt\")}\n# endregion\n${include(\"missing.txt\")}\n# region outro\nouto\n# endregion\n".as_bytes(),
+        )?;
+        std::fs::write(dir.child("a.txt"), "a".as_bytes())?;
+
+        assert_eq!(LoaderBuilder::new().allow_all_directives().build().load_file_recursively(dir.child("start.txt"))?, "intro a|outo");
+
+        std::fs::write(dir.child("start.txt"), "${include_section(\"doc.txt\", \"missing\")}".as_bytes())?;
+        match LoaderBuilder::new().allow_all_directives().build().load_file_recursively(dir.child("start.txt")) {
+            Err(Error::SectionNotFound(path, name)) => {
+                assert_eq!(path, dir.child("doc.txt"));
+                assert_eq!(name, "missing");
+            },
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[case(IndentMode::Literal, "\t ${include_indent(\"a.txt\")}", "\t a\n\t b")]
+    #[case(IndentMode::SpacesFromColumn(4), "\t ${include_indent(\"a.txt\")}", "\t a\n     b")]
+    #[case(IndentMode::SpacesFromColumn(4), " \t${include_indent(\"a.txt\")}", " \ta\n    b")]
+    #[case(IndentMode::SpacesFromColumn(8), "\t${include_indent(\"a.txt\")}", "\ta\n        b")]
+    #[case(IndentMode::SpacesFromColumn(4), "\t${include_with(\"\t\", \"a.txt\")}", "\ta\n\tb")]
+    fn should_normalize_indentation(
+        #[case] indent_mode: IndentMode,
+        #[case] content: &str,
+        #[case] expectation: &str,
+    ) -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::write(dir.child("start.txt"), content.as_bytes())?;
+        std::fs::write(dir.child("a.txt"), "a\nb".as_bytes())?;
+
+        let loader = LoaderBuilder::new().allow_all_directives().indent_mode(indent_mode).build();
+        assert_eq!(loader.load_file_recursively(dir.child("start.txt"))?, expectation);
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[case("key: ${include_indent(\"a.txt\")}", "key: a\n     b")]
+    #[case("\t- ${include_indent(\"a.txt\")}", "\t- a\n\t  b")]
+    #[case("  ä ${include_indent(\"a.txt\")} end", "  ä a\n    b end")]
+    #[case("x\n\n  ${include_indent(\"a.txt\")}", "x\n\n  a\n  b")]
+    #[case("x ${include(\"a.txt\")}", "x a\nb")]
+    fn should_align_includes_after_text(
+        #[case] content: &str,
+        #[case] expectation: &str,
+    ) -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::write(dir.child("start.txt"), content.as_bytes())?;
+        std::fs::write(dir.child("a.txt"), "a\nb\n".as_bytes())?;
+
+        assert_eq!(Loader::new().load_file_recursively(dir.child("start.txt"))?, expectation);
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_load_many_roots_independently() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::write(dir.child("a.txt"), "a ${include(\"shared.txt\")}".as_bytes())?;
+        std::fs::write(dir.child("b.txt"), "b ${include(\"cycle.txt\")}".as_bytes())?;
+        std::fs::write(dir.child("c.txt"), "c ${include(\"shared.txt\")}".as_bytes())?;
+        std::fs::write(dir.child("cycle.txt"), "${include(\"b.txt\")}".as_bytes())?;
+        std::fs::write(dir.child("shared.txt"), "shared".as_bytes())?;
+
+        let loader = LoaderBuilder::new().forbid_duplicate_includes(true).build();
+        let results = loader.load_many(&[dir.child("a.txt"), dir.child("b.txt"), dir.child("c.txt")]);
+
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].as_ref().ok().map(String::as_str), Some("a shared"));
+        match &results[1] {
+            Err(Error::CyclicDependency { from, to, .. }) => {
+                assert_eq!(from, &dir.child("cycle.txt"));
+                assert_eq!(to, &dir.child("b.txt"));
+            },
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert_eq!(results[2].as_ref().ok().map(String::as_str), Some("c shared"));
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[cfg(unix)]
+    fn should_include_command_output() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::create_dir(dir.child("sub"))?;
+        std::fs::write(dir.child("start.txt"), "${include(\"sub/cmd.txt\")}".as_bytes())?;
+        std::fs::write(dir.child("sub/cmd.txt"), "- ${include_cmd(\"printf 'a\\nb\\n'; ls\")}".as_bytes())?;
+        std::fs::write(dir.child("sub/file.txt"), "".as_bytes())?;
+
+        let loader = LoaderBuilder::new().allow_all_directives().allow_commands(true).build();
+        assert_eq!(loader.load_file_recursively(dir.child("start.txt"))?, "- a\n  b\n  cmd.txt\n  file.txt");
+
+        match LoaderBuilder::new().allow_all_directives().build().load_file_recursively(dir.child("start.txt")) {
+            Err(Error::CommandNotAllowed(command)) => assert_eq!(command, "printf 'a\\nb\\n'; ls"),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        std::fs::write(dir.child("sub/cmd.txt"), "${include_cmd(\"echo oops >&2; exit 3\")}".as_bytes())?;
+        match loader.load_file_recursively(dir.child("start.txt")) {
+            Err(Error::CommandFailed(_, status, stderr)) => {
+                assert_eq!(status.code(), Some(3));
+                assert_eq!(stderr, "oops");
+            },
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        Ok(())
+    }
+
+    /// A [FileSource] reading from the file system and counting the reads per file.
+    #[derive(Default)]
+    struct CountingSource {
+        reads: Arc<Mutex<HashMap<PathBuf, usize>>>,
+    }
+
+    impl FileSource for CountingSource {
+        fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+            std::fs::canonicalize(path)
+        }
+
+        fn open(&self, path: &Path) -> io::Result<Box<dyn io::Read + '_>> {
+            *self.reads.lock().unwrap().entry(path.to_owned()).or_default() += 1;
+            Ok(Box::new(std::fs::File::open(path)?))
+        }
+    }
+
+    #[rstest]
+    fn should_not_read_files_of_disabled_conditional_includes() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::write(
+            dir.child("start.txt"),
+            "${include_if(\"RFL_TEST_DISABLED\", \"a.txt\")}|${include_if(\"RFL_TEST_ENABLED\", \"b.txt\")}".as_bytes(),
+        )?;
+        std::fs::write(dir.child("a.txt"), "a".as_bytes())?;
+        std::fs::write(dir.child("b.txt"), "b".as_bytes())?;
+        std::env::remove_var("RFL_TEST_DISABLED");
+        std::env::set_var("RFL_TEST_ENABLED", "1");
+
+        let source = CountingSource::default();
+        let reads = Arc::clone(&source.reads);
+        let loader = LoaderBuilder::new().allow_all_directives().file_source(source).build();
+        let result = loader.load_file_recursively(dir.child("start.txt"));
+        let tree = loader.build_tree(dir.child("start.txt"));
+        std::env::remove_var("RFL_TEST_ENABLED");
+        assert_eq!(result?, "|b");
+        assert_eq!(tree?.children().len(), 1);
+
+        let reads = reads.lock().unwrap();
+        assert_eq!(reads.get(&dir.child("a.txt").canonicalize()?), None);
+        assert_eq!(reads.get(&dir.child("b.txt").canonicalize()?), Some(&2));
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_include_data_uris() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::write(
+            dir.child("start.txt"),
+            "- ${include_indent(\"data:text/plain,a%0A${include(%22b.txt%22)}\")}".as_bytes(),
+        )?;
+
+        let loader = LoaderBuilder::new().forbid_duplicate_includes(true).build();
+        assert_eq!(loader.load_file_recursively(dir.child("start.txt"))?, "- a\n  ${include(\"b.txt\")}");
+        assert!(loader.build_tree(dir.child("start.txt"))?.children().is_empty());
+
+        std::fs::write(dir.child("start.txt"), "${include(\"data:text/plain\")}".as_bytes())?;
+        match loader.load_file_recursively(dir.child("start.txt")) {
+            Err(Error::InvalidDataUri(uri, _)) => assert_eq!(uri, "data:text/plain"),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[case(true, "${include(a.txt)} ${include(\"a.txt\")}\n  ${include_indent( sub/b.txt )}", "a a\n  b\n  b")]
+    #[case(true, "\\${include(a.txt)} ${include(a b.txt)} ${include(a\"b.txt)}", "${include(a.txt)} ${include(a b.txt)} ${include(a\"b.txt)}")]
+    #[case(false, "${include(a.txt)} ${include(\"a.txt\")}", "${include(a.txt)} a")]
+    fn should_include_unquoted_paths(
+        #[case] allow_unquoted_paths: bool,
+        #[case] content: &str,
+        #[case] expectation: &str,
+    ) -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::create_dir(dir.child("sub"))?;
+        std::fs::write(dir.child("start.txt"), content.as_bytes())?;
+        std::fs::write(dir.child("a.txt"), "a".as_bytes())?;
+        std::fs::write(dir.child("sub/b.txt"), "b\nb".as_bytes())?;
+
+        let loader = LoaderBuilder::new().allow_unquoted_paths(allow_unquoted_paths).build();
+        assert_eq!(loader.load_file_recursively(dir.child("start.txt"))?, expectation);
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[case(false, "a  \n\t${include(\"b.txt\")} \t\r\n \t\nc\t", "a  \n\tb \t\n\t\t \t\r\n \t\nc\t")]
+    #[case(true, "a  \n\t${include(\"b.txt\")} \t\r\n \t\nc\t", "a\n\tb\n\r\n\nc")]
+    #[case(true, "${include_indent(\"b.txt\")}  ", "b\n")]
+    fn should_trim_trailing_whitespace(
+        #[case] trim_trailing_whitespace: bool,
+        #[case] content: &str,
+        #[case] expectation: &str,
+    ) -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::write(dir.child("start.txt"), content.as_bytes())?;
+        std::fs::write(dir.child("b.txt"), "b \t\n\t\t\n".as_bytes())?;
+
+        let loader = LoaderBuilder::new().trim_trailing_whitespace(trim_trailing_whitespace).build();
+        assert_eq!(loader.load_file_recursively(dir.child("start.txt"))?, expectation);
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_load_from_reader() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::create_dir(dir.child("sub"))?;
+        std::fs::write(dir.child("sub/a.txt"), "a ${include(\"../b.txt\")}".as_bytes())?;
+        std::fs::write(dir.child("b.txt"), "b".as_bytes())?;
+        std::fs::write(dir.child("root.txt"), "root ${include(\"sub/a.txt\")}".as_bytes())?;
+
+        let loader = Loader::new();
+        let reader = "from reader: ${include(\"a.txt\")}".as_bytes();
+        assert_eq!(loader.load_from_reader(reader, dir.child("sub"))?, "from reader: a b");
+
+        let file = std::fs::File::open(dir.child("root.txt"))?;
+        assert_eq!(loader.load_from_reader(file, dir.path())?, "root a b");
+
+        std::fs::write(dir.child("b.txt"), "${include(\"sub/a.txt\")}".as_bytes())?;
+        match loader.load_from_reader("${include(\"a.txt\")}".as_bytes(), dir.child("sub")) {
+            Err(Error::CyclicDependency { from, to, .. }) => {
+                assert_eq!(from.canonicalize()?, dir.child("b.txt").canonicalize()?);
+                assert_eq!(to.canonicalize()?, dir.child("sub/a.txt").canonicalize()?);
+            },
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[case(None, "a", "a")]
+    #[case(None, "a\n\n", "a\n\n")]
+    #[case(Some(true), "a", "a\n")]
+    #[case(Some(true), "a\n", "a\n")]
+    #[case(Some(true), "a\n\n\n", "a\n")]
+    #[case(Some(true), "a\r\n\r\n", "a\r\n")]
+    #[case(Some(false), "a", "a")]
+    #[case(Some(false), "a\n", "a")]
+    #[case(Some(false), "a\r\n\n", "a")]
+    fn should_ensure_final_newline(
+        #[case] ensure_final_newline: Option<bool>,
+        #[case] content: &str,
+        #[case] expectation: &str,
+    ) -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::write(dir.child("a.txt"), content.as_bytes())?;
+
+        let loader = LoaderBuilder::new().ensure_final_newline(ensure_final_newline).build();
+        assert_eq!(loader.load_file_recursively(dir.child("a.txt"))?, expectation);
+        assert_eq!(loader.load_resolved(content.to_owned(), dir.child("a.txt"))?, expectation);
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[case(5, true)]
+    #[case(4, false)]
+    fn should_limit_expansions(
+        #[case] max_expansions: usize,
+        #[case] succeeds: bool,
+    ) -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::write(
+            dir.child("start.txt"),
+            "${include(\"a.txt\")} ${include(\"a.txt\")} \\${include(\"a.txt\")} ${self_path}".as_bytes(),
+        )?;
+        std::fs::write(dir.child("a.txt"), "${include(\"b.txt\")}".as_bytes())?;
+        std::fs::write(dir.child("b.txt"), "b".as_bytes())?;
+
+        let loader = LoaderBuilder::new().allow_all_directives().max_expansions(max_expansions).build();
+        for _ in 0..2 {
+            match loader.load_file_recursively(dir.child("start.txt")) {
+                Ok(_) if succeeds => {},
+                Err(Error::ExpansionBudgetExceeded(limit)) if !succeeds => assert_eq!(limit, max_expansions),
+                other => panic!("unexpected result {:?}", other),
+            }
+            match loader.resolve_fragments(dir.child("start.txt")) {
+                Ok(_) if succeeds => {},
+                Err(Error::ExpansionBudgetExceeded(_)) if !succeeds => {},
+                other => panic!("unexpected result {:?}", other),
+            }
+        }
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[case(CommentStyle::Line("//".to_owned()), "a\n  // >>> begin sub/b.txt\n  b\n  // >>> begin ../c.txt\n  c\n  // <<< end ../c.txt\n  // <<< end sub/b.txt\nd")]
+    #[case(CommentStyle::Block("<!--".to_owned(), "-->".to_owned()), "a\n  <!-- >>> begin sub/b.txt -->\n  b\n  <!-- >>> begin ../c.txt -->\n  c\n  <!-- <<< end ../c.txt -->\n  <!-- <<< end sub/b.txt -->\nd")]
+    fn should_annotate_boundaries(
+        #[case] style: CommentStyle,
+        #[case] expectation: &str,
+    ) -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::create_dir(dir.child("sub"))?;
+        std::fs::write(dir.child("start.txt"), "a\n  ${include_indent(\"sub/b.txt\")}\nd".as_bytes())?;
+        std::fs::write(dir.child("sub/b.txt"), "b\n${include(\"../c.txt\")}\n".as_bytes())?;
+        std::fs::write(dir.child("c.txt"), "c\n".as_bytes())?;
+
+        let loader = LoaderBuilder::new().annotate_boundaries(style).build();
+        assert_eq!(loader.load_file_recursively(dir.child("start.txt"))?, expectation);
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[case(false, None)]
+    #[case(true, Some("caf\u{FFFD} \u{FFFD}5"))]
+    fn should_decode_lossily(
+        #[case] lossy: bool,
+        #[case] expectation: Option<&str>,
+    ) -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::write(dir.child("start.txt"), b"caf\xE9 ${include(\"price.txt\")}")?;
+        std::fs::write(dir.child("price.txt"), b"\x805")?;
+
+        let result = LoaderBuilder::new().lossy_decoding(lossy).build().load_file_recursively(dir.child("start.txt"));
+        match expectation {
+            Some(expectation) => assert_eq!(result?, expectation),
+            None => assert!(matches!(result, Err(Error::InvalidUtf8(_, 3)))),
+        }
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[cfg(feature = "encoding")]
+    fn should_decode_with_explicit_encoding() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::write(dir.child("start.txt"), b"caf\xE9 ${include(\"price.txt\")}")?;
+        std::fs::write(dir.child("price.txt"), b"\x805")?;
+
+        let loader = LoaderBuilder::new().encoding("windows-1252").build();
+        assert_eq!(loader.load_file_recursively(dir.child("start.txt"))?, "caf\u{E9} \u{20AC}5");
+
+        let loader = LoaderBuilder::new().encoding("ebcdic").build();
+        assert!(matches!(loader.load_file_recursively(dir.child("start.txt")), Err(Error::UnknownEncoding(_))));
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_rewrite_paths() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::create_dir(dir.child("new"))?;
+        std::fs::write(
+            dir.child("start.txt"),
+            "${include(\"old/a.txt\")} ${include(\"b.txt\")} ${include(\"missing.txt\" else \"old/b.txt\")}".as_bytes(),
+        )?;
+        std::fs::write(dir.child("new/a.txt"), "a".as_bytes())?;
+        std::fs::write(dir.child("new/b.txt"), "new b".as_bytes())?;
+        std::fs::write(dir.child("b.txt"), "b".as_bytes())?;
+
+        let loader = LoaderBuilder::new()
+            .rewrite_path(|path| path.strip_prefix("old/").map(|rest| format!("new/{}", rest)))
+            .build();
+        assert_eq!(loader.load_file_recursively(dir.child("start.txt"))?, "a b new b");
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_report_path_of_unreadable_files() -> Result<(), Error> {
+        struct LockedSource;
+
+        impl FileSource for LockedSource {
+            fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+                Ok(path.to_owned())
+            }
+
+            fn open(&self, path: &Path) -> io::Result<Box<dyn io::Read + '_>> {
+                match path.ends_with("locked.txt") {
+                    true => Err(io::Error::new(io::ErrorKind::PermissionDenied, "permission denied")),
+                    false => Ok(Box::new(b"${include(\"locked.txt\")}".as_slice())),
+                }
+            }
+        }
+
+        let loader = LoaderBuilder::new().file_source(LockedSource).build();
+        let error = loader.load_file_recursively("dir/start.txt").unwrap_err();
+        assert!(matches!(&error, Error::ReadFailed(path, _) if path == Path::new("dir/locked.txt")));
+        assert_eq!(error.to_string(), "failed to read 'dir/locked.txt': permission denied");
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_resolve_paths_relative_to_root_document() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::create_dir_all(dir.child("sub/deeper"))?;
+        std::fs::create_dir(dir.child("shared"))?;
+        std::fs::write(dir.child("start.txt"), "${include(\"sub/a.txt\")}".as_bytes())?;
+        std::fs::write(dir.child("sub/a.txt"), "a ${include(\"deeper/b.txt\")}".as_bytes())?;
+        std::fs::write(dir.child("sub/deeper/b.txt"), "b ${include(\"^/shared/x.txt\")} ${include(\"x.txt\")}".as_bytes())?;
+        std::fs::write(dir.child("sub/deeper/x.txt"), "local x".as_bytes())?;
+        std::fs::write(dir.child("shared/x.txt"), "shared x".as_bytes())?;
+
+        let loader = Loader::new();
+        assert_eq!(loader.load_file_recursively(dir.child("start.txt"))?, "a b shared x local x");
+        assert_eq!(loader.load_from_reader("${include(\"^/x.txt\")}".as_bytes(), dir.child("shared"))?, "shared x");
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[cfg(unix)]
+    fn should_not_expand_tilde_by_default() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::create_dir(dir.child("~"))?;
+        std::fs::write(dir.child("start.txt"), "${include(\"~/x.txt\")} ${include(\"a~b.txt\")}".as_bytes())?;
+        std::fs::write(dir.child("~/x.txt"), "local".as_bytes())?;
+        std::fs::write(dir.child("a~b.txt"), "tilde".as_bytes())?;
+
+        let loader = LoaderBuilder::new().build();
+        assert_eq!(loader.load_file_recursively(dir.child("start.txt"))?, "local tilde");
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_expand_tilde_to_home_dir() -> Result<(), Error> {
+        let Some(home) = dependency_path::home_dir() else {
+            return Ok(());
+        };
+        let dir = TempDir::new()?;
+        std::fs::write(dir.child("start.txt"), "${include(\"~/rfl-test-missing.txt\")}".as_bytes())?;
+
+        let loader = LoaderBuilder::new().expand_tilde(true).build();
+        match loader.load_file_recursively(dir.child("start.txt")) {
+            Err(Error::FileNotFound(path)) => assert_eq!(path, home.join("rfl-test-missing.txt")),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[cfg(feature = "cache")]
+    fn should_clear_caches_on_reset() -> Result<(), Error> {
+        use crate::{CacheStats, DirectoryCache};
+
+        let dir = TempDir::new()?;
+        std::fs::write(dir.child("start.txt"), "${include(\"a.txt\")}".as_bytes())?;
+        std::fs::write(dir.child("a.txt"), "a".as_bytes())?;
+
+        let loader = LoaderBuilder::new().disk_cache(DirectoryCache::new(dir.child("cache"))).build();
+        assert_eq!(loader.load_file_recursively(dir.child("start.txt"))?, "a");
+        std::thread::scope(|scope| {
+            let poisoning = scope.spawn(|| {
+                let _cache_stats = loader.cache_stats.lock().unwrap();
+                panic!("poisoning the loader");
+            });
+            assert!(poisoning.join().is_err());
+        });
+        assert!(loader.cache_stats.is_poisoned());
+
+        loader.reset();
+        assert_eq!(loader.cache_stats(), CacheStats::default());
+        assert!(!dir.child("cache").exists());
+        assert_eq!(loader.load_file_recursively(dir.child("start.txt"))?, "a");
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[cfg(feature = "glob")]
+    fn should_include_files_matching_glob() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::create_dir(dir.child("parts"))?;
+        std::fs::write(dir.child("start.txt"), "  ${include_glob(\"parts/*.txt\", indent=\"  \")}\n${include_glob(\"none/*.txt\")}|".as_bytes())?;
+        std::fs::write(dir.child("parts/b.txt"), "b\n".as_bytes())?;
+        std::fs::write(dir.child("parts/a.txt"), "a1\na2 ${include(\"../c.md\")}\n".as_bytes())?;
+        std::fs::write(dir.child("parts/skip.md"), "skipped".as_bytes())?;
+        std::fs::write(dir.child("c.md"), "c".as_bytes())?;
+
+        let loader = LoaderBuilder::new().allow_all_directives().build();
+        assert_eq!(loader.load_file_recursively(dir.child("start.txt"))?, "  a1\n  a2 c\n  b\n|");
+
+        let tree = loader.build_tree(dir.child("start.txt"))?;
+        let children: Vec<_> = tree.children().iter().map(|it| it.path().to_owned()).collect();
+        assert_eq!(children, vec![dir.child("parts/a.txt"), dir.child("parts/b.txt")]);
+
+        let fragments = loader.resolve_fragments(dir.child("start.txt"))?;
+        let texts: Vec<_> = fragments.iter().map(|it| it.text()).collect();
+        assert_eq!(texts, vec!["  ", "a1\n  a2 ", "c", "\n  ", "b", "\n", "|"]);
+
+        #[cfg(feature = "cache")]
+        {
+            let loader = LoaderBuilder::new().allow_all_directives().disk_cache(crate::DirectoryCache::new(dir.child("cache"))).build();
+            for _ in 0..2 {
+                assert_eq!(loader.load_file_recursively(dir.child("start.txt"))?, "  a1\n  a2 c\n  b\n|");
+            }
+        }
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[cfg(feature = "glob")]
+    #[case(false, "${include_where(\"posts/*.md\", \"draft == false\")}", Ok("---\ndraft: false\n---\npublished"))]
+    #[case(true, "${include_where(\"posts/*.md\", \"draft != true\")}", Ok("---\ndraft: false\n---\npublished\nplain"))]
+    #[case(false, "${include_where(\"posts/*.md\", \"draft\")}", Err("invalid condition 'draft', expected 'key == value' or 'key != value'"))]
+    fn should_include_files_matching_frontmatter(
+        #[case] include_without_frontmatter: bool,
+        #[case] content: &str,
+        #[case] expectation: Result<&str, &str>,
+    ) -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::create_dir(dir.child("posts"))?;
+        std::fs::write(dir.child("start.txt"), content.as_bytes())?;
+        std::fs::write(dir.child("posts/a.md"), "---\ndraft: true\n---\nunpublished\n".as_bytes())?;
+        std::fs::write(dir.child("posts/b.md"), "---\ndraft: false\n---\npublished\n".as_bytes())?;
+        std::fs::write(dir.child("posts/c.md"), "plain\n".as_bytes())?;
+
+        let loader = LoaderBuilder::new().allow_all_directives().include_without_frontmatter(include_without_frontmatter).build();
+        let result = loader.load_file_recursively(dir.child("start.txt"));
+        assert_eq!(result.as_deref().map_err(|e| e.to_string()), expectation.map_err(str::to_owned));
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[case(2, Ok("abc"))]
+    #[case(1, Err(2))]
+    fn should_limit_the_depth(#[case] max_depth: usize, #[case] expectation: Result<&str, usize>) -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::write(dir.child("a.txt"), "a${include(\"b.txt\")}".as_bytes())?;
+        std::fs::write(dir.child("b.txt"), "b${include(\"c.txt\")}".as_bytes())?;
+        std::fs::write(dir.child("c.txt"), "c".as_bytes())?;
+
+        let options = LoadOptions { max_depth: Some(max_depth), ..Default::default() };
+        match (crate::load_file_recursively_with(dir.child("a.txt"), options), expectation) {
+            (Ok(result), Ok(expectation)) => assert_eq!(result, expectation),
+            (Err(Error::MaxDepthExceeded(path, limit)), Err(depth)) => {
+                assert_eq!(path, dir.child("c.txt"));
+                assert_eq!(limit, max_depth);
+                assert_eq!(depth, max_depth + 1);
+            },
+            (other, _) => panic!("unexpected result {:?}", other),
+        }
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_restrict_files_to_root() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::create_dir(dir.child("docs"))?;
+        std::fs::write(dir.child("docs/start.txt"), "${include(\"inner.txt\")} ${include(\"../outer.txt\")}".as_bytes())?;
+        std::fs::write(dir.child("docs/inner.txt"), "inner".as_bytes())?;
+        std::fs::write(dir.child("outer.txt"), "outer".as_bytes())?;
+
+        let loader = LoaderBuilder::new().restrict_to(dir.child("docs")).build();
+        match loader.load_file_recursively(dir.child("docs/start.txt")) {
+            Err(Error::OutsideRoot(path, root)) => {
+                assert_eq!(path, dir.child("docs/../outer.txt"));
+                assert_eq!(root, std::fs::canonicalize(dir.child("docs"))?);
+            },
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        let loader = LoaderBuilder::new().restrict_to(dir.path()).build();
+        assert_eq!(loader.load_file_recursively(dir.child("docs/start.txt"))?, "inner outer");
+
+        let loader = LoaderBuilder::new().restrict_to(dir.child("docs")).file_source(CountingSource::default()).build();
+        assert!(matches!(
+            loader.load_file_recursively(dir.child("docs/start.txt")),
+            Err(Error::OutsideRoot(path, _)) if path == dir.child("docs/../outer.txt")
+        ));
+
+        std::fs::write(dir.child("docs/start.txt"), "${include(\"virtual.txt\")} ${include(\"../secret.txt\")}".as_bytes())?;
+        let loader = LoaderBuilder::new()
+            .restrict_to(dir.child("docs"))
+            .overrides(HashMap::from([
+                (dir.child("docs/virtual.txt"), "virtual".to_owned()),
+                (dir.child("secret.txt"), "secret".to_owned()),
+            ]))
+            .build();
+        assert!(matches!(
+            loader.load_file_recursively(dir.child("docs/start.txt")),
+            Err(Error::OutsideRoot(path, _)) if path == dir.child("docs/../secret.txt")
+        ));
+
+        #[cfg(feature = "http")]
+        {
+            std::fs::write(dir.child("docs/start.txt"), "${include(\"http://127.0.0.1:9/remote.txt\")}".as_bytes())?;
+            let loader = LoaderBuilder::new().restrict_to(dir.child("docs")).allow_http(true).build();
+            assert!(!matches!(loader.load_file_recursively(dir.child("docs/start.txt")), Err(Error::OutsideRoot(..))));
+        }
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[rstest]
+    #[case(CycleKey::Canonical)]
+    #[case(CycleKey::Literal)]
+    fn should_restrict_symlinks_pointing_outside_of_root(#[case] cycle_detection: CycleKey) -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        for name in ["docs", "docs/inner", "secrets"] {
+            std::fs::create_dir(dir.child(name))?;
+        }
+        std::fs::write(dir.child("docs/inner/a.txt"), "a".as_bytes())?;
+        std::fs::write(dir.child("secrets/key.txt"), "key".as_bytes())?;
+        std::os::unix::fs::symlink(dir.child("docs/inner/a.txt"), dir.child("docs/link.txt"))?;
+        std::os::unix::fs::symlink(dir.child("secrets/key.txt"), dir.child("docs/key.txt"))?;
+        std::os::unix::fs::symlink(dir.child("secrets"), dir.child("docs/secrets"))?;
+
+        let loader = LoaderBuilder::new()
+            .restrict_to(dir.child("docs"))
+            .cycle_detection(cycle_detection)
+            .build();
+        for (include, escaping) in [("key.txt", "docs/key.txt"), ("secrets/key.txt", "docs/secrets/key.txt")] {
+            std::fs::write(dir.child("docs/start.txt"), format!("${{include(\"link.txt\")}} ${{include(\"{}\")}}", include))?;
+            match loader.load_file_recursively(dir.child("docs/start.txt")) {
+                Err(Error::OutsideRoot(path, root)) => {
+                    assert_eq!(path, dir.child(escaping));
+                    assert_eq!(root, std::fs::canonicalize(dir.child("docs"))?);
+                },
+                other => panic!("unexpected result {:?}", other),
+            }
+        }
+
+        std::fs::write(dir.child("docs/start.txt"), "${include(\"link.txt\")}".as_bytes())?;
+        assert_eq!(loader.load_file_recursively(dir.child("docs/start.txt"))?, "a");
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_convert_between_builder_and_load_options() {
+        let options = LoadOptions {
+            max_depth: Some(3),
+            restrict_to: Some(PathBuf::from("docs")),
+            base_dir_override: Some(PathBuf::from("src")),
+            escaped_output: EscapedOutput::Literal,
+            lenient: true,
+            ..Default::default()
+        };
+
+        assert_eq!(LoaderBuilder::from(options.clone()).load_options(), options);
+        assert_eq!(LoaderBuilder::new().max_depth(3).load_options().max_depth, Some(3));
+    }
+
+    #[rstest]
+    #[case(true)]
+    #[case(false)]
+    fn should_warn_about_redundant_paths(#[case] warn_redundant_paths: bool) -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        for name in ["dir", "dir/x", "dir/y"] {
+            std::fs::create_dir(dir.child(name))?;
+        }
+        std::fs::write(
+            dir.child("dir/start.txt"),
+            "${include(\"./a.txt\")}${include(\"../dir/a.txt\")}${include(\"a.txt\")}${include(\"x/x.txt\")}${include(\"y/y.txt\")}".as_bytes(),
+        )?;
+        std::fs::write(dir.child("dir/a.txt"), "a".as_bytes())?;
+        std::fs::write(dir.child("dir/x/x.txt"), "${include(\"../shared.txt\")}".as_bytes())?;
+        std::fs::write(dir.child("dir/y/y.txt"), "${include(\"../shared.txt\")}".as_bytes())?;
+        std::fs::write(dir.child("dir/shared.txt"), "s".as_bytes())?;
+
+        let loader = LoaderBuilder::new().warn_redundant_paths(warn_redundant_paths).build();
+        let (text, warnings) = loader.load_with_warnings(dir.child("dir/start.txt"))?;
+        assert_eq!(text, "aaass");
+
+        match warn_redundant_paths {
+            true => {
+                assert_eq!(warnings.redundant_paths().len(), 1);
+                let redundant = &warnings.redundant_paths()[0];
+                assert_eq!(redundant.canonical(), std::fs::canonicalize(dir.child("dir/a.txt"))?);
+                assert_eq!(redundant.sources(), [dir.child("dir/../dir/a.txt"), dir.child("dir/./a.txt")]);
+            },
+            false => assert!(warnings.is_empty()),
+        }
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[case(20, Ok("start\nshort 0123456789\n"))]
+    #[case(15, Err((2, 16)))]
+    fn should_limit_the_line_length(#[case] max_line_length: usize, #[case] expectation: Result<&str, (usize, usize)>) -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::write(dir.child("start.txt"), "start\nshort ${include(\"long.txt\")}\n".as_bytes())?;
+        std::fs::write(dir.child("long.txt"), "0123456789\n".as_bytes())?;
+
+        let loader = LoaderBuilder::new().max_line_length(max_line_length).build();
+        match (loader.load_file_recursively(dir.child("start.txt")), expectation) {
+            (Ok(result), Ok(expectation)) => assert_eq!(result, expectation),
+            (Err(Error::LineTooLong(path, line, length)), Err(expectation)) => {
+                assert_eq!(path, dir.child("start.txt"));
+                assert_eq!((line, length), expectation);
+            },
+            (other, _) => panic!("unexpected result {:?}", other),
+        }
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_repeat_includes() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::write(dir.child("start.txt"), "rows:\n  ${include_repeat(\"row.txt\", 3)}\n".as_bytes())?;
+        std::fs::write(dir.child("row.txt"), "- ${include(\"cell.txt\")}\n".as_bytes())?;
+        std::fs::write(dir.child("cell.txt"), "x".as_bytes())?;
+
+        let loader = LoaderBuilder::new().allow_all_directives().build();
+        assert_eq!(loader.load_file_recursively(dir.child("start.txt"))?, "rows:\n  - x\n  - x\n  - x\n");
+
+        let fragments = loader.resolve_fragments(dir.child("start.txt"))?;
+        let texts: Vec<_> = fragments.iter().map(|it| it.text()).collect();
+        assert_eq!(texts, vec!["rows:\n  ", "- ", "x", "\n  ", "- ", "x", "\n  ", "- ", "x", "\n"]);
+
+        let loader = LoaderBuilder::new().allow_all_directives().max_expansions(3).build();
+        assert!(matches!(loader.load_file_recursively(dir.child("start.txt")), Err(Error::ExpansionBudgetExceeded(3))));
+
+        std::fs::write(dir.child("start.txt"), "${include_repeat(\"row.txt\", 100000000000000)}".as_bytes())?;
+        match LoaderBuilder::new().allow_all_directives().build().load_file_recursively(dir.child("start.txt")) {
+            Err(Error::RepeatTooLarge(path, 100000000000000)) => assert_eq!(path, dir.child("row.txt")),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert!(matches!(LoaderBuilder::new().allow_all_directives().build().resolve_fragments(dir.child("start.txt")), Err(Error::RepeatTooLarge(..))));
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_resolve_includes_ignoring_case() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::create_dir(dir.child("Parts"))?;
+        std::fs::write(dir.child("start.txt"), "${include(\"parts/header.TXT\")}".as_bytes())?;
+        std::fs::write(dir.child("Parts/Header.txt"), "header".as_bytes())?;
+
+        assert!(matches!(Loader::new().load_file_recursively(dir.child("start.txt")), Err(Error::FileNotFound(_))));
+
+        let loader = LoaderBuilder::new().resolve_case_insensitive(true).build();
+        assert_eq!(loader.load_file_recursively(dir.child("start.txt"))?, "header");
+
+        std::fs::write(dir.child("Parts/HEADER.txt"), "other".as_bytes())?;
+        assert!(matches!(loader.load_file_recursively(dir.child("start.txt")), Err(Error::AmbiguousCase(..))));
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[cfg(feature = "tracing")]
+    #[case(false)]
+    #[cfg_attr(feature = "parallel", case(true))]
+    fn should_nest_spans_like_includes(#[case] _parallel: bool) -> Result<(), Error> {
+        use tracing::{field::{Field, Visit}, span, Event, Id, Metadata, Subscriber};
+
+        /// Records the path and parent of every span, and the events per span.
+        #[derive(Default)]
+        struct Recorder {
+            spans: Mutex<Vec<(String, Option<u64>)>>,
+            metadata: Mutex<Vec<&'static Metadata<'static>>>,
+            events: Mutex<Vec<(String, Option<u64>)>>,
+            entered: Mutex<HashMap<std::thread::ThreadId, Vec<u64>>>,
+        }
+
+        struct FieldValue(&'static str, String);
+
+        impl Visit for FieldValue {
+            fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+                if field.name() == self.0 {
+                    self.1 = format!("{:?}", value);
+                }
+            }
+        }
+
+        impl Recorder {
+            fn current(&self) -> Option<u64> {
+                self.entered.lock().unwrap().get(&std::thread::current().id())?.last().copied()
+            }
+        }
+
+        impl Subscriber for Recorder {
+            fn enabled(&self, _: &Metadata<'_>) -> bool {
+                true
+            }
+
+            fn new_span(&self, attributes: &span::Attributes<'_>) -> Id {
+                let mut path = FieldValue("path", String::new());
+                attributes.record(&mut path);
+                let mut spans = self.spans.lock().unwrap();
+                spans.push((path.1, self.current()));
+                self.metadata.lock().unwrap().push(attributes.metadata());
+                Id::from_u64(spans.len() as u64)
+            }
+
+            fn current_span(&self) -> tracing_core::span::Current {
+                match self.current() {
+                    Some(id) => tracing_core::span::Current::new(Id::from_u64(id), self.metadata.lock().unwrap()[id as usize - 1]),
+                    None => tracing_core::span::Current::none(),
+                }
+            }
+
+            fn record(&self, _: &Id, _: &span::Record<'_>) {}
+
+            fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+            fn event(&self, event: &Event<'_>) {
+                let mut message = FieldValue("message", String::new());
+                event.record(&mut message);
+                self.events.lock().unwrap().push((message.1, self.current()));
+            }
+
+            fn enter(&self, span: &Id) {
+                self.entered.lock().unwrap().entry(std::thread::current().id()).or_default().push(span.into_u64());
+            }
+
+            fn exit(&self, _: &Id) {
+                self.entered.lock().unwrap().entry(std::thread::current().id()).or_default().pop();
+            }
+        }
+
+        let dir = TempDir::new()?;
+        std::fs::write(dir.child("start.txt"), "${include(\"a.txt\")}${include(\"c.txt\")}".as_bytes())?;
+        std::fs::write(dir.child("a.txt"), "${include(\"b.txt\")}".as_bytes())?;
+        std::fs::write(dir.child("b.txt"), "b".as_bytes())?;
+        std::fs::write(dir.child("c.txt"), "c".as_bytes())?;
+
+        let loader = LoaderBuilder::new();
+        #[cfg(feature = "parallel")]
+        let loader = loader.parallel(_parallel);
+        let loader = loader.build();
+        let recorder = Arc::new(Recorder::default());
+        tracing::subscriber::with_default(Arc::clone(&recorder), || loader.load_file_recursively(dir.child("start.txt")))?;
+
+        // Files included in parallel may start in any order, so spans are compared
+        // by their paths.
+        let path = |name: &str| dir.child(name).to_string_lossy().into_owned();
+        let spans = recorder.spans.lock().unwrap();
+        let mut nesting: Vec<_> = spans
+            .iter()
+            .map(|(path, parent)| (path.to_owned(), parent.map(|it| spans[it as usize - 1].0.to_owned())))
+            .collect();
+        nesting.sort();
+        assert_eq!(nesting, vec![
+            (path("a.txt"), Some(path("start.txt"))),
+            (path("b.txt"), Some(path("a.txt"))),
+            (path("c.txt"), Some(path("start.txt"))),
+            (path("start.txt"), None),
+        ]);
+        let events = recorder.events.lock().unwrap();
+        for span in 1..=4 {
+            let messages: Vec<_> = events.iter().filter(|it| it.1 == Some(span)).map(|it| it.0.as_str()).collect();
+            assert_eq!(messages, vec!["read", "found includes", "loaded"]);
+        }
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_include_files_newer_than_the_root() -> Result<(), Error> {
+        use std::time::{Duration, SystemTime};
+
+        let dir = TempDir::new()?;
+        std::fs::write(dir.child("start.txt"), "${include_if_newer(\"old.txt\")}|${include_if_newer(\"new.txt\")}".as_bytes())?;
+        std::fs::write(dir.child("old.txt"), "old".as_bytes())?;
+        std::fs::write(dir.child("new.txt"), "new".as_bytes())?;
+        let now = SystemTime::now();
+        for (name, modified) in [("start.txt", now), ("old.txt", now - Duration::from_secs(60)), ("new.txt", now + Duration::from_secs(60))] {
+            std::fs::File::options().write(true).open(dir.child(name))?.set_modified(modified)?;
+        }
+
+        let loader = LoaderBuilder::new().allow_all_directives().build();
+        assert_eq!(loader.load_file_recursively(dir.child("start.txt"))?, "|new");
+
+        let tree = loader.build_tree(dir.child("start.txt"))?;
+        let children: Vec<_> = tree.children().iter().map(|it| it.path().to_owned()).collect();
+        assert_eq!(children, vec![dir.child("new.txt")]);
+
+        #[cfg(feature = "cache")]
+        {
+            let loader = LoaderBuilder::new().allow_all_directives().disk_cache(crate::DirectoryCache::new(dir.child("cache"))).build();
+            assert_eq!(loader.load_file_recursively(dir.child("start.txt"))?, "|new");
+        }
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[case(false, "${include_indent(\"a.txt\")} ${include_name(\"a.txt\")} \\${include_env(\"HOME\")}", Ok("a ${include_name(\"a.txt\")} \\${include_env(\"HOME\")}"))]
+    #[case(true, "${include_indent(\"a.txt\")} \\${include_env(\"HOME\")}\n${include_name(\"a.txt\")}", Err(2))]
+    fn should_only_expand_allowed_directives(
+        #[case] reject_disabled_directives: bool,
+        #[case] content: &str,
+        #[case] expectation: Result<&str, usize>,
+    ) -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::write(dir.child("start.txt"), content.as_bytes())?;
+        std::fs::write(dir.child("a.txt"), "a".as_bytes())?;
+
+        let loader = LoaderBuilder::new()
+            .allowed_directives(["include", "include_indent"])
+            .reject_disabled_directives(reject_disabled_directives)
+            .build();
+        match (loader.load_file_recursively(dir.child("start.txt")), expectation) {
+            (Ok(result), Ok(expectation)) => assert_eq!(result, expectation),
+            (Err(Error::DirectiveNotAllowed(path, line, directive)), Err(expectation)) => {
+                assert_eq!(path, dir.child("start.txt"));
+                assert_eq!(line, expectation);
+                assert_eq!(directive, "${include_name(\"a.txt\")}");
+            },
+            (other, _) => panic!("unexpected result {:?}", other),
+        }
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[cfg(feature = "hash")]
+    fn should_hash_output_and_inputs() -> Result<(), Error> {
+        let load = |text: &str, included: &str| -> Result<crate::LoadResult, Error> {
+            let dir = TempDir::new()?;
+            std::fs::create_dir(dir.child("parts"))?;
+            std::fs::write(dir.child("start.txt"), text.as_bytes())?;
+            std::fs::write(dir.child("parts/a.txt"), included.as_bytes())?;
+            Loader::new().load_with_hashes(dir.child("start.txt"))
+        };
+
+        let result = load("${include(\"parts/a.txt\")}", "a")?;
+        assert_eq!(result.text(), "a");
+        assert_eq!(result, load("${include(\"parts/a.txt\")}", "a")?);
+
+        let changed = load("${include(\"parts/a.txt\")}", "a\n")?;
+        assert_eq!(changed.content_hash(), result.content_hash());
+        assert_ne!(changed.input_hash(), result.input_hash());
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_report_malformed_arguments_in_strict_mode() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::write(dir.child("start.txt"), "${include(\"a.txt\",)}\n${include(\"a.txt\" \"b.txt\")}".as_bytes())?;
+        std::fs::write(dir.child("a.txt"), "a".as_bytes())?;
+
+        assert_eq!(Loader::new().load_file_recursively(dir.child("start.txt"))?, "a\n${include(\"a.txt\" \"b.txt\")}");
+
+        let loader = LoaderBuilder::new().strict_directives(true).build();
+        match loader.load_file_recursively(dir.child("start.txt")) {
+            Err(Error::InvalidArgument(path, line, message)) => {
+                assert_eq!(path, dir.child("start.txt"));
+                assert_eq!(line, 2);
+                assert_eq!(message, "missing comma before `\"b.txt\"` in ${include(\"a.txt\" \"b.txt\")}");
+            },
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_resolve_includes_in_layered_roots() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        for name in ["base", "base/parts", "theme"] {
+            std::fs::create_dir(dir.child(name))?;
+        }
+        std::fs::write(
+            dir.child("base/start.txt"),
+            "${include(\"header.txt\")}|${include(\"parts/body.txt\")}|${include(\"extra.txt\")}".as_bytes(),
+        )?;
+        std::fs::write(dir.child("base/header.txt"), "base header".as_bytes())?;
+        std::fs::write(dir.child("base/parts/body.txt"), "body ${include(\"../footer.txt\")}".as_bytes())?;
+        std::fs::write(dir.child("base/footer.txt"), "base footer".as_bytes())?;
+        std::fs::write(dir.child("base/extra.txt"), "base extra".as_bytes())?;
+        std::fs::write(dir.child("theme/header.txt"), "theme header ${include(\"extra.txt\")}".as_bytes())?;
+        std::fs::write(dir.child("theme/footer.txt"), "theme footer".as_bytes())?;
+
+        let loader = Loader::new();
+        assert_eq!(loader.load_file_recursively(dir.child("base/start.txt"))?, "base header|body base footer|base extra");
+
+        let loader = LoaderBuilder::new().layered_roots([dir.child("theme"), dir.child("base")]).build();
+        assert_eq!(loader.load_file_recursively(dir.child("base/start.txt"))?, "theme header base extra|body theme footer|base extra");
+
+        std::fs::write(dir.child("theme/extra.txt"), "${include(\"header.txt\")}".as_bytes())?;
+        assert!(matches!(loader.load_file_recursively(dir.child("base/start.txt")), Err(Error::CyclicDependency { .. })));
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_diagnose_whole_tree() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::write(
+            dir.child("start.txt"),
+            "${include(\"a.txt\")}\n  ${include(\"missing.txt\")} ${include(\"\")}\n${include(\"b.txt\")}${include(\"a.txt\")}".as_bytes(),
+        )?;
+        std::fs::write(dir.child("a.txt"), "x ${inclde(\"c.txt\")}\n${include(\"start.txt\")}".as_bytes())?;
+        std::fs::write(dir.child("b.txt"), "${include(\"gone.txt\" else \"missing.txt\")}".as_bytes())?;
+
+        let diagnostics = Loader::new().diagnose(dir.child("start.txt"));
+        let actual: Vec<_> = diagnostics
+            .iter()
+            .map(|it| (it.path().to_owned(), it.position(), it.kind()))
+            .collect();
+
+        assert_eq!(actual, vec![
+            (dir.child("a.txt"), Some((1, 3)), DiagnosticKind::UnknownDirective),
+            (dir.child("a.txt"), Some((2, 1)), DiagnosticKind::Cycle),
+            (dir.child("b.txt"), Some((1, 1)), DiagnosticKind::MissingFile),
+            (dir.child("start.txt"), Some((2, 3)), DiagnosticKind::MissingFile),
+            (dir.child("start.txt"), Some((2, 29)), DiagnosticKind::EmptyPath),
+        ]);
+        assert_eq!(diagnostics.iter().nth(1).unwrap().message(), Error::CyclicDependency {
+            from: dir.child("a.txt"),
+            to: dir.child("start.txt"),
+            directive: "${include(\"start.txt\")}".to_owned(),
+            line: 2,
+            chain: [std::fs::canonicalize(dir.child("a.txt"))?, std::fs::canonicalize(dir.child("start.txt"))?].into(),
+        }.to_string());
+
+        let diagnostics = Loader::new().diagnose(dir.child("none.txt"));
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics.into_iter().next().map(|it| (it.position(), it.kind())), Some((None, DiagnosticKind::MissingFile)));
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[cfg(feature = "glob")]
+    #[case("", Ok("1 10 2"))]
+    #[case(", sort=\"lexical\"", Ok("1 10 2"))]
+    #[case(", sort=\"natural\"", Ok("1 2 10"))]
+    #[case(", sort=\"random\"", Err("unknown sort order \"random\", expected lexical, natural, mtime or none"))]
+    fn should_sort_glob_matches(
+        #[case] arguments: &str,
+        #[case] expectation: Result<&str, &str>,
+    ) -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::create_dir(dir.child("parts"))?;
+        std::fs::write(dir.child("start.txt"), format!("${{include_glob(\"parts/part*.txt\"{})}}", arguments))?;
+        for number in ["1", "2", "10"] {
+            std::fs::write(dir.child(format!("parts/part{}.txt", number)), number.as_bytes())?;
+        }
+
+        match (LoaderBuilder::new().allow_all_directives().build().load_file_recursively(dir.child("start.txt")), expectation) {
+            (Ok(result), Ok(expectation)) => assert_eq!(result.replace('\n', " "), expectation),
+            (Err(Error::InvalidArgument(_, _, message)), Err(expectation)) => assert!(message.starts_with(expectation)),
+            (other, _) => panic!("unexpected result {:?}", other),
+        }
+
+        Ok(())
     }
 
-    pub(crate) fn load_file_recursively<P: AsRef<Path>>(&self, path: P) -> Result<String, Error> {
-        self.get_text_for_path(path)
+    #[rstest]
+    #[cfg(feature = "glob")]
+    #[case(&["a", "b"], "", "a\nb|")]
+    #[case(&["a", "b"], r#", sep="\n---\n""#, "a\n---\nb|")]
+    #[case(&["a", "b", "c"], r#", sep=", ""#, "a, b, c|")]
+    #[case(&["a\n", "b\n", "c\n"], r#", sep="""#, "abc|")]
+    #[case(&["a"], r#", sep=", ""#, "a|")]
+    fn should_separate_glob_matches(
+        #[case] contents: &[&str],
+        #[case] arguments: &str,
+        #[case] expectation: &str,
+    ) -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::create_dir(dir.child("parts"))?;
+        std::fs::write(dir.child("start.txt"), format!("${{include_glob(\"parts/*.txt\"{})}}|", arguments))?;
+        for (index, content) in contents.iter().enumerate() {
+            std::fs::write(dir.child(format!("parts/{}.txt", index)), content.as_bytes())?;
+        }
+
+        let loader = LoaderBuilder::new().allow_all_directives().build();
+        assert_eq!(loader.load_file_recursively(dir.child("start.txt"))?, expectation);
+        let fragments = loader.resolve_fragments(dir.child("start.txt"))?;
+        assert_eq!(fragments.iter().map(|it| it.text()).collect::<String>(), expectation);
+
+        Ok(())
     }
 
-    fn get_text_for_path<P: AsRef<Path>>(&self, path: P) -> Result<String, Error> {
-        let path = CanonicalPath::new(path)?;
-        if self.resolution_stack.borrow().contains(&path) {
-            let stack = self.resolution_stack.borrow();
-            let last = stack.last().unwrap();
-            return Err(Error::CyclicDependency(last.source().to_owned(), path.source().to_owned()));
-        } else {
-            self.resolution_stack.borrow_mut().push(path.clone());
+    #[rstest]
+    fn should_separate_listed_files() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::write(dir.child("start.txt"), "${include_list(\"files.txt\", sep=\"\\n--\\n\")}|".as_bytes())?;
+        std::fs::write(dir.child("files.txt"), "a.txt\nb.txt\na.txt\n".as_bytes())?;
+        std::fs::write(dir.child("a.txt"), "a\n".as_bytes())?;
+        std::fs::write(dir.child("b.txt"), "b".as_bytes())?;
+
+        assert_eq!(LoaderBuilder::new().allow_all_directives().build().load_file_recursively(dir.child("start.txt"))?, "a\n--\nb\n--\na|");
+
+        std::fs::write(dir.child("files.txt"), "a.txt\nb.txt\n".as_bytes())?;
+        assert_eq!(LoaderBuilder::new().allow_all_directives().build().load_file_recursively(dir.child("start.txt"))?, "a\n--\nb|");
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_resolve_root_includes_against_overridden_base_dir() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        for name in ["generated", "src", "src/parts"] {
+            std::fs::create_dir(dir.child(name))?;
         }
+        std::fs::write(dir.child("generated/start.txt"), "${include(\"parts/a.txt\")}|${include(\"^/parts/a.txt\")}".as_bytes())?;
+        std::fs::write(dir.child("src/parts/a.txt"), "${include(\"b.txt\")}".as_bytes())?;
+        std::fs::write(dir.child("src/parts/b.txt"), "b".as_bytes())?;
+        std::fs::write(dir.child("src/b.txt"), "wrong".as_bytes())?;
+        std::fs::create_dir(dir.child("generated/parts"))?;
+        std::fs::write(dir.child("generated/parts/a.txt"), "generated".as_bytes())?;
+
+        assert_eq!(Loader::new().load_file_recursively(dir.child("generated/start.txt"))?, "generated|generated");
+
+        let loader = LoaderBuilder::new().base_dir_override(dir.child("src")).build();
+        assert_eq!(loader.load_file_recursively(dir.child("generated/start.txt"))?, "b|generated");
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_abort_loads_taking_too_long() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::write(dir.child("start.txt"), "${include(\"a.txt\")}".as_bytes())?;
+        std::fs::write(dir.child("a.txt"), "a".as_bytes())?;
+        let slow_loader = |max_duration| LoaderBuilder::new()
+            .max_duration(max_duration)
+            .transform(|_, content| {
+                std::thread::sleep(Duration::from_millis(20));
+                Ok(content)
+            })
+            .build();
 
-        let mut content = fs::read_to_string(&path)?;
-        let includes = self.find_includes(&path, &content)?;
-        for include in includes {
-            include.replace(&mut content, || self.get_text_for_path(include.path()))?;
+        let loader = slow_loader(Duration::from_millis(10));
+        match loader.load_file_recursively(dir.child("start.txt")) {
+            Err(Error::Timeout(limit)) => assert_eq!(limit, Duration::from_millis(10)),
+            other => panic!("unexpected result {:?}", other),
         }
+        assert_eq!(loader.load_file_recursively(dir.child("a.txt"))?, "a");
 
-        self.resolution_stack.borrow_mut().pop();
+        assert_eq!(slow_loader(Duration::from_secs(60)).load_file_recursively(dir.child("start.txt"))?, "a");
 
-        Ok(content)
+        Ok(())
     }
 
-    fn find_includes<P: AsRef<Path>>(
-        &self,
-        source_path: P,
-        text: &str,
-    ) -> Result<Vec<Include>, Error> {
-        use lazy_regex::{regex::Match, Captures};
+    #[rstest]
+    #[case("<p>${include_escaped(\"html\", \"snippet.txt\")}</p>", "<p>&lt;b&gt;${include(&quot;a.txt&quot;)}&lt;/b&gt;</p>")]
+    #[case("{\"s\": \"${include_escaped(\"json\", \"snippet.txt\")}\"}", "{\"s\": \"<b>${include(\\\"a.txt\\\")}</b>\"}")]
+    #[case("echo ${include_escaped(\"shell\", \"snippet.txt\")}", "echo '<b>${include(\"a.txt\")}</b>'")]
+    fn should_include_escaped_content_without_resolving_it(
+        #[case] content: &str,
+        #[case] expectation: &str,
+    ) -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::write(dir.child("start.txt"), content.as_bytes())?;
+        std::fs::write(dir.child("snippet.txt"), "<b>${include(\"a.txt\")}</b>\n".as_bytes())?;
 
-        let env_regex = lazy_regex::regex!(
-            r##"(?m)(?P<indentation>^\s*)?(?P<backslashes>\\*)(?P<expr>\$\{include(?P<indent>_indent)?\("(?P<path>[^"]*)"\)})"##
-        );
+        let loader = LoaderBuilder::new().allow_all_directives().build();
+        assert_eq!(loader.load_file_recursively(dir.child("start.txt"))?, expectation);
+
+        let fragments = loader.resolve_fragments(dir.child("start.txt"))?;
+        assert_eq!(fragments.iter().map(|it| it.text()).collect::<String>(), expectation);
+        assert!(loader.build_tree(dir.child("start.txt"))?.children()[0].children().is_empty());
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_validate_balanced_delimiters_of_assembled_text() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::write(dir.child("start.xml"), "<doc>\n${include(\"open.xml\")}\n</doc>\n".as_bytes())?;
+        std::fs::write(dir.child("open.xml"), "<item>".as_bytes())?;
+
+        assert_eq!(Loader::new().load_file_recursively(dir.child("start.xml"))?, "<doc>\n<item>\n</doc>\n");
+
+        let loader = LoaderBuilder::new().validate_balanced(Delimiters::Tags).build();
+        match loader.load_file_recursively(dir.child("start.xml")) {
+            Err(Error::Unbalanced(path, offset, description)) => {
+                assert_eq!(path, dir.child("start.xml"));
+                assert_eq!(offset, 13);
+                assert_eq!(description, "expected `</item>` but found `</doc>`");
+            },
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        std::fs::write(dir.child("open.xml"), "<item/>".as_bytes())?;
+        assert_eq!(loader.load_file_recursively(dir.child("start.xml"))?, "<doc>\n<item/>\n</doc>\n");
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_include_listed_files() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        for name in ["lists", "parts"] {
+            std::fs::create_dir(dir.child(name))?;
+        }
+        std::fs::write(dir.child("start.txt"), "${include_list(\"lists/files.txt\")}|".as_bytes())?;
+        std::fs::write(dir.child("lists/files.txt"), "# parts\n../parts/b.txt\n\n  ../parts/a.txt\n".as_bytes())?;
+        std::fs::write(dir.child("parts/a.txt"), "a ${include(\"c.txt\")}\n".as_bytes())?;
+        std::fs::write(dir.child("parts/b.txt"), "b\n".as_bytes())?;
+        std::fs::write(dir.child("parts/c.txt"), "c".as_bytes())?;
+
+        let loader = LoaderBuilder::new().allow_all_directives().build();
+        assert_eq!(loader.load_file_recursively(dir.child("start.txt"))?, "b\na c|");
+
+        let tree = loader.build_tree(dir.child("start.txt"))?;
+        let children: Vec<_> = tree.children().iter().map(|it| it.path().to_owned()).collect();
+        assert_eq!(children, vec![dir.child("lists/../parts/b.txt"), dir.child("lists/../parts/a.txt")]);
+
+        let fragments = loader.resolve_fragments(dir.child("start.txt"))?;
+        assert_eq!(fragments.iter().map(|it| it.text()).collect::<String>(), "b\na c|");
+
+        std::fs::write(dir.child("lists/files.txt"), "../parts/a.txt\n../parts/missing.txt\n".as_bytes())?;
+        match loader.load_file_recursively(dir.child("start.txt")) {
+            Err(Error::ListEntry(list, line, error)) => {
+                assert_eq!(list, dir.child("lists/files.txt"));
+                assert_eq!(line, 2);
+                assert!(matches!(*error, Error::FileNotFound(path) if path == dir.child("lists/../parts/missing.txt")));
+            },
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        let lenient = LoaderBuilder::new().allow_all_directives().lenient(true).failure_placeholder("?").build();
+        assert_eq!(lenient.load_file_recursively(dir.child("start.txt"))?, "a c\n?|");
 
-        let reversed_captures: Result<Vec<Include>, Error> = env_regex
-            .captures_iter(text)
-            .collect::<Vec<Captures>>()
+        let diagnostics: Vec<_> = loader
+            .diagnose(dir.child("start.txt"))
             .into_iter()
-            .rev()
-            .map(|capture| {
-                let backslashes = capture.name("backslashes").unwrap().range();
-                let expression: Match = capture.name("expr").unwrap();
-                let preserve_indentation: Option<Match> = capture.name("indent");
-                let indentation = capture
-                    .get(1)
-                    .map(|it| String::from(it.as_str()))
-                    .unwrap_or_default();
-                let path = capture.name("path").unwrap().as_str();
-                let path = source_path.get_dependency_path(path);
-
-                let indentation = preserve_indentation.map(|_| indentation);
-
-                Ok(Include::new(
-                    expression.range(),
-                    path,
-                    backslashes,
-                    indentation,
-                ))
-            })
+            .map(|it| (it.path().to_owned(), it.position(), it.kind()))
             .collect();
+        assert_eq!(diagnostics, vec![(dir.child("lists/files.txt"), Some((2, 1)), DiagnosticKind::MissingFile)]);
 
-        reversed_captures
+        std::fs::write(dir.child("lists/files.txt"), "../parts/a.txt\n\n../start.txt\n".as_bytes())?;
+        match loader.load_file_recursively(dir.child("start.txt")) {
+            Err(Error::ListEntry(_, 3, error)) => assert!(matches!(*error, Error::CyclicDependency { .. })),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        Ok(())
     }
-}
 
-#[cfg(test)]
-mod test_loader {
-    use crate::{Error, loader::Loader};
-    use rstest::rstest;
-    use temp_dir::TempDir;
+    #[cfg(unix)]
+    #[rstest]
+    fn should_resolve_symlinks_anew_for_every_load() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::write(dir.child("start.txt"), "${include(\"link.txt\")} ${include(\"./link.txt\")} ${include(\"link.txt\")}".as_bytes())?;
+        std::fs::write(dir.child("a.txt"), "a".as_bytes())?;
+        std::fs::write(dir.child("b.txt"), "b".as_bytes())?;
+        std::os::unix::fs::symlink(dir.child("a.txt"), dir.child("link.txt"))?;
+
+        let loader = Loader::new();
+        assert_eq!(loader.load_file_recursively(dir.child("start.txt"))?, "a a a");
+
+        std::fs::remove_file(dir.child("link.txt"))?;
+        std::os::unix::fs::symlink(dir.child("b.txt"), dir.child("link.txt"))?;
+        assert_eq!(loader.load_file_recursively(dir.child("start.txt"))?, "b b b");
+
+        Ok(())
+    }
 
     #[rstest]
-    fn should_load_single_files() -> Result<(), Error> {
+    fn should_substitute_variables() -> Result<(), Error> {
         let dir = TempDir::new()?;
+        std::fs::create_dir(dir.child("en"))?;
+        std::fs::write(dir.child("start.txt"), "${include(\"${var:lang}/greeting.txt\")}|${include_escaped(\"html\", \"en/greeting.txt\")}".as_bytes())?;
+        std::fs::write(dir.child("en/greeting.txt"), "Hello ${var:name}${var:punctuation:!} ${var:footer}".as_bytes())?;
+        std::fs::write(dir.child("footer.txt"), "(${var:name})".as_bytes())?;
+
+        let variables = HashMap::from([
+            ("lang".to_owned(), "en".to_owned()),
+            ("name".to_owned(), "World".to_owned()),
+            ("footer".to_owned(), "${include(\"../footer.txt\")}".to_owned()),
+        ]);
+        let loader = LoaderBuilder::new().allow_all_directives().variables(variables.clone()).build();
+        assert_eq!(
+            loader.load_file_recursively(dir.child("start.txt"))?,
+            "Hello World! (World)|Hello ${var:name}${var:punctuation:!} ${var:footer}",
+        );
+
+        let unsubstituted = LoaderBuilder::new().allow_all_directives().build().load_file_recursively(dir.child("en/greeting.txt"))?;
+        assert_eq!(unsubstituted, "Hello ${var:name}${var:punctuation:!} ${var:footer}");
+
+        let root = "${var:name}: ${include(\"footer.txt\")}".to_owned();
+        assert_eq!(loader.load_resolved(root.clone(), dir.child("start.txt"))?, "World: (World)");
+        assert_eq!(loader.load_from_reader(root.as_bytes(), dir.path())?, "World: (World)");
 
+        std::fs::write(dir.child("footer.txt"), "\n${var:year}".as_bytes())?;
+        match loader.load_file_recursively(dir.child("start.txt")) {
+            Err(Error::UndefinedVariable(path, 2, name)) => {
+                assert_eq!(path, dir.child("en/../footer.txt"));
+                assert_eq!(name, "year");
+            },
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_load_bytes() -> Result<(), Error> {
+        let dir = TempDir::new()?;
         std::fs::write(
             dir.child("start.txt"),
-            "hello, world!".as_bytes(),
+            "head\n  ${include_indent(\"text.txt\")}\n  ${include_raw_bytes(\"blob.bin\")}${include_raw_bytes(\"data:,%FE%0A\")}|".as_bytes(),
         )?;
+        std::fs::write(dir.child("text.txt"), "a\nb".as_bytes())?;
+        std::fs::write(dir.child("blob.bin"), b"\xff\n\x00${include(\"text.txt\")}")?;
 
-        let result = Loader::new().load_file_recursively(dir.child("start.txt"))?;
-        assert_eq!(result, "hello, world!");
+        let loader = LoaderBuilder::new().allow_all_directives().build();
+        assert_eq!(
+            loader.load_bytes_recursively(dir.child("start.txt"))?,
+            b"head\n  a\n  b\n  \xff\n\x00${include(\"text.txt\")}\xfe\n|",
+        );
+
+        let tree = loader.build_tree(dir.child("start.txt"))?;
+        let children: Vec<_> = tree.children().iter().map(|it| it.path().to_owned()).collect();
+        assert_eq!(children, vec![dir.child("text.txt"), dir.child("blob.bin")]);
+
+        assert!(matches!(loader.load_file_recursively(dir.child("start.txt")), Err(Error::InvalidUtf8(_, 0))));
+
+        std::fs::write(dir.child("blob.bin"), "${include(\"text.txt\")}\n".as_bytes())?;
+        std::fs::write(dir.child("start.txt"), "${include_raw_bytes(\"blob.bin\")}|".as_bytes())?;
+        assert_eq!(loader.load_file_recursively(dir.child("start.txt"))?, "${include(\"text.txt\")}\n|");
+        assert_eq!(loader.load_bytes_recursively(dir.child("start.txt"))?, b"${include(\"text.txt\")}\n|");
+        assert_eq!(
+            loader.resolve_fragments(dir.child("start.txt"))?,
+            vec![
+                Fragment::new(dir.child("blob.bin"), "${include(\"text.txt\")}\n"),
+                Fragment::new(dir.child("start.txt"), "|"),
+            ],
+        );
+
+        std::fs::write(dir.child("start.txt"), "${include_raw_bytes(\"missing.bin\")}".as_bytes())?;
+        assert!(matches!(loader.load_bytes_recursively(dir.child("start.txt")), Err(Error::FileNotFound(_))));
 
         Ok(())
     }
 
     #[rstest]
-    fn should_load_recursively() -> Result<(), Error> {
+    fn should_load_to_path() -> Result<(), Error> {
         let dir = TempDir::new()?;
+        std::fs::create_dir(dir.child("parts"))?;
+        std::fs::write(dir.child("start.txt"), "${include(\"parts/a.txt\")} ${include_list(\"files.txt\")}".as_bytes())?;
+        std::fs::write(dir.child("files.txt"), "parts/b.txt".as_bytes())?;
+        std::fs::write(dir.child("parts/a.txt"), "a".as_bytes())?;
+        std::fs::write(dir.child("parts/b.txt"), "b".as_bytes())?;
+
+        let loader = LoaderBuilder::new().allow_all_directives().build();
+        loader.load_file_recursively_to_path(dir.child("start.txt"), dir.child("out/nested/result.txt"))?;
+        assert_eq!(std::fs::read_to_string(dir.child("out/nested/result.txt"))?, "a b");
+        loader.load_file_recursively_to_path(dir.child("start.txt"), dir.child("out/nested/result.txt"))?;
+
+        for out in ["start.txt", "parts/../parts/a.txt", "parts/b.txt", "files.txt"] {
+            let before = std::fs::read_to_string(dir.child(out))?;
+            match loader.load_file_recursively_to_path(dir.child("start.txt"), dir.child(out)) {
+                Err(Error::OverwritesInput(path)) => assert_eq!(path, dir.child(out)),
+                other => panic!("unexpected result {:?}", other),
+            }
+            assert_eq!(std::fs::read_to_string(dir.child(out))?, before);
+        }
+        let result = loader.load_file_recursively_to_path(dir.child("start.txt"), dir.child("new/../start.txt"));
+        assert!(matches!(result, Err(Error::OverwritesInput(path)) if path == dir.child("new/../start.txt")));
+        assert!(!dir.child("new").exists());
+
+        std::fs::write(dir.child("parts/a.txt"), "${include(\"missing.txt\")}".as_bytes())?;
+        let result = loader.load_file_recursively_to_path(dir.child("start.txt"), dir.child("other/result.txt"));
+        assert!(matches!(result, Err(Error::FileNotFound(_))));
+        assert!(!dir.child("other").exists());
+
+        Ok(())
+    }
 
+    #[rstest]
+    fn should_skip_files_with_guards_seen_before() -> Result<(), Error> {
+        let dir = TempDir::new()?;
         std::fs::write(
             dir.child("start.txt"),
-            r#"hello, ${include("world.txt")}!"#.as_bytes(),
+            "${include(\"a.txt\")}|${include(\"b.txt\")}|${include(\"a.txt\")}|${include(\"c.txt\")}|${include_escaped(\"json\", \"b.txt\")}".as_bytes(),
         )?;
+        std::fs::write(dir.child("a.txt"), "${guard(\"COMMON\")}\na".as_bytes())?;
+        std::fs::write(dir.child("b.txt"), "\n${guard(\"COMMON\")}\nb".as_bytes())?;
+        std::fs::write(dir.child("c.txt"), "${guard(\"OTHER\")}\n${include(\"b.txt\")}c".as_bytes())?;
+
+        let loader = LoaderBuilder::new().allow_all_directives().build();
+        let expectation = "a|||c|\\n${guard(\\\"COMMON\\\")}\\nb";
+        assert_eq!(loader.load_file_recursively(dir.child("start.txt"))?, expectation);
+        assert_eq!(loader.load_file_recursively(dir.child("start.txt"))?, expectation);
+        assert_eq!(loader.load_file_recursively(dir.child("b.txt"))?, "b");
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[case(EscapedOutput::Collapsed, "\\${include(\"x\")} ${include_indent(\"a.txt\")}", "${include(\"x\")} a\n                b")]
+    #[case(EscapedOutput::Literal, "\\${include(\"x\")} ${include_indent(\"a.txt\")}", "\\${include(\"x\")} a\n                 b")]
+    #[case(EscapedOutput::Collapsed, "\\${include(\"x\")}\\${include(\"y\")} ${include_indent(\"a.txt\")}", "${include(\"x\")}${include(\"y\")} a\n                               b")]
+    #[case(EscapedOutput::Collapsed, "\\\\\\${include(\"x\")}|${include(\"a.txt\")}|", "\\${include(\"x\")}|a\nb|")]
+    #[case(EscapedOutput::Collapsed, "\\${include(\"x\")}\n  ${include_indent(\"a.txt\")}", "${include(\"x\")}\n  a\n  b")]
+    #[case(EscapedOutput::Collapsed, "\\${include_unknown(\"x\")} ${include_indent(\"a.txt\")}", "\\${include_unknown(\"x\")} a\n                         b")]
+    fn should_keep_positions_after_escaped_directives(
+        #[case] escaped_output: EscapedOutput,
+        #[case] content: &str,
+        #[case] expectation: &str,
+    ) -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::write(dir.child("start.txt"), content.as_bytes())?;
+        std::fs::write(dir.child("a.txt"), "a\nb\n".as_bytes())?;
+
+        let loader = LoaderBuilder::new().escaped_output(escaped_output).build();
+        assert_eq!(loader.load_file_recursively(dir.child("start.txt"))?, expectation);
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_keep_concurrent_loads_apart() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::write(dir.child("guarded.txt"), "${guard(\"G\")}\ng".as_bytes())?;
+        for index in 0..4 {
+            let content = format!("{}${{include(\"guarded.txt\")}}${{include(\"guarded.txt\")}}", index);
+            std::fs::write(dir.child(format!("start{}.txt", index)), content.as_bytes())?;
+        }
+
+        let loader = LoaderBuilder::new().max_expansions(2).build();
+        std::thread::scope(|scope| {
+            let threads: Vec<_> = (0..4)
+                .map(|index| {
+                    let (loader, dir) = (&loader, &dir);
+                    scope.spawn(move || {
+                        (0..200)
+                            .map(|_| loader.load_file_recursively(dir.child(format!("start{}.txt", index))))
+                            .collect::<Result<Vec<_>, _>>()
+                    })
+                })
+                .collect();
+            for (index, thread) in threads.into_iter().enumerate() {
+                let texts = thread.join().unwrap()?;
+                assert!(texts.iter().all(|it| *it == format!("{}g", index)));
+            }
+
+            Ok(())
+        })
+    }
+
+    #[rstest]
+    fn should_diagnose_past_invalid_arguments() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::write(dir.child("a.txt"), "a".as_bytes())?;
         std::fs::write(
-            dir.child("world.txt"),
-            "world".as_bytes(),
+            dir.child("start.txt"),
+            "${include_repeat(\"a.txt\",\"x\")}\n${include(\"missing.txt\")}".as_bytes(),
         )?;
 
-        let result = Loader::new().load_file_recursively(dir.child("start.txt"))?;
-        assert_eq!(result, "hello, world!");
+        let actual: Vec<_> = Loader::new()
+            .diagnose(dir.child("start.txt"))
+            .iter()
+            .map(|it| (it.position(), it.kind()))
+            .collect();
+        assert_eq!(actual, vec![
+            (Some((1, 1)), DiagnosticKind::InvalidArguments),
+            (Some((2, 1)), DiagnosticKind::MissingFile),
+        ]);
 
         Ok(())
     }
 
     #[rstest]
-    fn should_preserve_indentation() -> Result<(), Error> {
+    fn should_read_lists_like_included_files() -> Result<(), Error> {
         let dir = TempDir::new()?;
+        std::fs::create_dir(dir.child("parts"))?;
+        std::fs::write(dir.child("parts/a.txt"), "a".as_bytes())?;
+        std::fs::write(dir.child("start.txt"), "${include_list(\"files.txt\")}|".as_bytes())?;
+
+        let loader = LoaderBuilder::new().allow_all_directives()
+            .overrides(HashMap::from([(dir.child("files.txt"), "parts/a.txt\n".to_owned())]))
+            .build();
+        assert_eq!(loader.load_file_recursively(dir.child("start.txt"))?, "a|");
 
+        std::fs::write(dir.child("files.txt"), format!("parts/a.txt\n#{}\n", "x".repeat(986)).as_bytes())?;
+        let loader = LoaderBuilder::new().allow_all_directives().max_file_bytes(100).build();
+        assert!(matches!(
+            loader.load_file_recursively(dir.child("start.txt")),
+            Err(Error::FileTooLarge(path, 100)) if path == dir.child("files.txt")
+        ));
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_only_expand_base_directives_by_default() -> Result<(), Error> {
+        let dir = TempDir::new()?;
         std::fs::write(
             dir.child("start.txt"),
-            "start\n  ${include_indent(\"1.txt\")}".as_bytes(),
+            "${include(\"a.txt\")} ${include_with(\"  \", \"a.txt\")} ${include_name(\"a.txt\")}".as_bytes(),
         )?;
+        std::fs::write(dir.child("a.txt"), "a".as_bytes())?;
+
+        assert_eq!(
+            Loader::new().load_file_recursively(dir.child("start.txt"))?,
+            "a ${include_with(\"  \", \"a.txt\")} ${include_name(\"a.txt\")}",
+        );
+        let loader = LoaderBuilder::new().allow_all_directives().build();
+        assert_eq!(
+            loader.load_file_recursively(dir.child("start.txt"))?,
+            format!("a a {}", dir.child("a.txt").display()),
+        );
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[rstest]
+    fn should_stop_literal_cycles_through_directory_symlinks() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::create_dir(dir.child("docs"))?;
+        std::os::unix::fs::symlink(dir.child("docs"), dir.child("docs/loop"))?;
+        std::fs::write(dir.child("docs/start.txt"), r#"${include("loop/start.txt")}"#.as_bytes())?;
+
+        let loader = LoaderBuilder::new().cycle_detection(CycleKey::Literal).build();
+        assert!(matches!(
+            loader.load_file_recursively(dir.child("docs/start.txt")),
+            Err(Error::MaxDepthExceeded(_, LITERAL_MAX_DEPTH))
+        ));
+
+        let loader = LoaderBuilder::new().cycle_detection(CycleKey::Literal).max_depth(3).build();
+        assert!(matches!(loader.load_file_recursively(dir.child("docs/start.txt")), Err(Error::MaxDepthExceeded(_, 3))));
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[cfg(unix)]
+    fn should_treat_data_uris_as_paths_for_other_directives() -> Result<(), Error> {
+        let dir = TempDir::new()?;
         std::fs::write(
-            dir.child("1.txt"),
-            "1\n\t${include(\"2.txt\")}".as_bytes(),
+            dir.child("start.txt"),
+            "${include_section(\"data:,x\", \"x\")}|${include_name(\"data:,x\")}|${include_list(\"data:,list\")}".as_bytes(),
         )?;
+        std::fs::write(dir.child("data:,x"), "# region x\nX\n# endregion\n".as_bytes())?;
+        std::fs::write(dir.child("data:,list"), "data:,x\n".as_bytes())?;
+
+        let loader = LoaderBuilder::new().allow_all_directives().build();
+        assert_eq!(
+            loader.load_file_recursively(dir.child("start.txt"))?,
+            format!("X|{}|# region x\nX\n# endregion", dir.child("data:,x").to_string_lossy()),
+        );
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_not_count_skipped_includes_as_expansions() -> Result<(), Error> {
+        let dir = TempDir::new()?;
         std::fs::write(
-            dir.child("2.txt"),
-            "2\n2".as_bytes(),
+            dir.child("start.txt"),
+            "${include_if(\"RFL_TEST_UNSET_CONDITION\", \"a.txt\")}${include_repeat(\"a.txt\", 0)}${include_repeat(\"a.txt\", 2)}".as_bytes(),
         )?;
+        std::fs::write(dir.child("a.txt"), "a".as_bytes())?;
 
-        let result = Loader::new().load_file_recursively(dir.child("start.txt"))?;
-        assert_eq!(result, "start\n  1\n  \t2\n  2");
+        let loader = LoaderBuilder::new().allow_all_directives().max_expansions(2).build();
+        assert_eq!(loader.load_file_recursively(dir.child("start.txt"))?, "a\na");
+        assert_eq!(loader.resolve_fragments(dir.child("start.txt"))?.iter().map(Fragment::text).collect::<String>(), "a\na");
 
         Ok(())
     }
 
     #[rstest]
-    fn should_respect_escapes() -> Result<(), Error> {
+    fn should_stop_reading_sources_past_the_size_limit() -> Result<(), Error> {
+        struct EndlessSource;
+
+        impl FileSource for EndlessSource {
+            fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+                Ok(path.to_owned())
+            }
+
+            fn open(&self, _: &Path) -> io::Result<Box<dyn io::Read + '_>> {
+                Ok(Box::new(io::repeat(b'x')))
+            }
+        }
+
+        let loader = LoaderBuilder::new().file_source(EndlessSource).max_file_bytes(100).build();
+        match loader.load_file_recursively("start.txt") {
+            Err(Error::FileTooLarge(path, 100)) => assert_eq!(path, PathBuf::from("start.txt")),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_resolve_paths_of_includes_if_newer() -> Result<(), Error> {
+        use std::time::{Duration, SystemTime};
+
+        let dir = TempDir::new()?;
+        std::fs::write(dir.child("start.txt"), "${include_if_newer(\"old\")}|${include_if_newer(\"changed.txt\")}".as_bytes())?;
+        std::fs::write(dir.child("old.txt"), "old".as_bytes())?;
+        std::fs::write(dir.child("changed.txt"), "on disk".as_bytes())?;
+        let now = SystemTime::now();
+        for (name, modified) in [("start.txt", now), ("old.txt", now - Duration::from_secs(60)), ("changed.txt", now - Duration::from_secs(60))] {
+            std::fs::File::options().write(true).open(dir.child(name))?.set_modified(modified)?;
+        }
+
+        let loader = LoaderBuilder::new()
+            .allow_all_directives()
+            .try_extensions(["txt"])
+            .overrides(HashMap::from([(dir.child("changed.txt"), "changed".to_owned())]))
+            .build();
+        assert_eq!(loader.load_file_recursively(dir.child("start.txt"))?, "|changed");
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_prepare_content_given_to_load_resolved() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::write(dir.child("start.txt"), "on disk".as_bytes())?;
+        std::fs::write(dir.child("b.txt"), "b  \n".as_bytes())?;
+
+        let loader = LoaderBuilder::new()
+            .trim_trailing_whitespace(true)
+            .transform(|path, content| Ok(content.replace("{{name}}", &path.file_name().unwrap().to_string_lossy())))
+            .build();
+        let content = "{{name}} \n${include(\"b.txt\")}\t".to_owned();
+        assert_eq!(loader.load_resolved(content, dir.child("start.txt"))?, "start.txt\nb");
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_prepare_content_read_from_reader() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        std::fs::write(dir.child("b.txt"), "b  \n".as_bytes())?;
+
+        let loader = LoaderBuilder::new()
+            .trim_trailing_whitespace(true)
+            .transform(|path, content| Ok(content.replace("{{name}}", &path.file_name().unwrap().to_string_lossy())))
+            .build();
+        let content = "{{name}} \n${include(\"b.txt\")}\t";
+        assert_eq!(loader.load_from_reader(content.as_bytes(), dir.path())?, "<reader>\nb");
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn should_forbid_duplicate_includes() -> Result<(), Error> {
         let dir = TempDir::new()?;
 
         std::fs::write(
             dir.child("start.txt"),
-            "hello, \\${include(\"world.txt\")}!".as_bytes(),
+            "${include(\"a.txt\")}\\${include(\"b.txt\")}${include(\"b.txt\")}".as_bytes(),
         )?;
+        std::fs::write(dir.child("a.txt"), "${include(\"b.txt\")}".as_bytes())?;
+        std::fs::write(dir.child("b.txt"), "b".as_bytes())?;
 
-        let result = Loader::new().load_file_recursively(dir.child("start.txt"))?;
-        assert_eq!(result, "hello, ${include(\"world.txt\")}!");
+        let loader = LoaderBuilder::new()
+            .forbid_duplicate_includes(true)
+            .build();
+        match loader.load_file_recursively(dir.child("start.txt")) {
+            Err(Error::DuplicateInclude(path, first, second)) => {
+                assert_eq!(path, dir.child("b.txt"));
+                assert_eq!(first, dir.child("a.txt"));
+                assert_eq!(second, dir.child("start.txt"));
+            },
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        std::fs::write(dir.child("a.txt"), "a".as_bytes())?;
+        assert_eq!(loader.load_file_recursively(dir.child("start.txt"))?, "a${include(\"b.txt\")}b");
+        assert_eq!(loader.load_file_recursively(dir.child("start.txt"))?, "a${include(\"b.txt\")}b");
 
         Ok(())
     }
 
     #[rstest]
-    fn should_report_file_not_found() -> Result<(), Error> {
+    fn should_report_directories() -> Result<(), Error> {
         let dir = TempDir::new()?;
-        let file = dir.child("non-existent.txt");
+        std::fs::create_dir(dir.child("somedir"))?;
 
-        let result = Loader::new().load_file_recursively(&file);
-        if let Err(e) = result {
-            let msg = e.to_string();
-            assert!(msg.contains(&format!("file not found: '{}'", file.to_string_lossy())));
-        } else {
-            panic!("expected an err");
-        }
+        std::fs::write(
+            dir.child("start.txt"),
+            r#"${include("somedir")}"#.as_bytes(),
+        )?;
+
+        let error = Loader::new().load_file_recursively(dir.child("start.txt")).unwrap_err();
+        assert!(matches!(&error, Error::IsADirectory(path) if path == &dir.child("somedir")));
+        assert_eq!(error.to_string(), format!("'{}' is a directory, not a file", dir.child("somedir").to_string_lossy()));
 
         Ok(())
     }
@@ -189,25 +4373,36 @@ mod test_loader {
         )?;
 
         std::fs::write(
-            mid,
+            &mid,
             "${include(\"../end/end.txt\")}".as_bytes(),
         )?;
 
         std::fs::write(
-            end,
+            &end,
             "${include(\"../start.txt\")}".as_bytes(),
         )?;
 
         let result = Loader::new().load_file_recursively(&start);
         if let Err(e) = result {
-            let msg = e.to_string();
-            assert!(msg.contains("cyclic dependency detected between"));
-            assert!(msg.contains("/end/end.txt' and '"));
-            assert!(msg.contains("../start.txt'"));
+            assert!(matches!(&e, Error::CyclicDependency { from, to, directive, line: 1, .. }
+                if from.ends_with("end/end.txt") && to.ends_with("end/../start.txt") && directive == r#"${include("../start.txt")}"#));
+            let [start, mid, end] = [&start, &mid, &end].map(|it| std::fs::canonicalize(it).unwrap().to_string_lossy().into_owned());
+            assert_eq!(e.to_string(), format!(
+                "cyclic dependency detected: '{end}' -> '{start}' -> '{mid}' -> '{end}', closed by `${{include(\"../start.txt\")}}` on line 1 of '{}'",
+                dir.child("mid/../end/end.txt").to_string_lossy(),
+            ));
         } else {
             panic!("expected an err");
         }
 
+        let messages: Vec<_> = [&start, &mid, &end, &dir.child("mid/../end/end.txt")]
+            .into_iter()
+            .map(|it| Loader::new().load_file_recursively(it).unwrap_err().to_string())
+            .collect();
+        let cycles: Vec<_> = messages.iter().map(|it| it.split(", closed by").next().unwrap()).collect();
+        assert!(cycles.iter().all(|it| it == &cycles[0]));
+        assert_eq!(Loader::new().load_file_recursively(&mid).unwrap_err().to_string(), messages[1]);
+
         Ok(())
     }
 }
@@ -0,0 +1,143 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::Error;
+
+/// A record of all files consumed by a load, see [crate::Loader::load_with_manifest].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    root: PathBuf,
+    files: Vec<ManifestEntry>,
+}
+
+/// A single file consumed by a load, with the SHA-256 hash of its raw content.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    path: PathBuf,
+    size: u64,
+    sha256: String,
+}
+
+impl Manifest {
+    pub(crate) fn new<P: AsRef<Path>>(root: P, files: Vec<ManifestEntry>) -> Self {
+        Self {
+            root: root.as_ref().to_owned(),
+            files,
+        }
+    }
+
+    /// The path the load was started from.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// The consumed files in the order they were first read.
+    pub fn files(&self) -> &[ManifestEntry] {
+        &self.files
+    }
+
+    pub fn to_json(&self) -> Result<String, Error> {
+        serde_json::to_string(self).map_err(|e| Error::InvalidManifest(e.to_string()))
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, Error> {
+        serde_json::from_str(json).map_err(|e| Error::InvalidManifest(e.to_string()))
+    }
+
+    /// Compare against another manifest, reporting the first file that differs.
+    pub(crate) fn compare(&self, other: &Manifest) -> Result<(), Error> {
+        let mismatch = self.files
+            .iter()
+            .zip(other.files.iter())
+            .find(|(expected, actual)| expected != actual)
+            .map(|(expected, _)| expected)
+            .or_else(|| self.files.get(other.files.len()))
+            .or_else(|| other.files.get(self.files.len()));
+
+        match mismatch {
+            Some(entry) => Err(Error::ManifestMismatch(entry.path.clone())),
+            None => Ok(()),
+        }
+    }
+}
+
+impl ManifestEntry {
+    pub(crate) fn new<P: AsRef<Path>>(path: P, content: &[u8]) -> Self {
+        let sha256 = Sha256::digest(content)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+
+        Self {
+            path: path.as_ref().to_owned(),
+            size: content.len() as u64,
+            sha256,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// The hex encoded SHA-256 hash of the file's raw content.
+    pub fn sha256(&self) -> &str {
+        &self.sha256
+    }
+}
+
+#[cfg(test)]
+mod test_manifest {
+    use super::{Manifest, ManifestEntry};
+    use crate::Error;
+    use rstest::rstest;
+
+    fn manifest(files: &[(&str, &str)]) -> Manifest {
+        let files = files
+            .iter()
+            .map(|(path, content)| ManifestEntry::new(path, content.as_bytes()))
+            .collect();
+
+        Manifest::new("start.txt", files)
+    }
+
+    #[rstest]
+    fn should_hash_content() {
+        let entry = ManifestEntry::new("a.txt", b"hello");
+
+        assert_eq!(entry.size(), 5);
+        assert_eq!(entry.sha256(), "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824");
+    }
+
+    #[rstest]
+    fn should_round_trip_json() -> Result<(), Error> {
+        let manifest = manifest(&[("start.txt", "a"), ("b.txt", "b")]);
+
+        assert_eq!(Manifest::from_json(&manifest.to_json()?)?, manifest);
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[case(&[("start.txt", "a"), ("b.txt", "b")], None)]
+    #[case(&[("start.txt", "a"), ("b.txt", "x")], Some("b.txt"))]
+    #[case(&[("start.txt", "a")], Some("b.txt"))]
+    #[case(&[("start.txt", "a"), ("b.txt", "b"), ("c.txt", "c")], Some("c.txt"))]
+    fn should_report_first_difference(
+        #[case] actual: &[(&str, &str)],
+        #[case] expectation: Option<&str>,
+    ) {
+        let expected = manifest(&[("start.txt", "a"), ("b.txt", "b")]);
+
+        match (expected.compare(&manifest(actual)), expectation) {
+            (Ok(()), None) => {},
+            (Err(Error::ManifestMismatch(path)), Some(expectation)) => assert_eq!(path.to_str(), Some(expectation)),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,238 @@
+use std::{collections::HashMap, fmt::Debug, io, path::{Path, PathBuf}, sync::Arc, time::Duration};
+
+use crate::{Delimiters, Error, FileSource};
+#[cfg(feature = "cache")]
+use crate::DiskCache;
+
+/// Controls how the backslashes in front of a directive end up in the output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EscapedOutput {
+    /// Every pair of backslashes is collapsed into a single backslash, so
+    /// `\\${include("x.txt")}` becomes `\` followed by the content of `x.txt`,
+    /// and `\${include("x.txt")}` becomes the literal directive text.
+    ///
+    /// This is the default.
+    #[default]
+    Collapsed,
+
+    /// Backslashes are written to the output verbatim. An odd number of
+    /// backslashes still escapes the directive, which is then left untouched.
+    Literal,
+}
+
+/// The base directory relative include paths are resolved against.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RelativeBase {
+    /// Resolve relative paths against the directory of the file containing the
+    /// directive. This is the default.
+    #[default]
+    SourceFile,
+
+    /// Resolve relative paths against the current working directory of the process,
+    /// regardless of which file contains the directive.
+    Cwd,
+}
+
+/// The key used to identify files, both for cycle detection and as the base for
+/// resolving relative includes inside them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CycleKey {
+    /// Identify files by their canonical path, with all symlinks resolved.
+    /// This is the default.
+    #[default]
+    Canonical,
+
+    /// Identify files by their lexically cleaned absolute path, without resolving
+    /// symlinks. Relative includes are resolved against the symlink's location
+    /// rather than its target, and `..` is applied lexically.
+    ///
+    /// Note that this can miss cycles that go through symlinks, e.g. a directory
+    /// symlink pointing to one of its ancestors. Such a cycle keeps producing new
+    /// paths, so includes are limited to [LITERAL_MAX_DEPTH] levels unless
+    /// [crate::LoaderBuilder::max_depth] is set, failing with an
+    /// [crate::Error::MaxDepthExceeded] instead.
+    Literal,
+}
+
+/// The depth includes are limited to with [CycleKey::Literal] if no other limit is
+/// set, see [crate::LoaderBuilder::max_depth].
+pub const LITERAL_MAX_DEPTH: usize = 32;
+
+/// How the indentation in front of a directive is taken over into the included
+/// content, e.g. by `include_indent`.
+///
+/// The indentation captured for a directive is everything on its line in the output
+/// in front of it, with characters other than spaces and tabs replaced by spaces, see
+/// [crate::load_file_recursively]. Indentation given explicitly, e.g. by
+/// `include_with`, is used as is.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IndentMode {
+    /// Reproduce spaces and tabs in front of the directive verbatim. This is the default.
+    #[default]
+    Literal,
+
+    /// Reproduce the visual column the directive starts at as spaces, expanding
+    /// tabs to the next multiple of the given tab width.
+    SpacesFromColumn(usize),
+}
+
+impl IndentMode {
+    /// Normalize the whitespace captured in front of a directive.
+    pub(crate) fn normalize(self, indentation: String) -> String {
+        match self {
+            IndentMode::Literal => indentation,
+            IndentMode::SpacesFromColumn(tab_width) => {
+                let line = indentation.rsplit('\n').next().unwrap_or_default();
+                let column = line.chars().fold(0, |column, it| match it {
+                    '\t' if tab_width > 0 => (column / tab_width + 1) * tab_width,
+                    '\t' => column,
+                    _ => column + 1,
+                });
+                " ".repeat(column)
+            },
+        }
+    }
+}
+
+/// The syntax of the comments marking where included files begin and end, see
+/// [crate::LoaderBuilder::annotate_boundaries].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommentStyle {
+    /// Comments running to the end of the line, starting with the given text, e.g. `//` or `#`.
+    Line(String),
+
+    /// Comments enclosed by the given start and end text, e.g. `<!--` and `-->`.
+    Block(String, String),
+}
+
+impl CommentStyle {
+    /// Turn the text into a comment of this style.
+    pub(crate) fn comment(&self, text: &str) -> String {
+        match self {
+            CommentStyle::Line(start) => format!("{} {}", start, text),
+            CommentStyle::Block(start, end) => format!("{} {} {}", start, text, end),
+        }
+    }
+}
+
+/// Per-load settings for [crate::load_file_recursively_with], a plain alternative
+/// to [crate::LoaderBuilder] for setting a few options inline.
+///
+/// ```
+/// use recursive_file_loader::LoadOptions;
+///
+/// let options = LoadOptions { max_depth: Some(3), ..Default::default() };
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LoadOptions {
+    /// See [crate::LoaderBuilder::max_depth].
+    pub max_depth: Option<usize>,
+    /// See [crate::LoaderBuilder::restrict_to].
+    pub restrict_to: Option<PathBuf>,
+    /// See [crate::LoaderBuilder::max_file_bytes].
+    pub max_file_bytes: Option<u64>,
+    /// See [crate::LoaderBuilder::max_includes_per_file].
+    pub max_includes_per_file: Option<usize>,
+    /// See [crate::LoaderBuilder::max_expansions].
+    pub max_expansions: Option<usize>,
+    /// See [crate::LoaderBuilder::relative_to].
+    pub relative_to: RelativeBase,
+    /// See [crate::LoaderBuilder::base_dir_override].
+    pub base_dir_override: Option<PathBuf>,
+    /// See [crate::LoaderBuilder::escaped_output].
+    pub escaped_output: EscapedOutput,
+    /// See [crate::LoaderBuilder::lenient].
+    pub lenient: bool,
+    /// See [crate::LoaderBuilder::strict_directives].
+    pub strict_directives: bool,
+}
+
+pub(crate) type Transform = dyn Fn(&Path, String) -> Result<String, Error> + Send + Sync;
+pub(crate) type Canonicalizer = dyn Fn(&Path) -> io::Result<PathBuf> + Send + Sync;
+pub(crate) type PathRewriter = dyn Fn(&str) -> Option<String> + Send + Sync;
+
+/// A user supplied callback, shared between clones of the options.
+pub(crate) struct Callback<F: ?Sized>(pub(crate) Arc<F>);
+
+impl<F: ?Sized> Clone for Callback<F> {
+    fn clone(&self) -> Self {
+        Self(Arc::clone(&self.0))
+    }
+}
+
+impl<F: ?Sized> Debug for Callback<F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Callback")
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Options {
+    pub(crate) escaped_output: EscapedOutput,
+    pub(crate) max_file_bytes: Option<u64>,
+    pub(crate) max_includes_per_file: Option<usize>,
+    pub(crate) max_expansions: Option<usize>,
+    pub(crate) max_depth: Option<usize>,
+    pub(crate) max_duration: Option<Duration>,
+    pub(crate) restrict_to: Option<PathBuf>,
+    pub(crate) relative_to: RelativeBase,
+    pub(crate) base_dir_override: Option<PathBuf>,
+    pub(crate) cycle_detection: CycleKey,
+    pub(crate) case_insensitive: bool,
+    pub(crate) resolve_case_insensitive: bool,
+    pub(crate) try_extensions: Vec<String>,
+    pub(crate) root_marker: Option<String>,
+    pub(crate) layered_roots: Vec<PathBuf>,
+    pub(crate) expand_tilde: bool,
+    #[cfg(feature = "glob")]
+    pub(crate) include_without_frontmatter: bool,
+    pub(crate) dedent_included: bool,
+    #[cfg(feature = "encoding")]
+    pub(crate) encoding: Option<String>,
+    pub(crate) lossy_decoding: bool,
+    pub(crate) trim_trailing_whitespace: bool,
+    pub(crate) ensure_final_newline: Option<bool>,
+    pub(crate) max_line_length: Option<usize>,
+    pub(crate) validate_balanced: Option<Delimiters>,
+    pub(crate) indent_mode: IndentMode,
+    pub(crate) annotate_boundaries: Option<CommentStyle>,
+    pub(crate) line_number_start: Option<usize>,
+    pub(crate) line_number_width: usize,
+    pub(crate) region_markers: Option<(String, String)>,
+    pub(crate) lenient: bool,
+    pub(crate) overrides: HashMap<PathBuf, String>,
+    /// The current directory when the overrides were set, which relative paths are
+    /// matched against them from.
+    pub(crate) override_dir: PathBuf,
+    pub(crate) variables: Option<HashMap<String, String>>,
+    pub(crate) failure_placeholder: Option<String>,
+    pub(crate) forbid_duplicate_includes: bool,
+    pub(crate) warn_redundant_paths: bool,
+    pub(crate) strict_directives: bool,
+    /// The names of the directives recognized, [crate::directives::DEFAULT_DIRECTIVE_NAMES]
+    /// if `None`.
+    pub(crate) allowed_directives: Option<Vec<String>>,
+    pub(crate) reject_disabled_directives: bool,
+    pub(crate) warn_unclosed_directives: bool,
+    pub(crate) allow_unquoted_paths: bool,
+    pub(crate) allow_commands: bool,
+    #[cfg(feature = "parallel")]
+    pub(crate) parallel: bool,
+    pub(crate) transform: Option<Callback<Transform>>,
+    pub(crate) canonicalizer: Option<Callback<Canonicalizer>>,
+    pub(crate) rewrite_path: Option<Callback<PathRewriter>>,
+    pub(crate) file_source: Option<Callback<dyn FileSource>>,
+    #[cfg(feature = "http")]
+    pub(crate) allow_http: bool,
+    #[cfg(feature = "http")]
+    pub(crate) http_timeout: Option<Duration>,
+    #[cfg(feature = "cache")]
+    pub(crate) disk_cache: Option<Callback<dyn DiskCache>>,
+}
+
+impl Options {
+    /// The default options with all directives enabled.
+    pub(crate) fn with_all_directives() -> Self {
+        Self { allowed_directives: Some(crate::directives::directive_names()), ..Self::default() }
+    }
+}
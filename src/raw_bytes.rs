@@ -0,0 +1,47 @@
+/// The noncharacters enclosing the index of the bytes a marker stands in for. They
+/// are reserved for internal use by Unicode, so text is very unlikely to contain them.
+const MARKER_START: char = '\u{FDD0}';
+const MARKER_END: char = '\u{FDD1}';
+
+/// The text standing in for the bytes at `index` until they're spliced in.
+pub(crate) fn marker(index: usize) -> String {
+    format!("{}{}{}", MARKER_START, index, MARKER_END)
+}
+
+/// Encode the text as UTF-8, replacing every marker with the bytes it stands in for.
+/// Marker-like text with an index out of range is kept as is.
+pub(crate) fn splice(text: &str, parts: &[Vec<u8>]) -> Vec<u8> {
+    let marker_regex = lazy_regex::regex!("\u{FDD0}(?P<index>[0-9]+)\u{FDD1}");
+
+    let mut bytes = Vec::with_capacity(text.len() + parts.iter().map(Vec::len).sum::<usize>());
+    let mut end = 0;
+    for capture in marker_regex.captures_iter(text) {
+        let Some(part) = capture["index"].parse().ok().and_then(|index: usize| parts.get(index)) else {
+            continue;
+        };
+        let whole = capture.get(0).unwrap();
+        bytes.extend_from_slice(&text.as_bytes()[end..whole.start()]);
+        bytes.extend_from_slice(part);
+        end = whole.end();
+    }
+    bytes.extend_from_slice(&text.as_bytes()[end..]);
+
+    bytes
+}
+
+#[cfg(test)]
+mod test_splice {
+    use super::{marker, splice};
+    use rstest::rstest;
+
+    #[rstest]
+    fn should_splice_bytes_in_place_of_markers() {
+        let parts = vec![vec![0xff, 0x00], vec![]];
+        let text = format!("a{}b{}{}c{}", marker(0), marker(1), marker(0), marker(2));
+
+        let mut expectation = b"a\xff\x00b\xff\x00c".to_vec();
+        expectation.extend_from_slice(marker(2).as_bytes());
+        assert_eq!(splice(&text, &parts), expectation);
+        assert_eq!(splice("plain ä", &parts), "plain ä".as_bytes());
+    }
+}
@@ -0,0 +1,83 @@
+/// The markers delimiting sections unless configured otherwise.
+pub(crate) const DEFAULT_MARKERS: (&str, &str) = ("# region", "# endregion");
+
+/// Extract the lines between the line starting the section with the given name
+/// and the line ending it, or `None` if there's no such section.
+///
+/// A section starts with a line consisting of the `start` marker followed by the
+/// name and ends with a line consisting of the `end` marker, optionally followed
+/// by anything. Sections may be nested, the marker lines of nested sections are
+/// left out.
+pub(crate) fn extract(text: &str, name: &str, start: &str, end: &str) -> Option<String> {
+    let section_name = |line| section_name(line, start);
+    let is_end = |line: &str| line.trim().starts_with(end);
+
+    let mut lines = text.split_inclusive('\n');
+    lines.find(|line| !is_end(line) && section_name(line) == Some(name))?;
+
+    let mut section = String::new();
+    let mut depth = 0;
+    for line in lines {
+        if is_end(line) {
+            if depth == 0 {
+                return Some(section);
+            }
+            depth -= 1;
+        } else if section_name(line).is_some() {
+            depth += 1;
+        } else {
+            section.push_str(line);
+        }
+    }
+
+    None
+}
+
+/// The name of the section started by the line, if it starts one.
+fn section_name<'a>(line: &'a str, start: &str) -> Option<&'a str> {
+    line.trim().strip_prefix(start).map(str::trim)
+}
+
+#[cfg(test)]
+mod test_extract {
+    use super::{extract, DEFAULT_MARKERS};
+    use indoc::indoc;
+    use rstest::rstest;
+
+    const TEXT: &str = indoc!("
+        before
+        # region intro
+        intro 1
+          # region nested
+        nested
+          # endregion
+        intro 2
+        # endregion intro
+        # region  outro
+        outro
+        # endregion
+        # region open
+        open
+    ");
+
+    #[rstest]
+    #[case("intro", Some("intro 1\nnested\nintro 2\n"))]
+    #[case("nested", Some("nested\n"))]
+    #[case("outro", Some("outro\n"))]
+    #[case("open", None)]
+    #[case("missing", None)]
+    #[case("", None)]
+    fn should_extract_sections(
+        #[case] name: &str,
+        #[case] expectation: Option<&str>,
+    ) {
+        let (start, end) = DEFAULT_MARKERS;
+        assert_eq!(extract(TEXT, name, start, end).as_deref(), expectation);
+    }
+
+    #[rstest]
+    fn should_use_custom_markers() {
+        let text = "//#region a\r\na\r\n//#endregion\r\n";
+        assert_eq!(extract(text, "a", "//#region", "//#endregion").as_deref(), Some("a\r\n"));
+    }
+}
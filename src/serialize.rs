@@ -0,0 +1,270 @@
+use std::path::{Path, PathBuf};
+
+use serde::{ser::SerializeMap, Serialize, Serializer};
+
+use crate::{ChainedError, Error};
+
+/// A field of a serialized [Error]. Paths are serialized lossily, so errors about
+/// files with non UTF-8 names can still be reported.
+enum Field<'a> {
+    Path(&'a Path),
+    Paths(&'a [PathBuf]),
+    Text(&'a str),
+    Owned(String),
+    Number(u64),
+    Code(Option<i32>),
+}
+
+impl Serialize for Field<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Field::Path(path) => serializer.serialize_str(&path.to_string_lossy()),
+            Field::Paths(paths) => serializer.collect_seq(paths.iter().map(|it| it.to_string_lossy())),
+            Field::Text(text) => serializer.serialize_str(text),
+            Field::Owned(text) => serializer.serialize_str(text),
+            Field::Number(number) => serializer.serialize_u64(*number),
+            Field::Code(code) => code.serialize(serializer),
+        }
+    }
+}
+
+impl Error {
+    /// The name of the variant, e.g. `FileNotFound`.
+    fn kind(&self) -> &'static str {
+        match self {
+            Error::FileNotFound(..) => "FileNotFound",
+            Error::FilesNotFound(..) => "FilesNotFound",
+            Error::CyclicDependency { .. } => "CyclicDependency",
+            Error::UnknownDirective(..) => "UnknownDirective",
+            Error::DirectiveNotAllowed(..) => "DirectiveNotAllowed",
+            Error::UnclosedDirective(..) => "UnclosedDirective",
+            Error::InvalidArgument(..) => "InvalidArgument",
+            Error::SectionNotFound(..) => "SectionNotFound",
+            Error::TooManyIncludes(..) => "TooManyIncludes",
+            Error::ExpansionBudgetExceeded(..) => "ExpansionBudgetExceeded",
+            Error::RepeatTooLarge(..) => "RepeatTooLarge",
+            Error::MaxDepthExceeded(..) => "MaxDepthExceeded",
+            Error::Timeout(..) => "Timeout",
+            Error::Unbalanced(..) => "Unbalanced",
+            Error::OutsideRoot(..) => "OutsideRoot",
+            Error::LineTooLong(..) => "LineTooLong",
+            Error::DuplicateInclude(..) => "DuplicateInclude",
+            Error::RootMarkerNotFound(..) => "RootMarkerNotFound",
+            Error::ListEntry(..) => "ListEntry",
+            #[cfg(feature = "glob")]
+            Error::InvalidGlob(..) => "InvalidGlob",
+            #[cfg(feature = "glob")]
+            Error::InvalidCondition(..) => "InvalidCondition",
+            Error::TildeNotExpanded(..) => "TildeNotExpanded",
+            Error::InvalidUtf8(..) => "InvalidUtf8",
+            Error::UndefinedEnvVar(..) => "UndefinedEnvVar",
+            Error::UndefinedVariable(..) => "UndefinedVariable",
+            Error::InvalidDataUri(..) => "InvalidDataUri",
+            Error::CommandNotAllowed(..) => "CommandNotAllowed",
+            Error::CommandFailed(..) => "CommandFailed",
+            Error::IsADirectory(..) => "IsADirectory",
+            Error::FileTooLarge(..) => "FileTooLarge",
+            #[cfg(feature = "http")]
+            Error::HttpStatus(..) => "HttpStatus",
+            #[cfg(feature = "http")]
+            Error::HttpTimeout(..) => "HttpTimeout",
+            #[cfg(feature = "http")]
+            Error::HttpTransport(..) => "HttpTransport",
+            #[cfg(feature = "manifest")]
+            Error::ManifestMismatch(..) => "ManifestMismatch",
+            #[cfg(feature = "manifest")]
+            Error::InvalidManifest(..) => "InvalidManifest",
+            #[cfg(feature = "encoding")]
+            Error::UnknownEncoding(..) => "UnknownEncoding",
+            Error::CaseMismatch(..) => "CaseMismatch",
+            Error::AmbiguousCase(..) => "AmbiguousCase",
+            Error::ReadFailed(..) => "ReadFailed",
+            Error::WriteFailed(..) => "WriteFailed",
+            Error::OverwritesInput(..) => "OverwritesInput",
+            Error::IOError(..) => "IOError",
+        }
+    }
+
+    /// The values held by the variant, with a name for each.
+    fn fields(&self) -> Vec<(&'static str, Field<'_>)> {
+        use Field::*;
+
+        match self {
+            Error::FileNotFound(path) => vec![("path", Path(path))],
+            Error::FilesNotFound(paths) => vec![("paths", Paths(paths))],
+            Error::CyclicDependency { from, to, directive, line, chain } => vec![
+                ("from", Path(from)),
+                ("to", Path(to)),
+                ("directive", Text(directive)),
+                ("line", Number(*line as u64)),
+                ("chain", Paths(chain)),
+            ],
+            Error::UnknownDirective(directive, path) => vec![("directive", Text(directive)), ("path", Path(path))],
+            Error::DirectiveNotAllowed(path, line, directive) => vec![
+                ("path", Path(path)),
+                ("line", Number(*line as u64)),
+                ("directive", Text(directive)),
+            ],
+            Error::UnclosedDirective(path, line) => vec![("path", Path(path)), ("line", Number(*line as u64))],
+            Error::InvalidArgument(path, line, description) => vec![
+                ("path", Path(path)),
+                ("line", Number(*line as u64)),
+                ("description", Text(description)),
+            ],
+            Error::SectionNotFound(path, section) => vec![("path", Path(path)), ("section", Text(section))],
+            Error::TooManyIncludes(path, count) => vec![("path", Path(path)), ("count", Number(*count as u64))],
+            Error::ExpansionBudgetExceeded(limit) => vec![("limit", Number(*limit as u64))],
+            Error::RepeatTooLarge(path, count) => vec![("path", Path(path)), ("count", Number(*count as u64))],
+            Error::MaxDepthExceeded(path, limit) => vec![("path", Path(path)), ("limit", Number(*limit as u64))],
+            Error::Timeout(limit) => vec![("limit_ms", Number(limit.as_millis() as u64))],
+            Error::OutsideRoot(path, root) => vec![("path", Path(path)), ("root", Path(root))],
+            Error::LineTooLong(path, line, length) => vec![
+                ("path", Path(path)),
+                ("line", Number(*line as u64)),
+                ("length", Number(*length as u64)),
+            ],
+            Error::Unbalanced(path, offset, description) => vec![
+                ("path", Path(path)),
+                ("offset", Number(*offset as u64)),
+                ("description", Text(description)),
+            ],
+            Error::DuplicateInclude(path, first, second) => vec![
+                ("path", Path(path)),
+                ("first", Path(first)),
+                ("second", Path(second)),
+            ],
+            Error::RootMarkerNotFound(path, marker) => vec![("path", Path(path)), ("marker", Text(marker))],
+            Error::ListEntry(path, line, error) => vec![
+                ("path", Path(path)),
+                ("line", Number(*line as u64)),
+                ("error", Owned(error.to_string())),
+            ],
+            #[cfg(feature = "glob")]
+            Error::InvalidGlob(pattern, reason) => vec![("pattern", Text(pattern)), ("reason", Text(reason))],
+            #[cfg(feature = "glob")]
+            Error::InvalidCondition(condition) => vec![("condition", Text(condition))],
+            Error::TildeNotExpanded(path) => vec![("path", Text(path))],
+            Error::InvalidUtf8(path, offset) => vec![("path", Path(path)), ("offset", Number(*offset as u64))],
+            Error::UndefinedEnvVar(name) => vec![("name", Text(name))],
+            Error::UndefinedVariable(path, line, name) => vec![
+                ("path", Path(path)),
+                ("line", Number(*line as u64)),
+                ("name", Text(name)),
+            ],
+            Error::InvalidDataUri(uri, reason) => vec![("uri", Text(uri)), ("reason", Text(reason))],
+            Error::CommandNotAllowed(command) => vec![("command", Text(command))],
+            Error::CommandFailed(command, status, stderr) => vec![
+                ("command", Text(command)),
+                ("code", Code(status.code())),
+                ("stderr", Text(stderr)),
+            ],
+            Error::IsADirectory(path) => vec![("path", Path(path))],
+            Error::FileTooLarge(path, limit) => vec![("path", Path(path)), ("limit", Number(*limit))],
+            #[cfg(feature = "http")]
+            Error::HttpStatus(url, status) => vec![("url", Text(url)), ("status", Number(u64::from(*status)))],
+            #[cfg(feature = "http")]
+            Error::HttpTimeout(url) => vec![("url", Text(url))],
+            #[cfg(feature = "http")]
+            Error::HttpTransport(url, reason) => vec![("url", Text(url)), ("reason", Text(reason))],
+            #[cfg(feature = "manifest")]
+            Error::ManifestMismatch(path) => vec![("path", Path(path))],
+            #[cfg(feature = "manifest")]
+            Error::InvalidManifest(reason) => vec![("reason", Text(reason))],
+            #[cfg(feature = "encoding")]
+            Error::UnknownEncoding(label) => vec![("label", Text(label))],
+            Error::CaseMismatch(path, actual) => vec![("path", Path(path)), ("actual", Path(actual))],
+            Error::AmbiguousCase(path, matches) => vec![("path", Path(path)), ("matches", Paths(matches))],
+            Error::ReadFailed(path, e) => vec![("path", Path(path)), ("reason", Owned(e.to_string()))],
+            Error::WriteFailed(path, e) => vec![("path", Path(path)), ("reason", Owned(e.to_string()))],
+            Error::OverwritesInput(path) => vec![("path", Path(path))],
+            Error::IOError(e) => vec![("reason", Owned(e.to_string()))],
+        }
+    }
+
+    fn serialize_entries<M: SerializeMap>(&self, map: &mut M) -> Result<(), M::Error> {
+        map.serialize_entry("kind", self.kind())?;
+        for (name, value) in self.fields() {
+            map.serialize_entry(name, &value)?;
+        }
+        map.serialize_entry("message", &self.to_string())
+    }
+}
+
+/// Serializes an error as a map of its `kind`, the name of the variant, the values
+/// it holds by name, and the `message` it displays as, e.g.
+/// `{"kind": "FileNotFound", "path": "x.txt", "message": "file not found: 'x.txt'"}`.
+impl Serialize for Error {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        self.serialize_entries(&mut map)?;
+        map.end()
+    }
+}
+
+/// Serializes like the [Error], with the `chain` of files added.
+impl Serialize for ChainedError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        self.error().serialize_entries(&mut map)?;
+        map.serialize_entry("chain", &Field::Paths(self.chain()))?;
+        map.end()
+    }
+}
+
+#[cfg(test)]
+mod test_serialize {
+    use crate::{ChainedError, Error};
+    use rstest::rstest;
+    use serde_json::json;
+    use std::path::PathBuf;
+
+    #[rstest]
+    #[case(
+        Error::FileNotFound(PathBuf::from("x.txt")),
+        json!({"kind": "FileNotFound", "path": "x.txt", "message": "file not found: 'x.txt'"}),
+    )]
+    #[case(
+        Error::CyclicDependency {
+            from: PathBuf::from("a.txt"),
+            to: PathBuf::from("b.txt"),
+            directive: "${include(\"b.txt\")}".to_owned(),
+            line: 3,
+            chain: [PathBuf::from("/a.txt"), PathBuf::from("/b.txt")].into(),
+        },
+        json!({
+            "kind": "CyclicDependency",
+            "from": "a.txt",
+            "to": "b.txt",
+            "directive": "${include(\"b.txt\")}",
+            "line": 3,
+            "chain": ["/a.txt", "/b.txt"],
+            "message": "cyclic dependency detected: '/a.txt' -> '/b.txt' -> '/a.txt', closed by `${include(\"b.txt\")}` on line 3 of 'a.txt'",
+        }),
+    )]
+    #[case(
+        Error::FilesNotFound(vec![PathBuf::from("a"), PathBuf::from("a.txt")]),
+        json!({"kind": "FilesNotFound", "paths": ["a", "a.txt"], "message": "file not found, tried 'a', 'a.txt'"}),
+    )]
+    #[case(
+        Error::IOError(std::io::Error::other("broken")),
+        json!({"kind": "IOError", "reason": "broken", "message": "IO error: broken"}),
+    )]
+    fn should_serialize_errors(#[case] error: Error, #[case] expectation: serde_json::Value) {
+        assert_eq!(serde_json::to_value(&error).unwrap(), expectation);
+    }
+
+    #[rstest]
+    fn should_serialize_the_chain() {
+        let error = ChainedError::new(
+            Error::FileNotFound(PathBuf::from("missing.txt")),
+            vec![PathBuf::from("start.txt"), PathBuf::from("missing.txt")],
+        );
+
+        assert_eq!(serde_json::to_value(&error).unwrap(), json!({
+            "kind": "FileNotFound",
+            "path": "missing.txt",
+            "message": "file not found: 'missing.txt'",
+            "chain": ["start.txt", "missing.txt"],
+        }));
+    }
+}
@@ -0,0 +1,174 @@
+use std::{io::{self, Read}, path::{Path, PathBuf}};
+
+/// Where a loader reads files from instead of the file system, see
+/// [crate::LoaderBuilder::file_source].
+///
+/// Paths are resolved against the including file as usual and passed to the
+/// source as they are. Options inspecting the file system directly, like
+/// [crate::LoaderBuilder::case_insensitive] and [crate::LoaderBuilder::root_marker],
+/// keep doing so.
+pub trait FileSource: Send + Sync {
+    /// Resolve a path to the one identifying the file for cycle detection, failing
+    /// with [io::ErrorKind::NotFound] if there's no such file.
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf>;
+
+    /// Open the file at a path returned by [FileSource::canonicalize] for reading.
+    /// With [crate::LoaderBuilder::max_file_bytes] set, no more than a byte past the
+    /// limit is read.
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + '_>>;
+}
+
+#[cfg(feature = "tar")]
+pub use self::tar::TarSource;
+
+#[cfg(feature = "tar")]
+mod tar {
+    use std::{collections::HashMap, io::{self, ErrorKind, Read}, path::{Component, Path, PathBuf}};
+
+    use super::FileSource;
+    use crate::canonical_path::clean;
+
+    /// A [FileSource] reading the files of a tar archive, which is loaded into
+    /// memory as a whole.
+    ///
+    /// Paths are taken relative to the root of the archive, with `.` and `..`
+    /// resolved lexically, so absolute paths refer to the root of the archive.
+    ///
+    /// ```no_run
+    /// use recursive_file_loader::{LoaderBuilder, TarSource};
+    ///
+    /// let archive = std::fs::File::open("templates.tar").unwrap();
+    /// let loader = LoaderBuilder::new()
+    ///     .file_source(TarSource::new(archive).unwrap())
+    ///     .build();
+    /// let content = loader.load_file_recursively("templates/index.txt").unwrap();
+    /// ```
+    #[derive(Debug, Clone)]
+    pub struct TarSource {
+        files: HashMap<PathBuf, Vec<u8>>,
+    }
+
+    impl TarSource {
+        /// Read all regular files from the archive.
+        pub fn new<R: Read>(archive: R) -> io::Result<Self> {
+            let mut files = HashMap::new();
+            for entry in ::tar::Archive::new(archive).entries()? {
+                let mut entry = entry?;
+                if !entry.header().entry_type().is_file() {
+                    continue;
+                }
+
+                let path = member_path(&entry.path()?);
+                let mut content = vec![];
+                entry.read_to_end(&mut content)?;
+                files.insert(path, content);
+            }
+
+            Ok(Self { files })
+        }
+    }
+
+    impl FileSource for TarSource {
+        fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+            let member = member_path(path);
+            match self.files.contains_key(&member) {
+                true => Ok(member),
+                false => Err(not_found(path)),
+            }
+        }
+
+        fn open(&self, path: &Path) -> io::Result<Box<dyn Read + '_>> {
+            match self.files.get(&member_path(path)) {
+                Some(content) => Ok(Box::new(content.as_slice())),
+                None => Err(not_found(path)),
+            }
+        }
+    }
+
+    /// The path of a file relative to the root of the archive.
+    fn member_path(path: &Path) -> PathBuf {
+        clean(path)
+            .components()
+            .filter(|it| matches!(it, Component::Normal(_)))
+            .collect()
+    }
+
+    fn not_found(path: &Path) -> io::Error {
+        io::Error::new(ErrorKind::NotFound, format!("'{}' is not in the archive", path.display()))
+    }
+
+    #[cfg(test)]
+    mod test_tar_source {
+        use super::TarSource;
+        use crate::{Error, FileSource, LoaderBuilder};
+        use rstest::rstest;
+        use std::path::Path;
+
+        fn archive(files: &[(&str, &str)]) -> Result<TarSource, Error> {
+            let mut builder = ::tar::Builder::new(vec![]);
+            for (path, content) in files {
+                let mut header = ::tar::Header::new_gnu();
+                header.set_size(content.len() as u64);
+                header.set_mode(0o644);
+                header.set_cksum();
+                builder.append_data(&mut header, path, content.as_bytes())?;
+            }
+
+            Ok(TarSource::new(builder.into_inner()?.as_slice())?)
+        }
+
+        #[rstest]
+        #[case("a.txt", Some("a.txt"))]
+        #[case("./dir/../a.txt", Some("a.txt"))]
+        #[case("/dir/b.txt", Some("dir/b.txt"))]
+        #[case("b.txt", None)]
+        #[case("dir", None)]
+        fn should_canonicalize_member_paths(
+            #[case] path: &str,
+            #[case] expectation: Option<&str>,
+        ) -> Result<(), Error> {
+            let source = archive(&[("a.txt", "a"), ("dir/b.txt", "b")])?;
+
+            assert_eq!(source.canonicalize(Path::new(path)).ok(), expectation.map(Into::into));
+
+            Ok(())
+        }
+
+        #[rstest]
+        fn should_load_includes_from_archive() -> Result<(), Error> {
+            let source = archive(&[
+                ("templates/start.txt", "${include(\"parts/a.txt\")} ${include(\"../shared.txt\")}"),
+                ("templates/parts/a.txt", "a"),
+                ("shared.txt", "shared"),
+            ])?;
+            let loader = LoaderBuilder::new().file_source(source).build();
+
+            assert_eq!(loader.load_file_recursively("templates/start.txt")?, "a shared");
+
+            Ok(())
+        }
+
+        #[rstest]
+        fn should_detect_cycles_within_archive() -> Result<(), Error> {
+            let source = archive(&[
+                ("start.txt", "${include(\"dir/a.txt\")}"),
+                ("dir/a.txt", "${include(\"../start.txt\")}"),
+            ])?;
+            let loader = LoaderBuilder::new().file_source(source).build();
+
+            match loader.load_file_recursively("start.txt") {
+                Err(Error::CyclicDependency { from, to, .. }) => {
+                    assert_eq!(from, Path::new("dir/a.txt"));
+                    assert_eq!(to, Path::new("dir/../start.txt"));
+                },
+                other => panic!("unexpected result {:?}", other),
+            }
+            match loader.load_file_recursively("missing.txt") {
+                Err(Error::FileNotFound(path)) => assert_eq!(path, Path::new("missing.txt")),
+                other => panic!("unexpected result {:?}", other),
+            }
+
+            Ok(())
+        }
+    }
+}
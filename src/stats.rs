@@ -0,0 +1,46 @@
+use std::path::{Path, PathBuf};
+
+/// Metrics collected during a load, see [crate::Loader::load_with_stats].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LoadStats {
+    bytes_read: u64,
+    files_read: usize,
+    max_depth: usize,
+    canonical_root: Option<PathBuf>,
+}
+
+impl LoadStats {
+    pub(crate) fn record_read(&mut self, bytes: usize, depth: usize) {
+        self.bytes_read += bytes as u64;
+        self.files_read += 1;
+        self.max_depth = self.max_depth.max(depth);
+    }
+
+    pub(crate) fn record_root(&mut self, canonical_root: &Path) {
+        self.canonical_root = Some(canonical_root.to_owned());
+    }
+
+    /// The total number of bytes read from disk. Files included several times
+    /// are counted each time.
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    /// The number of files read, counting files included several times each time.
+    pub fn files_read(&self) -> usize {
+        self.files_read
+    }
+
+    /// The deepest level of nested includes, where the loaded file itself is at
+    /// depth 0 and the files it includes directly are at depth 1.
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    /// The path the loader identified the loaded file by, e.g. with symlinks
+    /// resolved, see [crate::LoaderBuilder::cycle_detection]. `None` if the load
+    /// failed before reading it.
+    pub fn canonical_root(&self) -> Option<&Path> {
+        self.canonical_root.as_deref()
+    }
+}
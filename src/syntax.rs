@@ -0,0 +1,138 @@
+use std::{fmt::Display, ops::Range, path::PathBuf};
+
+use crate::{
+    directives,
+    includes::{Include, IncludeKind},
+    options::Options,
+};
+
+/// A malformed directive found by [crate::check_syntax], with the byte range of
+/// the offending text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyntaxIssue {
+    /// A directive with an empty path or variable name.
+    EmptyPath(Range<usize>),
+
+    /// A directive-like expression with a name that isn't a known directive.
+    UnknownDirective(Range<usize>),
+
+    /// A known directive with arguments it doesn't accept.
+    InvalidArguments(Range<usize>),
+
+    /// The opening of a directive that isn't closed on its line, ranging up to
+    /// the end of the line.
+    Unclosed(Range<usize>),
+}
+
+impl SyntaxIssue {
+    pub fn range(&self) -> &Range<usize> {
+        match self {
+            SyntaxIssue::EmptyPath(range)
+            | SyntaxIssue::UnknownDirective(range)
+            | SyntaxIssue::InvalidArguments(range)
+            | SyntaxIssue::Unclosed(range) => range,
+        }
+    }
+
+    pub(crate) fn description(&self) -> &'static str {
+        match self {
+            SyntaxIssue::EmptyPath(_) => "empty path",
+            SyntaxIssue::UnknownDirective(_) => "unknown directive",
+            SyntaxIssue::InvalidArguments(_) => "invalid arguments",
+            SyntaxIssue::Unclosed(_) => "unclosed directive",
+        }
+    }
+}
+
+impl Display for SyntaxIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at bytes {}..{}", self.description(), self.range().start, self.range().end)
+    }
+}
+
+/// Find the malformed directives in `text`, ordered by their position.
+pub(crate) fn check(text: &str) -> Vec<SyntaxIssue> {
+    let find_includes = |text| directives::find_includes(text, None, &Options::with_all_directives(), None, |path| Ok(PathBuf::from(path)));
+    let has_empty_path = |include: &Include| {
+        !include.is_escaped()
+            && include.path().as_os_str().is_empty()
+            && matches!(include.kind(), IncludeKind::File | IncludeKind::Env | IncludeKind::Command)
+    };
+    // Directives with invalid named arguments fail the whole text, they're found
+    // one by one below.
+    let includes = find_includes(text).unwrap_or_default();
+
+    let empty = includes
+        .iter()
+        .filter(|it| has_empty_path(it))
+        .map(|it| SyntaxIssue::EmptyPath(it.range().clone()));
+
+    let unclosed = directives::unclosed_directives(text, &includes)
+        .map(|opening| {
+            let end = text[opening.start()..]
+                .find('\n')
+                .map_or(text.len(), |it| opening.start() + it);
+            SyntaxIssue::Unclosed(opening.start()..end)
+        })
+        .collect::<Vec<_>>();
+
+    // An unclosed directive can run into the next one, which is reported as part of it.
+    let unknown = directives::unknown_directives(text, &includes)
+        .filter(|expr| !unclosed.iter().any(|it| it.range().start == expr.start()))
+        .filter_map(|expr| {
+            match find_includes(expr.as_str()) {
+                Err(_) => return Some(SyntaxIssue::InvalidArguments(expr.range())),
+                Ok(includes) if includes.iter().any(has_empty_path) => return Some(SyntaxIssue::EmptyPath(expr.range())),
+                Ok(includes) if !includes.is_empty() => return None,
+                Ok(_) => {},
+            }
+            let name = expr.as_str()[2..]
+                .trim_start()
+                .split(|it: char| !(it.is_alphanumeric() || it == '_'))
+                .next()
+                .unwrap_or_default();
+            match (directives::is_directive_name(name), expr.as_str().contains('(')) {
+                (true, _) => Some(SyntaxIssue::InvalidArguments(expr.range())),
+                (false, true) => Some(SyntaxIssue::UnknownDirective(expr.range())),
+                (false, false) if name.starts_with("include") => Some(SyntaxIssue::UnknownDirective(expr.range())),
+                // `${name}` without parentheses is likely meant for a different template engine.
+                (false, false) => None,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let mut issues: Vec<SyntaxIssue> = empty.chain(unknown).chain(unclosed).collect();
+    issues.sort_by_key(|it| it.range().start);
+
+    issues
+}
+
+#[cfg(test)]
+mod test_check {
+    use super::{check, SyntaxIssue};
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(r#"${include("a.txt")} ${include_name("")} ${template}"#, vec![])]
+    #[case(r#"a ${include("")}"#, vec![SyntaxIssue::EmptyPath(2..16)])]
+    #[case(r#"${include_env("")}"#, vec![SyntaxIssue::EmptyPath(0..18)])]
+    #[case(r#"${inclued("a.txt")}"#, vec![SyntaxIssue::UnknownDirective(0..19)])]
+    #[case(r#"${include_all}"#, vec![SyntaxIssue::UnknownDirective(0..14)])]
+    #[case(r#"${include("a.txt", "b.txt")}"#, vec![SyntaxIssue::InvalidArguments(0..28)])]
+    #[case("x ${include(\"a.txt\"\ny", vec![SyntaxIssue::Unclosed(2..19)])]
+    #[case(
+        r#"${include("a.txt", size="2")} ${include("")} ${include("b.txt", indent=" ")}"#,
+        vec![SyntaxIssue::InvalidArguments(0..29), SyntaxIssue::EmptyPath(30..44)],
+    )]
+    #[case(r#"\${include("")} \${unknown()}"#, vec![])]
+    #[case(
+        "${include(\"a.txt\" ${foo()} ${include(\"\")}",
+        vec![SyntaxIssue::Unclosed(0..41), SyntaxIssue::EmptyPath(27..41)],
+    )]
+    fn should_report_malformed_directives(
+        #[case] input: &str,
+        #[case] expectation: Vec<SyntaxIssue>,
+    ) {
+        assert_eq!(check(input), expectation);
+    }
+}
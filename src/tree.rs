@@ -0,0 +1,125 @@
+use std::path::{Component, Path, PathBuf};
+
+use crate::canonical_path::clean;
+
+/// The hierarchy of files included by a file, see [crate::build_tree].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IncludeTree {
+    path: PathBuf,
+    children: Vec<IncludeTree>,
+    closes_cycle: bool,
+}
+
+impl IncludeTree {
+    pub(crate) fn new<P: AsRef<Path>>(path: P, children: Vec<IncludeTree>) -> Self {
+        Self {
+            path: path.as_ref().to_owned(),
+            children,
+            closes_cycle: false,
+        }
+    }
+
+    /// A file that is already being loaded by one of its ancestors, which is
+    /// recorded instead of failing in lenient mode.
+    pub(crate) fn cycle<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            closes_cycle: true,
+            ..Self::new(path, vec![])
+        }
+    }
+
+    /// The path of the file, as it was referenced.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The trees of the files included by this file, in the order of their includes.
+    pub fn children(&self) -> &[IncludeTree] {
+        &self.children
+    }
+
+    /// Whether including this file closes a cycle, in which case it has no children.
+    /// Only happens in lenient mode, see [crate::LoaderBuilder::lenient].
+    pub fn closes_cycle(&self) -> bool {
+        self.closes_cycle
+    }
+
+    /// Render the tree like the `tree` command does, with paths relative to the
+    /// directory of the root file.
+    ///
+    /// ```text
+    /// start.txt
+    /// ├── a.txt
+    /// │   └── parts/b.txt
+    /// └── ../shared.txt (cycle)
+    /// ```
+    pub(crate) fn render(&self) -> String {
+        let base = self.path.parent().unwrap_or(Path::new(""));
+        let mut lines = vec![];
+        self.render_into(base, "", "", &mut lines);
+
+        lines.join("\n")
+    }
+
+    fn render_into(&self, base: &Path, first_prefix: &str, prefix: &str, lines: &mut Vec<String>) {
+        let marker = match self.closes_cycle {
+            true => " (cycle)",
+            false => "",
+        };
+        lines.push(format!("{}{}{}", first_prefix, relative_path(&self.path, base).to_string_lossy(), marker));
+
+        for (index, child) in self.children.iter().enumerate() {
+            let (first, rest) = match index + 1 == self.children.len() {
+                true => ("└── ", "    "),
+                false => ("├── ", "│   "),
+            };
+            child.render_into(base, &format!("{}{}", prefix, first), &format!("{}{}", prefix, rest), lines);
+        }
+    }
+}
+
+/// The path relative to `base`, going up with `..` where necessary. Both paths
+/// are cleaned lexically first.
+pub(crate) fn relative_path(path: &Path, base: &Path) -> PathBuf {
+    let path = clean(path);
+    let base = clean(base);
+    let common = path
+        .components()
+        .zip(base.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    base.components()
+        .skip(common)
+        .map(|_| Component::ParentDir.as_os_str())
+        .chain(path.components().skip(common).map(|it| it.as_os_str()))
+        .collect()
+}
+
+#[cfg(test)]
+mod test_render {
+    use super::IncludeTree;
+    use rstest::rstest;
+
+    #[rstest]
+    fn should_render_trees_relative_to_root() {
+        let tree = IncludeTree::new("/dir/start.txt", vec![
+            IncludeTree::new("/dir/a.txt", vec![
+                IncludeTree::new("/dir/parts/../parts/b.txt", vec![]),
+                IncludeTree::new("/dir/c.txt", vec![]),
+            ]),
+            IncludeTree::new("/other/d.txt", vec![
+                IncludeTree::cycle("/dir/start.txt"),
+            ]),
+        ]);
+
+        assert_eq!(tree.render(), [
+            "start.txt",
+            "├── a.txt",
+            "│   ├── parts/b.txt",
+            "│   └── c.txt",
+            "└── ../other/d.txt",
+            "    └── start.txt (cycle)",
+        ].join("\n"));
+    }
+}
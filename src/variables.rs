@@ -0,0 +1,62 @@
+use std::{borrow::Cow, collections::HashMap};
+
+/// Replace the `${var:NAME}` and `${var:NAME:default}` placeholders in `text` with
+/// the value of the variable, or the default if it isn't defined. A placeholder
+/// preceded by a backslash is kept, without the backslash. Fails with the byte
+/// offset and the name of the first undefined variable without a default.
+pub(crate) fn substitute<'a>(text: &'a str, variables: &HashMap<String, String>) -> Result<Cow<'a, str>, (usize, String)> {
+    if !text.contains("${var:") {
+        return Ok(Cow::Borrowed(text));
+    }
+
+    let placeholder_regex = lazy_regex::regex!(r"(?P<backslash>\\)?\$\{var:(?P<name>[A-Za-z_][\w.-]*)(?::(?P<default>[^}\n]*))?\}");
+
+    let mut substituted = String::with_capacity(text.len());
+    let mut end = 0;
+    for capture in placeholder_regex.captures_iter(text) {
+        let whole = capture.get(0).unwrap();
+        substituted.push_str(&text[end..whole.start()]);
+        end = whole.end();
+
+        if capture.name("backslash").is_some() {
+            substituted.push_str(&whole.as_str()[1..]);
+            continue;
+        }
+        let name = capture.name("name").unwrap().as_str();
+        match (variables.get(name), capture.name("default")) {
+            (Some(value), _) => substituted.push_str(value),
+            (None, Some(default)) => substituted.push_str(default.as_str()),
+            (None, None) => return Err((whole.start(), name.to_owned())),
+        }
+    }
+    substituted.push_str(&text[end..]);
+
+    Ok(Cow::Owned(substituted))
+}
+
+#[cfg(test)]
+mod test_substitute {
+    use super::substitute;
+    use rstest::rstest;
+    use std::collections::HashMap;
+
+    #[rstest]
+    #[case("no placeholders ${include(\"a.txt\")}", Ok("no placeholders ${include(\"a.txt\")}"))]
+    #[case("Hello ${var:name}, ${var:name}!", Ok("Hello World, World!"))]
+    #[case("${var:lang}/${var:region:us}", Ok("en/us"))]
+    #[case("${var:name:}${var:empty:x}|", Ok("World|"))]
+    #[case("\\${var:name} ${var}", Ok("${var:name} ${var}"))]
+    #[case("a\n${var:missing}", Err((2, "missing")))]
+    fn should_substitute_variables(#[case] text: &str, #[case] expectation: Result<&str, (usize, &str)>) {
+        let variables = HashMap::from([
+            ("name".to_owned(), "World".to_owned()),
+            ("lang".to_owned(), "en".to_owned()),
+            ("empty".to_owned(), "".to_owned()),
+        ]);
+
+        assert_eq!(
+            substitute(text, &variables).map(|it| it.into_owned()),
+            expectation.map(str::to_owned).map_err(|(offset, name)| (offset, name.to_owned())),
+        );
+    }
+}
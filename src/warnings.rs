@@ -0,0 +1,48 @@
+use std::path::{Path, PathBuf};
+
+/// Non-fatal findings of a load, see [crate::Loader::load_with_warnings].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LoadWarnings {
+    redundant_paths: Vec<RedundantPath>,
+}
+
+impl LoadWarnings {
+    pub(crate) fn new(redundant_paths: Vec<RedundantPath>) -> Self {
+        Self { redundant_paths }
+    }
+
+    /// The files referenced through more than one path, ordered by their canonical
+    /// path. Only collected with [crate::LoaderBuilder::warn_redundant_paths].
+    pub fn redundant_paths(&self) -> &[RedundantPath] {
+        &self.redundant_paths
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.redundant_paths.is_empty()
+    }
+}
+
+/// A file included through several differently spelled paths, e.g. `./a.txt` and
+/// `../dir/a.txt`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RedundantPath {
+    canonical: PathBuf,
+    sources: Vec<PathBuf>,
+}
+
+impl RedundantPath {
+    pub(crate) fn new(canonical: PathBuf, sources: Vec<PathBuf>) -> Self {
+        Self { canonical, sources }
+    }
+
+    /// The path the loader identified the file by, see
+    /// [crate::LoaderBuilder::cycle_detection].
+    pub fn canonical(&self) -> &Path {
+        &self.canonical
+    }
+
+    /// The distinct paths the file was referenced through, in lexical order.
+    pub fn sources(&self) -> &[PathBuf] {
+        &self.sources
+    }
+}